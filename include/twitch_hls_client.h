@@ -0,0 +1,50 @@
+/*
+ * C ABI of twitch-hls-client, built with:
+ * cargo rustc --lib --release --features ffi --crate-type cdylib
+ *
+ * One session runs at a time. Sessions take the same arguments as the command line,
+ * without the program name, and the config file is read unless --no-config is passed.
+ */
+
+#ifndef TWITCH_HLS_CLIENT_H
+#define TWITCH_HLS_CLIENT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Called on a worker thread with each chunk of the stream, returning non-zero stops it */
+typedef int (*thc_output_cb)(const uint8_t *data, size_t len, void *user_data);
+
+/* Counts are for the whole process */
+typedef struct {
+    int running;
+    uint64_t segments;
+    uint64_t bytes;
+    uint64_t queued;
+    uint64_t skipped;
+    uint64_t dropped;
+} thc_status;
+
+/* Returns 0, or -1 if an argument isn't valid UTF-8 or a session is already running */
+int thc_session_start(int argc, const char *const *argv);
+
+/* Stops the stream gracefully and waits for it, returns 0 or 1 like an exit code,
+ * or -1 if no session was started */
+int thc_session_stop(void);
+
+/* Used by sessions started after this, NULL removes it. user_data must be usable from
+ * any thread. */
+void thc_set_output_callback(thc_output_cb callback, void *user_data);
+
+/* Returns -1 if status is NULL */
+int thc_session_status(thc_status *status);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
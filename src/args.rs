@@ -1,5 +1,14 @@
+#[cfg(feature = "ffi")]
+use std::ffi::OsString;
 use std::{
-    borrow::Cow, env, error::Error, fmt::Display, fs, path::Path, process, str::FromStr,
+    borrow::Cow,
+    env,
+    error::Error,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
     time::Duration,
 };
 
@@ -7,7 +16,7 @@ use anyhow::{Context, Result, bail};
 use pico_args::Arguments;
 
 use crate::{
-    Args as MainArgs, constants, hls::Args as HlsArgs, http::Args as HttpArgs,
+    cli::Args as MainArgs, constants, hls::Args as HlsArgs, http::Args as HttpArgs,
     output::Args as OutputArgs,
 };
 
@@ -16,12 +25,22 @@ pub trait Parse {
 }
 
 pub fn parse() -> Result<(MainArgs, HttpArgs, HlsArgs, OutputArgs)> {
+    parse_from(Arguments::from_env())
+}
+
+//Command line passed in by an embedding application, without the program name
+#[cfg(feature = "ffi")]
+pub fn parse_vec(args: Vec<OsString>) -> Result<(MainArgs, HttpArgs, HlsArgs, OutputArgs)> {
+    parse_from(Arguments::from_vec(args))
+}
+
+fn parse_from(args: Arguments) -> Result<(MainArgs, HttpArgs, HlsArgs, OutputArgs)> {
     let mut main = MainArgs::default();
     let mut http = HttpArgs::default();
     let mut output = OutputArgs::default();
     let mut hls = HlsArgs::default();
 
-    let mut parser = Parser::new()?;
+    let mut parser = Parser::new(args)?;
 
     main.parse(&mut parser)?;
     http.parse(&mut parser)?;
@@ -35,9 +54,11 @@ pub fn parse() -> Result<(MainArgs, HttpArgs, HlsArgs, OutputArgs)> {
     Ok((main, http, hls, output))
 }
 
+#[allow(clippy::struct_field_names)]
 pub struct Parser {
     parser: Arguments,
     config: Option<String>,
+    config_dir: Option<PathBuf>,
 }
 
 impl Parser {
@@ -56,16 +77,20 @@ impl Parser {
         self.parse_fn(dst, key, Self::opt_from_str)
     }
 
-    pub fn parse_opt_cfg<T: FromStr>(
+    //Short or long key, the config key is the long one
+    pub fn parse_opt_or<T: FromStr>(
         &mut self,
         dst: &mut Option<T>,
-        key: &'static str,
-        cfg_key: &'static str,
+        key1: &'static str,
+        key2: &'static str,
     ) -> Result<()>
     where
         <T as FromStr>::Err: Display + Send + Sync + Error + 'static,
     {
-        self.parse_fn_cfg(dst, key, cfg_key, Self::opt_from_str)
+        let arg = self
+            .parser
+            .opt_value_from_fn([key1, key2], Self::opt_from_str)?;
+        self.resolve(dst, arg, key2, Self::opt_from_str)
     }
 
     pub fn parse_free(&mut self, dst: &mut Option<String>, cfg_key: &'static str) -> Result<()> {
@@ -115,15 +140,6 @@ impl Parser {
 
     /* These types should eventually just be wrapped with a FromStr impl */
 
-    pub fn parse_cow_string(
-        &mut self,
-        dst: &mut Cow<'static, str>,
-        key: &'static str,
-    ) -> Result<()> {
-        let arg = self.parser.opt_value_from_fn(key, Self::cow_string_impl)?;
-        self.resolve(dst, arg, key, Self::cow_string_impl)
-    }
-
     pub fn parse_cow_string_cfg(
         &mut self,
         dst: &mut Cow<'static, str>,
@@ -158,6 +174,52 @@ impl Parser {
         self.parse_fn_cfg(dst, key, cfg_key, Self::comma_list_impl)
     }
 
+    //Repeatable short or long key, each value can also be a comma separated list
+    pub fn parse_comma_list_or<T: for<'a> From<&'a str>>(
+        &mut self,
+        dst: &mut Option<Vec<T>>,
+        key1: &'static str,
+        key2: &'static str,
+    ) -> Result<()> {
+        let arg = self
+            .parser
+            .values_from_fn([key1, key2], Self::comma_list_impl)?
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        self.resolve(
+            dst,
+            (!arg.is_empty()).then_some(Some(arg)),
+            key2,
+            Self::comma_list_impl,
+        )
+    }
+
+    //Repeatable key, every matching config line is used if it's not on the command line
+    pub fn parse_repeated<T>(
+        &mut self,
+        dst: &mut Vec<T>,
+        key: &'static str,
+        f: fn(_: &str) -> Result<T>,
+    ) -> Result<()> {
+        let args = self.parser.values_from_fn(key, f)?;
+        if !args.is_empty() {
+            *dst = args;
+        } else if let Some(cfg) = &self.config {
+            let key = key.trim_start_matches('-');
+            *dst = cfg
+                .lines()
+                .filter_map(|l| l.split_once('='))
+                .filter(|(k, _)| *k == key)
+                .map(|(_, v)| f(v))
+                .collect::<Result<_>>()?;
+        }
+
+        Ok(())
+    }
+
     fn resolve<T, E>(
         &self,
         dst: &mut T,
@@ -233,13 +295,9 @@ impl Parser {
         Ok(constants::DEFAULT_CONFIG_PATH)
     }
 
-    fn new() -> Result<Self> {
-        let mut parser = Arguments::from_env();
+    fn new(mut parser: Arguments) -> Result<Self> {
         if parser.contains("-h") || parser.contains("--help") {
-            print!(
-                include_str!("usage"),
-                default_user_agent = constants::USER_AGENT,
-            );
+            print!(include_str!("usage"));
 
             process::exit(0);
         }
@@ -249,14 +307,16 @@ impl Parser {
             process::exit(0);
         }
 
+        let config_dir: Option<PathBuf> = parser.opt_value_from_str("--config-dir")?;
         Ok(Self {
             config: {
                 if parser.contains("--no-config") {
                     None
                 } else {
-                    let path = match parser.opt_value_from_str("-c")? {
-                        Some(path) => path,
-                        None => Self::default_config_path()?,
+                    let path = match (parser.opt_value_from_str("-c")?, &config_dir) {
+                        (Some(path), _) => path,
+                        (None, Some(dir)) => dir.join("config").to_string_lossy().into_owned(),
+                        (None, None) => Self::default_config_path()?,
                     };
 
                     if Path::new(&path).try_exists()? {
@@ -267,9 +327,22 @@ impl Parser {
                 }
             },
             parser,
+            config_dir,
         })
     }
 
+    //Holds the config file and other persistent files like locks
+    pub fn config_dir(&self) -> Result<PathBuf> {
+        if let Some(dir) = &self.config_dir {
+            return Ok(dir.clone());
+        }
+
+        Ok(Path::new(&Self::default_config_path()?)
+            .parent()
+            .context("Invalid config directory")?
+            .to_path_buf())
+    }
+
     fn finish(self) -> Option<String> {
         self.parser.finish().into_iter().next()?.into_string().ok()
     }
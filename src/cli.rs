@@ -0,0 +1,739 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    io::{self, Write},
+    net::{SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+    process::ExitCode,
+    thread::{self, Builder as ThreadBuilder, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, ensure};
+use log::{debug, error, info};
+
+use crate::{
+    args::{self, Parse, Parser},
+    control,
+    hls::{
+        Args as HlsArgs, Handler, Metadata, OfflineError, Playlist, RefreshError, ResetError,
+        Stream,
+    },
+    http::{self, Agent, Connection, Method, Url},
+    lock::{ChannelLock, Mode as LockMode},
+    logger::{self, Logger, Style as LogStyle},
+    output::{
+        Args as OutputArgs, FileSizeLimitError, Output, Player, PlayerClosedError, SERVE,
+        StdoutClosedError, Writer,
+    },
+    replay::REPLAY,
+    shutdown,
+    sidecar::Sidecar,
+    stats::{Format as StatsFormat, STATS, StatusLine},
+};
+
+#[cfg(unix)]
+use crate::daemon;
+
+#[derive(Default, Debug)]
+pub struct Args {
+    pub debug: bool,
+    pub log_style: LogStyle,
+    status_line: bool,
+    no_terminal_title: bool,
+    stats_file: Option<String>,
+    stats_format: StatsFormat,
+    control: Option<SocketAddr>,
+    replay_buffer: Option<Duration>,
+    replay_dir: Option<PathBuf>,
+    duration: Option<Duration>,
+    channel_lock: LockMode,
+    config_dir: Option<PathBuf>,
+    #[cfg(unix)]
+    daemon: bool,
+    #[cfg(unix)]
+    pidfile: Option<String>,
+    #[cfg(unix)]
+    log_file: Option<String>,
+}
+
+//Lets wrapper scripts tell a stream that ended apart from a closed player
+const STREAM_ENDED_EXIT_CODE: u8 = 10;
+
+#[derive(Debug)]
+struct ShutdownError;
+
+impl std::error::Error for ShutdownError {}
+
+impl Display for ShutdownError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("Interrupted")
+    }
+}
+
+#[derive(Debug)]
+struct DurationReachedError;
+
+impl std::error::Error for DurationReachedError {}
+
+impl Display for DurationReachedError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("Duration reached")
+    }
+}
+
+impl Parse for Args {
+    fn parse(&mut self, parser: &mut Parser) -> Result<()> {
+        parser.parse_switch_or(&mut self.debug, "-d", "--debug")?;
+        parser.parse_fn(&mut self.log_style, "--log-style", LogStyle::new)?;
+        parser.parse_switch(&mut self.status_line, "--status-line")?;
+        parser.parse_switch(&mut self.no_terminal_title, "--no-terminal-title")?;
+        parser.parse_opt(&mut self.stats_file, "--stats-file")?;
+        parser.parse_fn(&mut self.stats_format, "--stats-format", StatsFormat::new)?;
+        parser.parse_fn(&mut self.control, "--control", |arg| {
+            Ok(Some(
+                arg.to_socket_addrs()?
+                    .next()
+                    .context("Invalid socket address")?,
+            ))
+        })?;
+        parser.parse_fn(&mut self.replay_buffer, "--replay-buffer", parse_time)?;
+        parser.parse_fn(&mut self.replay_dir, "--replay-dir", |arg| {
+            Ok(Some(PathBuf::from(arg)))
+        })?;
+        parser.parse_fn(&mut self.duration, "--duration", parse_time)?;
+        parser.parse_fn(&mut self.channel_lock, "--channel-lock", LockMode::new)?;
+        self.config_dir = parser.config_dir().ok();
+        #[cfg(unix)]
+        {
+            parser.parse_switch(&mut self.daemon, "--daemon")?;
+            parser.parse_opt(&mut self.pidfile, "--pidfile")?;
+            parser.parse_opt(&mut self.log_file, "--log-file")?;
+        }
+
+        ensure!(
+            self.replay_buffer.is_none() || self.control.is_some(),
+            "--replay-buffer requires --control"
+        );
+        ensure!(
+            self.replay_buffer.is_none() || self.replay_dir.is_some(),
+            "--replay-buffer requires --replay-dir"
+        );
+
+        Ok(())
+    }
+}
+
+//Playlists followed by main_loop, recordings run on their own threads
+#[derive(Copy, Clone)]
+enum Follow {
+    Main,
+    Audio,
+    Recording, //--record-quality
+}
+
+impl Follow {
+    fn handler(self, writer: Writer, agent: &Agent, hls_args: &HlsArgs) -> Result<Handler> {
+        match self {
+            Self::Main => Handler::new(writer, agent, hls_args),
+            Self::Audio => Handler::audio(writer, agent),
+            Self::Recording => Handler::recording(writer, agent),
+        }
+    }
+
+    //Resolved again when segments keep returning not found
+    fn playlist(self, hls_args: &HlsArgs, agent: &Agent) -> Result<Playlist> {
+        match self {
+            Self::Main => {
+                Playlist::new(Stream::refresh(hls_args, agent)?, hls_args.dump_playlists())
+            }
+            Self::Audio => Playlist::new(Stream::audio(hls_args, agent)?, None),
+            Self::Recording => Playlist::new(Stream::recording(hls_args, agent)?, None),
+        }
+    }
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Main => "main",
+            Self::Audio => "audio",
+            Self::Recording => "recording",
+        }
+    }
+}
+
+fn main_loop(
+    follow: Follow,
+    mut writer: Writer,
+    mut playlist: Playlist,
+    mut status: Option<StatusLine>,
+    deadline: Option<Instant>,
+    agent: &Agent,
+    hls_args: &HlsArgs,
+) -> Result<()> {
+    loop {
+        if let Some(url) = playlist.header() {
+            let mut request = agent.binary(Vec::new());
+            request.call(Method::Get, url)?;
+
+            let header = request.into_writer();
+            if matches!(follow, Follow::Main) {
+                REPLAY.set_header(&header);
+                SERVE.set_header(&header);
+            }
+            writer.set_header(&header)?;
+        }
+
+        if writer.should_wait() {
+            writer.wait_for_output()?;
+        }
+
+        let mut handler = follow.handler(writer, agent, hls_args)?;
+        let error = stream_loop(
+            follow,
+            &mut handler,
+            &mut playlist,
+            &mut status,
+            deadline,
+            agent,
+            hls_args,
+        );
+
+        if error.is::<DurationReachedError>() || error.is::<ShutdownError>() {
+            //Let queued segments finish so the outputs end cleanly
+            handler.into_writer()?.finish()?;
+            return Err(error);
+        }
+
+        if !(matches!(follow, Follow::Main) && hls_args.reconnect() && error.is::<OfflineError>()) {
+            return Err(error);
+        }
+
+        //Keep the outputs (and player process) for when the stream comes back
+        writer = handler.into_writer()?;
+        writer.finish()?;
+        playlist = Playlist::new(wait_for_stream(hls_args, agent)?, hls_args.dump_playlists())?;
+    }
+}
+
+fn stream_loop(
+    follow: Follow,
+    handler: &mut Handler,
+    playlist: &mut Playlist,
+    status: &mut Option<StatusLine>,
+    deadline: Option<Instant>,
+    agent: &Agent,
+    hls_args: &HlsArgs,
+) -> anyhow::Error {
+    let mut ad_alternate = AdAlternate::Idle;
+    loop {
+        let time = Instant::now();
+        if deadline.is_some_and(|d| time >= d) {
+            return DurationReachedError.into();
+        }
+
+        if shutdown::requested() {
+            return ShutdownError.into();
+        }
+
+        if let Err(error) = playlist.reload() {
+            return error;
+        }
+
+        if let Err(error) = handler.process(playlist, time) {
+            if error.is::<ResetError>() {
+                playlist.reset();
+                continue;
+            }
+
+            if error.is::<RefreshError>() {
+                info!("{error}, refreshing playlist URL...");
+                match follow.playlist(hls_args, agent) {
+                    Ok(refreshed) => *playlist = refreshed,
+                    Err(error) => return error,
+                }
+
+                continue;
+            }
+
+            return error;
+        }
+
+        if matches!(follow, Follow::Main)
+            && (hls_args.proxy_on_ads() || hls_args.ad_audio())
+            && let Err(error) = ad_alternate.update(handler, hls_args, agent)
+        {
+            return error;
+        }
+
+        if let Some(status) = status {
+            status.update(playlist.latency());
+        }
+    }
+}
+
+fn wait_for_stream(hls_args: &HlsArgs, agent: &Agent) -> Result<Connection> {
+    const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+    let deadline = hls_args.reconnect_window().map(|w| Instant::now() + w);
+    match hls_args.reconnect_window() {
+        Some(window) => info!(
+            "Stream went offline, waiting up to {}s for it to come back...",
+            window.as_secs()
+        ),
+        None => info!("Stream went offline, waiting for it to come back..."),
+    }
+
+    loop {
+        let delay = deadline.map_or(RETRY_DELAY, |d| {
+            RETRY_DELAY.min(d.saturating_duration_since(Instant::now()))
+        });
+
+        thread::sleep(delay);
+        if shutdown::requested() {
+            return Err(ShutdownError.into());
+        }
+
+        match Stream::refresh(hls_args, agent) {
+            Ok(conn) => {
+                info!("Stream is back online, resuming...");
+                return Ok(conn);
+            }
+            Err(e) if e.is::<OfflineError>() && deadline.is_some_and(|d| Instant::now() >= d) => {
+                return Err(e);
+            }
+            Err(e) if e.is::<OfflineError>() => debug!("{e}, retrying..."),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+//Written in place of filtered ads, a proxied playlist with --proxy-on-ads or audio only with --ad-audio
+enum AdAlternate {
+    Idle,
+    Active(Box<Playlist>),
+    Failed, //not retried until the next ad break
+}
+
+impl AdAlternate {
+    fn update(&mut self, handler: &mut Handler, hls_args: &HlsArgs, agent: &Agent) -> Result<()> {
+        if !handler.is_filtering_ads() {
+            if matches!(self, Self::Active(_)) {
+                info!("Ad break over, switching back to the original playlist");
+            }
+
+            *self = Self::Idle;
+            handler.alternate_stopped();
+            return Ok(());
+        }
+
+        match self {
+            Self::Idle => {
+                match Self::fetch(hls_args, agent).and_then(|conn| Playlist::new(conn, None)) {
+                    Ok(playlist) => *self = Self::Active(Box::new(playlist)),
+                    Err(e) => {
+                        error!("Failed to fetch playlist for ad break: {e}");
+                        *self = Self::Failed;
+                        handler.alternate_stopped();
+                        return Ok(());
+                    }
+                }
+            }
+            Self::Active(playlist) => {
+                if let Err(e) = playlist.reload() {
+                    error!("Failed to reload playlist for ad break: {e}");
+                    *self = Self::Failed;
+                    handler.alternate_stopped();
+                    return Ok(());
+                }
+            }
+            Self::Failed => return Ok(()),
+        }
+
+        if let Self::Active(playlist) = self {
+            handler.process_alternate(playlist)?;
+        }
+
+        Ok(())
+    }
+
+    fn fetch(hls_args: &HlsArgs, agent: &Agent) -> Result<Connection> {
+        if hls_args.proxy_on_ads() {
+            info!("Switching to playlist proxy during ad break...");
+            return Stream::proxy(hls_args, agent);
+        }
+
+        info!("Switching to audio only during ad break...");
+        Stream::audio(hls_args, agent)
+    }
+}
+
+//Doesn't depend on which stream is played
+fn init_session(main_args: &Args) -> Result<()> {
+    if let Some(path) = &main_args.stats_file {
+        STATS.open_file(path, main_args.stats_format)?;
+    }
+
+    if let Some(addr) = &main_args.control {
+        control::spawn(addr, main_args.replay_dir.clone())?;
+    }
+
+    if let Some(length) = main_args.replay_buffer {
+        REPLAY.enable(length);
+    }
+
+    Ok(())
+}
+
+//Falls through to the next channel while they're offline
+fn open_stream(hls_args: &mut HlsArgs, agent: &Agent) -> Result<Stream> {
+    loop {
+        match Stream::new(hls_args, agent) {
+            Err(e) if e.is::<OfflineError>() && hls_args.next_channel() => {
+                info!("{e}, trying {}...", hls_args.channel());
+            }
+            stream => return stream,
+        }
+    }
+}
+
+//Recordings that follow their own playlists next to the main one
+fn spawn_recordings(
+    writer: &mut Writer,
+    recording: Option<Connection>,
+    output_args: &OutputArgs,
+    deadline: Option<Instant>,
+    hls_args: &HlsArgs,
+    agent: &Agent,
+) -> Result<Vec<JoinHandle<()>>> {
+    let mut handles = Vec::new();
+    if let Some(conn) = recording {
+        let playlist = Playlist::new(conn, None)?;
+        handles.push(spawn_follow(
+            Follow::Recording,
+            writer.split_recording()?,
+            playlist,
+            deadline,
+            hls_args,
+            agent,
+        )?);
+    }
+
+    if let Some(audio_writer) = Writer::audio(output_args)? {
+        let playlist = Playlist::new(Stream::audio(hls_args, agent)?, None)?;
+        handles.push(spawn_follow(
+            Follow::Audio,
+            audio_writer,
+            playlist,
+            deadline,
+            hls_args,
+            agent,
+        )?);
+    }
+
+    Ok(handles)
+}
+
+//Stopped with the main playlist through shutdown::request, see stop_recordings
+fn spawn_follow(
+    follow: Follow,
+    writer: Writer,
+    playlist: Playlist,
+    deadline: Option<Instant>,
+    hls_args: &HlsArgs,
+    agent: &Agent,
+) -> Result<JoinHandle<()>> {
+    let hls_args = hls_args.clone();
+    let agent = agent.clone();
+
+    ThreadBuilder::new()
+        .name(follow.name().to_owned())
+        .spawn(move || {
+            let error = main_loop(follow, writer, playlist, None, deadline, &agent, &hls_args)
+                .expect_err("Recording loop returned Ok");
+
+            if !(error.is::<OfflineError>()
+                || error.is::<DurationReachedError>()
+                || error.is::<ShutdownError>())
+            {
+                error!("{} stopped: {error}", follow.name());
+            }
+        })
+        .with_context(|| format!("Failed to spawn {} thread", follow.name()))
+}
+
+//Queued segments are written before the recordings are closed
+fn stop_recordings(handles: Vec<JoinHandle<()>>) {
+    if handles.is_empty() {
+        return;
+    }
+
+    shutdown::request();
+    for handle in handles {
+        if handle.join().is_err() {
+            error!("Recording thread panicked");
+        }
+    }
+}
+
+//Keeps the terminal title in sync with the stream title, which can change at any time
+fn spawn_title(metadata: Metadata, quality: Option<&str>, agent: &Agent) -> Result<()> {
+    const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+    let quality = quality.map_or_else(String::new, |q| format!(" ({q})"));
+    let agent = agent.clone();
+
+    logger::set_title(&format!("{}{quality}", metadata.channel()));
+    ThreadBuilder::new()
+        .name("title".to_owned())
+        .spawn(move || {
+            let mut current = None;
+            loop {
+                match metadata.fetch_title(&agent) {
+                    Ok(title) if title != current => {
+                        logger::set_title(&title.as_ref().map_or_else(
+                            || format!("{}{quality}", metadata.channel()),
+                            |t| format!("{} \u{2014} {t}{quality}", metadata.channel()),
+                        ));
+                        current = title;
+                    }
+                    Ok(_) => (),
+                    Err(e) => debug!("Failed to fetch stream title: {e}"),
+                }
+
+                thread::sleep(POLL_INTERVAL);
+            }
+        })
+        .context("Failed to spawn title thread")?;
+
+    Ok(())
+}
+
+//Shown in the player's window title, not worth failing over
+fn set_player_title(output_args: &mut OutputArgs, hls_args: &HlsArgs, agent: &Agent) {
+    if !output_args.player.wants_title() {
+        return;
+    }
+
+    let Some(metadata) = Metadata::new(hls_args) else {
+        return;
+    };
+
+    let (title, game) = match metadata.fetch_info(agent) {
+        Ok(info) => info,
+        Err(e) => {
+            debug!("Failed to fetch stream title: {e}");
+            return;
+        }
+    };
+
+    let channel = metadata.channel();
+    output_args.player.set_title(match (title, game) {
+        (Some(title), Some(game)) => format!("{channel} \u{2014} {title} ({game})"),
+        (Some(title), None) => format!("{channel} \u{2014} {title}"),
+        (None, _) => channel.to_owned(),
+    });
+}
+
+//Clips are a single MP4 file, there's no playlist to follow
+fn play_clip(
+    output_args: &OutputArgs,
+    channel: &str,
+    agent: &Agent,
+    url: &Url,
+) -> Result<ExitCode> {
+    let mut writer = Writer::new(output_args, channel)?;
+    if writer.should_wait() {
+        writer.wait_for_output()?;
+    }
+
+    let mut request = agent.binary(writer);
+    request.call(Method::Get, url)?;
+    request.into_writer().flush()?;
+
+    info!("Clip ended, exiting...");
+    Ok(ExitCode::SUCCESS)
+}
+
+//SECONDS, MM:SS or HH:MM:SS
+fn parse_time(arg: &str) -> Result<Option<Duration>> {
+    let mut secs = 0.0;
+    for (i, part) in arg.split(':').enumerate() {
+        ensure!(i < 3, "Invalid time, expected SECONDS, MM:SS or HH:MM:SS");
+        secs = secs * 60.0 + part.parse::<f64>()?;
+    }
+
+    Ok(Some(Duration::try_from_secs_f64(secs)?))
+}
+
+/// Runs the client with the process's command line, used by the binary
+///
+/// # Errors
+///
+/// The error that stopped the client, normal ways of stopping return an exit code instead
+pub fn main() -> Result<ExitCode> {
+    let (main_args, http_args, hls_args, output_args) = args::parse()?;
+
+    #[cfg(unix)]
+    let pidfile = {
+        if main_args.daemon {
+            if daemon::is_child() {
+                daemon::detach()?;
+            } else {
+                daemon::spawn(main_args.log_file.as_deref())?;
+            }
+        }
+
+        main_args
+            .pidfile
+            .as_deref()
+            .map(daemon::Pidfile::create)
+            .transpose()?
+    };
+
+    let result = Logger::init(main_args.debug, main_args.log_style, output_args.stdout())
+        .and_then(|()| run(&main_args, http_args, hls_args, output_args));
+
+    #[cfg(unix)]
+    if let Some(pidfile) = pidfile {
+        pidfile.remove();
+    }
+
+    result
+}
+
+pub fn run(
+    main_args: &Args,
+    http_args: http::Args,
+    mut hls_args: HlsArgs,
+    mut output_args: OutputArgs,
+) -> Result<ExitCode> {
+    debug!("\n{main_args:#?}\n{http_args:#?}\n{hls_args:#?}\n{output_args:#?}");
+
+    let (writer, playlist, status, deadline, agent, hls_args, recordings, sidecar, _lock) = {
+        init_session(main_args)?;
+
+        let agent = Agent::new(http_args)?;
+        let (conn, recording) = match open_stream(&mut hls_args, &agent) {
+            Ok(Stream::Variant(conn, recording)) => (conn, recording),
+            Ok(Stream::Passthrough(url)) => {
+                return Player::passthrough(&mut output_args.player, &url, hls_args.channel());
+            }
+            Ok(Stream::Clip(url)) => {
+                return play_clip(&output_args, hls_args.channel(), &agent, &url).or_else(exit);
+            }
+            Ok(Stream::Exit) => return Ok(ExitCode::SUCCESS),
+            Err(e) if e.is::<OfflineError>() => {
+                info!("{e}, exiting...");
+                return Ok(ExitCode::from(STREAM_ENDED_EXIT_CODE));
+            }
+            Err(e) => return Err(e),
+        };
+
+        //Locked once it's known which of the channels is played
+        let lock = ChannelLock::acquire(
+            main_args.config_dir.as_deref(),
+            hls_args.channel(),
+            main_args.channel_lock,
+        )?;
+
+        if !main_args.no_terminal_title
+            && !main_args.log_style.is_plain()
+            && let Some(metadata) = Metadata::new(&hls_args)
+        {
+            spawn_title(metadata, hls_args.quality(), &agent)?;
+        }
+
+        set_player_title(&mut output_args, &hls_args, &agent);
+
+        let mut writer = Writer::new(&output_args, hls_args.channel())?;
+        let sidecar = output_args
+            .record_metadata()
+            .then(|| writer.recording_path())
+            .flatten()
+            .map(|path| Sidecar::new(&path, &hls_args, &agent))
+            .transpose()?;
+
+        let deadline = main_args.duration.map(|d| Instant::now() + d);
+        let recordings = spawn_recordings(
+            &mut writer,
+            recording,
+            &output_args,
+            deadline,
+            &hls_args,
+            &agent,
+        )?;
+
+        (
+            writer,
+            Playlist::new(conn, hls_args.dump_playlists())?,
+            (main_args.status_line && !main_args.debug && !main_args.log_style.is_plain())
+                .then(|| StatusLine::new(hls_args.quality())),
+            deadline,
+            agent,
+            hls_args,
+            recordings,
+            sidecar,
+            lock,
+        )
+    };
+
+    let _stopped = shutdown::register()?;
+    let error = main_loop(
+        Follow::Main,
+        writer,
+        playlist,
+        status,
+        deadline,
+        &agent,
+        &hls_args,
+    )
+    .expect_err("Main loop returned Ok");
+    stop_recordings(recordings);
+    logger::clear_status();
+    STATS.print_summary();
+
+    if let Some(sidecar) = &sidecar
+        && let Err(e) = sidecar.write()
+    {
+        error!("{e}");
+    }
+
+    if error.is::<OfflineError>() {
+        info!("Stream ended, exiting...");
+        return Ok(ExitCode::from(STREAM_ENDED_EXIT_CODE));
+    }
+
+    exit(error)
+}
+
+//Errors that are a normal way for the client to stop
+fn exit(error: anyhow::Error) -> Result<ExitCode> {
+    if error.is::<DurationReachedError>() || error.is::<ShutdownError>() {
+        info!("{error}, exiting...");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(error) = error.downcast_ref::<io::Error>().and_then(|e| e.get_ref()) {
+        if let Some(error) = error.downcast_ref::<PlayerClosedError>() {
+            info!("Player closed, exiting...");
+            return Ok(error.exit_code());
+        }
+
+        if error.is::<StdoutClosedError>() {
+            info!("{error}, exiting...");
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        if error.is::<FileSizeLimitError>() {
+            info!("{error}, exiting...");
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        #[cfg(feature = "ffi")]
+        if error.is::<crate::output::CallbackStoppedError>() {
+            info!("{error}, exiting...");
+            return Ok(ExitCode::SUCCESS);
+        }
+    }
+
+    Err(error)
+}
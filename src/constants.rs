@@ -1,12 +1,20 @@
 pub const USER_AGENT: &str =
     "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:149.0) Gecko/20100101 Firefox/149.0";
+pub const FIREFOX_LINUX_USER_AGENT: &str =
+    "Mozilla/5.0 (X11; Linux x86_64; rv:149.0) Gecko/20100101 Firefox/149.0";
+pub const CHROME_WINDOWS_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) \
+    AppleWebKit/537.36 (KHTML, like Gecko) Chrome/147.0.0.0 Safari/537.36";
+pub const CHROME_LINUX_USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64) \
+    AppleWebKit/537.36 (KHTML, like Gecko) Chrome/147.0.0.0 Safari/537.36";
 
 pub const PLAYER_VERSION: &str = "1.49.0-rc.3";
 
 pub const TWITCH_GQL_ENDPOINT: &str = "https://gql.twitch.tv/gql";
 pub const TWITCH_OAUTH_ENDPOINT: &str = "https://id.twitch.tv/oauth2/validate";
 pub const TWITCH_HLS_BASE: &str = "https://usher.ttvnw.net/api/channel/hls/";
+pub const TWITCH_DOMAINS: [&str; 3] = ["twitch.tv", "ttvnw.net", "jtvnw.net"];
 
+#[cfg(feature = "kick")]
 pub const KICK_CHANNELS_ENDPOINT: &str = "https://kick.com/api/v2/channels";
 
 pub const DEFAULT_CLIENT_ID: &str = "kimne78kx3ncx6brgo4mv6wki5h1ko";
@@ -0,0 +1,101 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    thread::Builder as ThreadBuilder,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use log::{debug, error, info};
+
+use crate::{replay::REPLAY, stats::STATS};
+
+//Line based control interface, one command per line
+pub fn spawn(addr: &SocketAddr, replay_dir: Option<PathBuf>) -> Result<()> {
+    let listener = TcpListener::bind(addr).context("Failed to bind control address/port")?;
+    info!("Control interface listening on: {addr}");
+
+    ThreadBuilder::new()
+        .name("control".to_owned())
+        .spawn(move || {
+            for incoming in listener.incoming() {
+                match incoming {
+                    Ok(sock) => {
+                        if let Err(e) = handle_client(sock, replay_dir.as_deref()) {
+                            debug!("Control client error: {e}");
+                        }
+                    }
+                    Err(e) => error!("Failed to accept control client: {e}"),
+                }
+            }
+        })
+        .context("Failed to spawn control thread")?;
+
+    Ok(())
+}
+
+fn handle_client(sock: TcpStream, replay_dir: Option<&Path>) -> io::Result<()> {
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    sock.set_read_timeout(Some(TIMEOUT))?;
+    sock.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut writer = sock.try_clone()?;
+    for line in BufReader::new(sock).lines() {
+        match line?.trim() {
+            "status" => STATS.write_status(&mut writer)?,
+            "" => continue,
+            command => match command.split_once(' ') {
+                Some(("replay", name)) => save_replay(&mut writer, replay_dir, name.trim())?,
+                _ => writeln!(writer, "error=unknown command: {command}")?,
+            },
+        }
+
+        writer.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+//Anyone that can connect can send commands, so replays only go in --replay-dir
+fn save_replay(mut writer: impl Write, dir: Option<&Path>, name: &str) -> io::Result<()> {
+    let Some(dir) = dir.filter(|_| REPLAY.is_enabled()) else {
+        return writeln!(writer, "error=--replay-buffer not configured");
+    };
+
+    if !is_file_name(name) {
+        return writeln!(writer, "error=invalid file name: {name}");
+    }
+
+    let path = dir.join(name);
+    let path = path.as_path();
+
+    match REPLAY.save(path) {
+        Ok(duration) => {
+            info!(
+                "Replay saved to: {} ({}s)",
+                path.display(),
+                duration.as_secs()
+            );
+            writeln!(
+                writer,
+                "path={}\nduration={}",
+                path.display(),
+                duration.as_secs()
+            )
+        }
+        Err(e) => {
+            error!("Failed to save replay: {e}");
+            writeln!(writer, "error={e}")
+        }
+    }
+}
+
+//Single normal component, no separators, parent directories or roots
+fn is_file_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    !name.contains(['/', '\\'])
+        && matches!(components.next(), Some(Component::Normal(_)))
+        && components.next().is_none()
+}
@@ -0,0 +1,118 @@
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    process::{self, Command, Stdio},
+};
+
+use anyhow::{Context, Result, bail};
+use log::error;
+use nix::{
+    errno::Errno,
+    sys::signal,
+    unistd::{self, Pid},
+};
+
+//Set in the environment of the re-executed background process
+const CHILD_ENV: &str = "TWITCH_HLS_CLIENT_DAEMON";
+
+pub fn is_child() -> bool {
+    env::var_os(CHILD_ENV).is_some()
+}
+
+//Re-executes the client with output going to the log file, the child detaches itself.
+//Forking isn't possible without unsafe, and this keeps the parent's arguments and config intact.
+pub fn spawn(log_file: Option<&str>) -> Result<()> {
+    let (stdout, stderr) = match log_file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .context("Failed to open log file")?;
+
+            (Stdio::from(file.try_clone()?), Stdio::from(file))
+        }
+        None => (Stdio::null(), Stdio::null()),
+    };
+
+    let child = Command::new(env::current_exe().context("Failed to find executable path")?)
+        .args(env::args_os().skip(1))
+        .env(CHILD_ENV, "1")
+        .stdin(Stdio::null())
+        .stdout(stdout)
+        .stderr(stderr)
+        .spawn()
+        .context("Failed to start background process")?;
+
+    println!("Running in background (pid {})", child.id());
+    process::exit(0);
+}
+
+//Called first in the background process. A new session has no controlling terminal,
+//so closing the terminal or the shell's job control doesn't signal it anymore.
+pub fn detach() -> Result<()> {
+    unistd::setsid().context("Failed to create new session")?;
+    Ok(())
+}
+
+//Removed on exit by main, dropping it only covers returning early with an error
+pub struct Pidfile {
+    path: Option<String>,
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            Self::remove_file(&path);
+        }
+    }
+}
+
+impl Pidfile {
+    pub fn create(path: &str) -> Result<Self> {
+        //A pidfile of a process that's gone was left behind by a kill or crash
+        if let Some(pid) = Self::running_pid(path) {
+            bail!("Already running with pid {pid} (pidfile: {path})");
+        }
+
+        fs::write(path, format!("{}\n", process::id())).context("Failed to write pidfile")?;
+        Ok(Self {
+            path: Some(path.to_owned()),
+        })
+    }
+
+    pub fn remove(mut self) {
+        if let Some(path) = self.path.take() {
+            Self::remove_file(&path);
+        }
+    }
+
+    fn remove_file(path: &str) {
+        //Another instance took it over after this one's was removed by hand
+        if Self::read_pid(path).is_some_and(|pid| pid != Self::own_pid()) {
+            return;
+        }
+
+        if let Err(e) = fs::remove_file(path) {
+            error!("Failed to remove pidfile: {e}");
+        }
+    }
+
+    fn running_pid(path: &str) -> Option<i32> {
+        let pid = Self::read_pid(path)?;
+
+        //Signal 0 only checks if the process exists, EPERM means it's another user's
+        (pid > 0
+            && pid != Self::own_pid()
+            && !matches!(signal::kill(Pid::from_raw(pid), None), Err(Errno::ESRCH)))
+        .then_some(pid)
+    }
+
+    fn read_pid(path: &str) -> Option<i32> {
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+
+    fn own_pid() -> i32 {
+        Pid::this().as_raw()
+    }
+}
@@ -0,0 +1,160 @@
+//C ABI for embedding the client, see include/twitch_hls_client.h.
+//One session runs at a time, it's configured with the same arguments as the command line.
+
+use std::{
+    ffi::{CStr, OsString, c_char, c_int, c_void},
+    process::ExitCode,
+    slice,
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{Builder as ThreadBuilder, JoinHandle},
+};
+
+use anyhow::Result;
+
+use crate::{
+    args,
+    logger::Logger,
+    output::{Callback, CallbackFunction},
+    shutdown,
+    stats::STATS,
+};
+
+static SESSION: Mutex<Option<JoinHandle<Result<ExitCode>>>> = Mutex::new(None);
+
+//The logger is process wide, the first session's --debug and --log-style are kept
+static LOGGER: AtomicBool = AtomicBool::new(false);
+
+#[repr(C)]
+pub struct Status {
+    running: c_int,
+    segments: u64,
+    bytes: u64,
+    queued: u64,
+    skipped: u64,
+    dropped: u64,
+}
+
+//Returns 0, or -1 if the arguments aren't valid UTF-8 or a session is already running
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn thc_session_start(count: c_int, args: *const *const c_char) -> c_int {
+    let Ok(count) = usize::try_from(count) else {
+        return -1;
+    };
+
+    let args = if count == 0 {
+        Ok(Vec::new())
+    } else {
+        //SAFETY: the caller passes count valid C strings, like main's argv
+        unsafe { slice::from_raw_parts(args, count) }
+            .iter()
+            .map(|&arg| unsafe { CStr::from_ptr(arg) }.to_str().map(OsString::from))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let Ok(args) = args else {
+        return -1;
+    };
+
+    let mut session = SESSION.lock().unwrap_or_else(PoisonError::into_inner);
+    if session.as_ref().is_some_and(|s| !s.is_finished()) {
+        return -1;
+    }
+
+    if let Some(finished) = session.take() {
+        report(finished);
+    }
+
+    shutdown::embed();
+    let handle = match ThreadBuilder::new()
+        .name("session".to_owned())
+        .spawn(move || run(args))
+    {
+        Ok(handle) => handle,
+        Err(e) => {
+            eprintln!("Failed to spawn session thread: {e}");
+            return -1;
+        }
+    };
+
+    *session = Some(handle);
+    0
+}
+
+//Stops the stream gracefully and waits for it, returns the session's result like an exit
+//code (0 or 1), or -1 if no session was started
+#[unsafe(no_mangle)]
+pub extern "C" fn thc_session_stop() -> c_int {
+    let Some(session) = SESSION
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+    else {
+        return -1;
+    };
+
+    shutdown::request();
+    report(session)
+}
+
+//Replaces the callback for sessions started after this, NULL removes it
+#[unsafe(no_mangle)]
+pub extern "C" fn thc_set_output_callback(
+    callback: Option<CallbackFunction>,
+    user_data: *mut c_void,
+) {
+    Callback::set(callback, user_data);
+}
+
+//Counts are for the whole process, returns -1 if status is NULL
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn thc_session_status(status: *mut Status) -> c_int {
+    if status.is_null() {
+        return -1;
+    }
+
+    let running = SESSION
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+        .is_some_and(|s| !s.is_finished());
+
+    //SAFETY: checked for NULL, the caller passes a writable thc_status
+    unsafe {
+        status.write(Status {
+            running: c_int::from(running),
+            segments: STATS.segments(),
+            bytes: STATS.bytes(),
+            queued: STATS.queued(),
+            skipped: STATS.skipped(),
+            dropped: STATS.dropped(),
+        });
+    }
+
+    0
+}
+
+fn run(args: Vec<OsString>) -> Result<ExitCode> {
+    let (main_args, http_args, hls_args, output_args) = args::parse_vec(args)?;
+    if !LOGGER.swap(true, Ordering::Relaxed) {
+        Logger::init(main_args.debug, main_args.log_style, output_args.stdout())?;
+    }
+
+    crate::cli::run(&main_args, http_args, hls_args, output_args)
+}
+
+fn report(session: JoinHandle<Result<ExitCode>>) -> c_int {
+    match session.join() {
+        Ok(Ok(code)) if code == ExitCode::SUCCESS => 0,
+        Ok(Ok(_)) => 1,
+        Ok(Err(e)) => {
+            eprintln!("Error: {e:?}"); //same as returning it from main
+            1
+        }
+        Err(_) => {
+            eprintln!("Session thread panicked");
+            1
+        }
+    }
+}
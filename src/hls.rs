@@ -1,22 +1,36 @@
 mod cache;
+mod clip;
+mod hook;
+#[cfg(feature = "kick")]
+mod kick;
+mod metadata;
 mod multivariant;
+mod parse;
 mod playlist;
+mod queue;
 mod segment;
+mod state;
 
+pub use metadata::Metadata;
 pub use multivariant::Stream;
 pub use playlist::Playlist;
-pub use segment::{Handler, ResetError};
+pub use segment::{Handler, RefreshError, ResetError};
 
 use std::{
-    borrow::Cow,
     fmt::{self, Debug, Display, Formatter},
+    time::Duration,
 };
 
 use anyhow::{Context, Result, bail, ensure};
 
+use hook::Args as HookArgs;
+use queue::Policy as QueuePolicy;
+
 use crate::{
     args::{Parse, Parser},
-    http::{StatusError, Url},
+    constants,
+    http::{StatusError, Url, encode_base64},
+    obs::Args as ObsArgs,
 };
 
 #[derive(Debug)]
@@ -30,39 +44,90 @@ impl Display for OfflineError {
     }
 }
 
+#[derive(Clone)]
 pub struct Args {
     servers: Option<Vec<Url>>,
+    proxy_auth: Option<String>,
     print_streams: bool,
     no_low_latency: bool,
     passthrough: Passthrough,
     client_id: Option<String>,
     auth_token: Option<String>,
-    codecs: Cow<'static, str>,
+    gql_url: Url,
+    codecs: Option<String>,
+    access_platform: AccessPlatform,
     never_proxy: Option<Vec<String>>,
     playlist_cache_dir: Option<String>,
     use_cache_only: bool,
     write_cache_only: bool,
     force_playlist_url: Option<Url>,
+    latency_target: Option<Duration>,
+    segment_timeout: Option<f32>,
+    queue_size: usize,
+    queue_policy: QueuePolicy,
+    parallel_prefetch: bool,
+    max_segment_lag: Option<Duration>,
+    start_buffer: usize,
+    mpv_ipc: Option<String>,
+    max_height: Option<u16>,
+    record_quality: Option<String>,
+    dump_playlists: Option<String>,
+    reconnect: bool,
+    reconnect_window: Option<Duration>,
+    ad_audio: bool,
+    no_ad_filter: bool,
+    proxy_on_ads: bool,
+    ad_log: Option<String>,
+    obs: ObsArgs,
+    hook: HookArgs,
     channel: String,
+    fallback_channels: Vec<String>, //reversed so the next one can be popped
+    clip: Option<String>,
+    playlist_url: Option<Url>,
     quality: Option<String>,
 }
 
 impl Default for Args {
     fn default() -> Self {
         Self {
-            codecs: "av1,h265,h264".into(),
+            codecs: Option::default(),
+            gql_url: constants::TWITCH_GQL_ENDPOINT.into(),
             servers: Option::default(),
+            proxy_auth: Option::default(),
             print_streams: bool::default(),
             no_low_latency: bool::default(),
             passthrough: Passthrough::default(),
             client_id: Option::default(),
             auth_token: Option::default(),
+            access_platform: AccessPlatform::default(),
             never_proxy: Option::default(),
             playlist_cache_dir: Option::default(),
             use_cache_only: bool::default(),
             write_cache_only: bool::default(),
             force_playlist_url: Option::default(),
+            latency_target: Option::default(),
+            segment_timeout: Option::default(),
+            queue_size: queue::DEFAULT_SIZE,
+            queue_policy: QueuePolicy::default(),
+            parallel_prefetch: bool::default(),
+            max_segment_lag: Option::default(),
+            start_buffer: usize::default(),
+            mpv_ipc: Option::default(),
+            max_height: Option::default(),
+            record_quality: Option::default(),
+            dump_playlists: Option::default(),
+            reconnect: bool::default(),
+            reconnect_window: Option::default(),
+            ad_audio: bool::default(),
+            no_ad_filter: bool::default(),
+            proxy_on_ads: bool::default(),
+            ad_log: Option::default(),
+            obs: ObsArgs::default(),
+            hook: HookArgs::default(),
             channel: String::default(),
+            fallback_channels: Vec::default(),
+            clip: Option::default(),
+            playlist_url: Option::default(),
             quality: Option::default(),
         }
     }
@@ -79,18 +144,43 @@ impl Debug for Args {
 
         f.debug_struct("Args")
             .field("servers", &self.servers)
+            .field("proxy_auth", &hide_option(&self.proxy_auth))
             .field("print_streams", &self.print_streams)
             .field("no_low_latency", &self.no_low_latency)
             .field("passthrough", &self.passthrough)
             .field("client_id", &hide_option(&self.client_id))
             .field("auth_token", &hide_option(&self.auth_token))
+            .field("gql_url", &self.gql_url)
             .field("codecs", &self.codecs)
+            .field("access_platform", &self.access_platform)
             .field("never_proxy", &self.never_proxy)
             .field("playlist_cache_dir", &self.playlist_cache_dir)
             .field("use_cache_only", &self.use_cache_only)
             .field("write_cache_only", &self.write_cache_only)
             .field("force_playlist_url", &self.force_playlist_url)
+            .field("latency_target", &self.latency_target)
+            .field("segment_timeout", &self.segment_timeout)
+            .field("queue_size", &self.queue_size)
+            .field("queue_policy", &self.queue_policy)
+            .field("parallel_prefetch", &self.parallel_prefetch)
+            .field("max_segment_lag", &self.max_segment_lag)
+            .field("start_buffer", &self.start_buffer)
+            .field("mpv_ipc", &self.mpv_ipc)
+            .field("max_height", &self.max_height)
+            .field("record_quality", &self.record_quality)
+            .field("dump_playlists", &self.dump_playlists)
+            .field("reconnect", &self.reconnect)
+            .field("reconnect_window", &self.reconnect_window)
+            .field("ad_audio", &self.ad_audio)
+            .field("no_ad_filter", &self.no_ad_filter)
+            .field("proxy_on_ads", &self.proxy_on_ads)
+            .field("ad_log", &self.ad_log)
+            .field("obs", &self.obs)
+            .field("hook", &self.hook)
             .field("channel", &self.channel)
+            .field("fallback_channels", &self.fallback_channels)
+            .field("clip", &self.clip)
+            .field("playlist_url", &self.playlist_url)
             .field("quality", &self.quality)
             .finish()
     }
@@ -99,17 +189,59 @@ impl Debug for Args {
 impl Parse for Args {
     fn parse(&mut self, parser: &mut Parser) -> Result<()> {
         parser.parse_comma_list_cfg(&mut self.servers, "-s", "servers")?;
+        parser.parse_fn(&mut self.proxy_auth, "--proxy-auth", parse_proxy_auth)?;
         parser.parse_switch(&mut self.print_streams, "--print-streams")?;
         parser.parse_switch(&mut self.no_low_latency, "--no-low-latency")?;
         parser.parse_fn(&mut self.passthrough, "--passthrough", Passthrough::new)?;
         parser.parse_opt(&mut self.client_id, "--client-id")?;
         parser.parse_opt(&mut self.auth_token, "--auth-token")?;
-        parser.parse_cow_string(&mut self.codecs, "--codecs")?;
+        parser.parse(&mut self.gql_url, "--gql-url")?;
+        parser.parse_opt(&mut self.codecs, "--codecs")?;
+        parser.parse_fn(
+            &mut self.access_platform,
+            "--access-platform",
+            AccessPlatform::new,
+        )?;
         parser.parse_comma_list(&mut self.never_proxy, "--never-proxy")?;
         parser.parse_opt(&mut self.playlist_cache_dir, "--playlist-cache-dir")?;
         parser.parse_switch(&mut self.use_cache_only, "--use-cache-only")?;
         parser.parse_switch(&mut self.write_cache_only, "--write-cache-only")?;
         parser.parse_opt(&mut self.force_playlist_url, "--force-playlist-url")?;
+        parser.parse_fn(&mut self.latency_target, "--latency-target", |arg| {
+            Ok(Some(Duration::try_from_secs_f64(arg.parse()?)?))
+        })?;
+        parser.parse_fn(&mut self.segment_timeout, "--segment-timeout", |arg| {
+            let multiple = arg.parse()?;
+            ensure!(multiple > 0.0, "--segment-timeout must be greater than 0");
+
+            Ok(Some(multiple))
+        })?;
+        parser.parse_fn(&mut self.queue_size, "--queue-size", |arg| {
+            let size = arg.parse()?;
+            ensure!(size > 0, "--queue-size must be greater than 0");
+
+            Ok(size)
+        })?;
+        parser.parse_fn(&mut self.queue_policy, "--queue-policy", QueuePolicy::new)?;
+        parser.parse_switch(&mut self.parallel_prefetch, "--parallel-prefetch")?;
+        parser.parse_fn(&mut self.max_segment_lag, "--max-segment-lag", |arg| {
+            Ok(Some(Duration::try_from_secs_f64(arg.parse()?)?))
+        })?;
+        parser.parse(&mut self.start_buffer, "--start-buffer")?;
+        parser.parse_opt(&mut self.mpv_ipc, "--mpv-ipc")?;
+        parser.parse_opt(&mut self.max_height, "--max-height")?;
+        parser.parse_opt(&mut self.record_quality, "--record-quality")?;
+        parser.parse_opt(&mut self.dump_playlists, "--dump-playlists")?;
+        parser.parse_switch(&mut self.reconnect, "--reconnect")?;
+        parser.parse_fn(&mut self.reconnect_window, "--reconnect-window", |arg| {
+            Ok(Some(Duration::try_from_secs_f64(arg.parse()?)?))
+        })?;
+        parser.parse_switch(&mut self.ad_audio, "--ad-audio")?;
+        parser.parse_switch(&mut self.no_ad_filter, "--no-ad-filter")?;
+        parser.parse_opt(&mut self.ad_log, "--ad-log")?;
+        parser.parse_switch(&mut self.proxy_on_ads, "--proxy-on-ads")?;
+        self.obs.parse(parser)?;
+        self.hook.parse(parser)?;
 
         if self.use_cache_only || self.write_cache_only {
             ensure!(
@@ -118,6 +250,16 @@ impl Parse for Args {
             );
         }
 
+        ensure!(
+            self.record_quality.is_none() || self.force_playlist_url.is_none(),
+            "--record-quality requires fetching the multivariant playlist"
+        );
+
+        ensure!(
+            !self.proxy_on_ads || self.servers.is_some(),
+            "--proxy-on-ads requires a playlist proxy (-s)"
+        );
+
         ensure!(
             !(self.use_cache_only && self.write_cache_only),
             "--use-cache-only and --write-cache-only cannot be used together"
@@ -127,22 +269,32 @@ impl Parse for Args {
             .parse_free_required()
             .context("Missing channel argument")?;
 
-        self.channel = channel
-            .rsplit_once('/')
-            .map_or(channel.as_str(), |s| s.1)
-            .to_lowercase();
+        if is_playlist_url(&channel) {
+            let url = Url::from(channel);
+            self.channel = match url.host() {
+                Ok(host) if !host.is_empty() => host.to_owned(),
+                _ => "playlist".to_owned(),
+            };
+            self.playlist_url = Some(url);
+        } else if let Some((channel, slug)) = parse_clip(&channel) {
+            self.channel = channel.to_lowercase();
+            self.clip = Some(slug.to_owned());
+        } else {
+            //CHANNEL1,CHANNEL2, the first one that's live is played
+            let mut channels = channel
+                .split(',')
+                .map(str::trim)
+                .map(|c| c.rsplit_once('/').map_or(c, |s| s.1).to_lowercase());
+
+            self.channel = channels.next().unwrap_or_default();
+            self.fallback_channels = channels.rev().collect();
+        }
 
         parser.parse_free(&mut self.quality, "quality")?;
         if self.print_streams {
             self.quality = None;
         }
 
-        if let Some(never_proxy) = &self.never_proxy
-            && never_proxy.iter().any(|a| a.eq(&self.channel))
-        {
-            self.servers = None;
-        }
-
         Ok(())
     }
 }
@@ -151,9 +303,73 @@ impl Args {
     pub fn channel(&self) -> &str {
         &self.channel
     }
+
+    //Switches to the next fallback channel, false if there are none left
+    pub fn next_channel(&mut self) -> bool {
+        let Some(channel) = self.fallback_channels.pop() else {
+            return false;
+        };
+
+        self.channel = channel;
+        true
+    }
+
+    //Proxies are only used for ad breaks with --proxy-on-ads
+    fn servers(&self) -> Option<&[Url]> {
+        if self.proxy_on_ads {
+            return None;
+        }
+
+        self.proxy_servers()
+    }
+
+    fn proxy_servers(&self) -> Option<&[Url]> {
+        if self
+            .never_proxy
+            .as_ref()
+            .is_some_and(|n| n.contains(&self.channel))
+        {
+            return None;
+        }
+
+        self.servers.as_deref()
+    }
+
+    pub fn quality(&self) -> Option<&str> {
+        self.quality.as_deref()
+    }
+
+    pub fn record_quality(&self) -> Option<&str> {
+        self.record_quality.as_deref()
+    }
+
+    pub fn dump_playlists(&self) -> Option<&str> {
+        self.dump_playlists.as_deref()
+    }
+
+    pub const fn proxy_on_ads(&self) -> bool {
+        self.proxy_on_ads
+    }
+
+    pub const fn ad_audio(&self) -> bool {
+        self.ad_audio
+    }
+
+    pub const fn reconnect(&self) -> bool {
+        self.reconnect || self.reconnect_window.is_some()
+    }
+
+    //How long to wait for the stream to come back, None to wait forever
+    pub const fn reconnect_window(&self) -> Option<Duration> {
+        if self.reconnect {
+            return None;
+        }
+
+        self.reconnect_window
+    }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 enum Passthrough {
     Variant,
     Multivariant,
@@ -173,6 +389,95 @@ impl Passthrough {
     }
 }
 
+//Platform the access token is requested as, each can get a different ad load
+#[derive(Debug, Default, Copy, Clone)]
+enum AccessPlatform {
+    #[default]
+    Web,
+    Ios,
+    Android,
+    Embed,
+}
+
+impl AccessPlatform {
+    fn new(arg: &str) -> Result<Self> {
+        match arg {
+            "web" => Ok(Self::Web),
+            "ios" => Ok(Self::Ios),
+            "android" => Ok(Self::Android),
+            "embed" => Ok(Self::Embed),
+            _ => bail!("Invalid access platform"),
+        }
+    }
+
+    //playerType and platform sent with the GQL token request.
+    //Turbo and subscriptions aren't applied to embedded players, so those use the site player when authenticated.
+    const fn gql(self, authenticated: bool) -> (&'static str, &'static str) {
+        match self {
+            Self::Web => ("site", "site"),
+            Self::Embed if authenticated => ("site", "web"),
+            Self::Ios => ("site", "ios"),
+            Self::Android => ("site", "android"),
+            Self::Embed => ("embed", "web"),
+        }
+    }
+
+    //platform sent with the playlist request
+    const fn usher(self) -> &'static str {
+        match self {
+            Self::Web | Self::Embed => "web",
+            Self::Ios => "ios",
+            Self::Android => "android",
+        }
+    }
+}
+
+//basic:USERNAME:PASSWORD or bearer:TOKEN, formatted as a Proxy-Authorization value
+fn parse_proxy_auth(arg: &str) -> Result<Option<String>> {
+    match arg.split_once(':') {
+        Some(("basic", credentials)) => {
+            ensure!(
+                credentials.contains(':'),
+                "Invalid proxy credentials, expected basic:<USERNAME:PASSWORD>"
+            );
+
+            Ok(Some(format!(
+                "Basic {}",
+                encode_base64(credentials.as_bytes())
+            )))
+        }
+        Some(("bearer", token)) => Ok(Some(format!("Bearer {token}"))),
+        _ => bail!("Invalid proxy auth, expected basic:<USERNAME:PASSWORD> or bearer:<TOKEN>"),
+    }
+}
+
+//HLS playlists from anywhere other than Twitch or Kick
+fn is_playlist_url(arg: &str) -> bool {
+    ["http://", "https://", "file://"]
+        .iter()
+        .any(|scheme| arg.starts_with(scheme))
+        && arg.contains(".m3u8")
+}
+
+//clip:SLUG, clips.twitch.tv/SLUG or twitch.tv/CHANNEL/clip/SLUG, slugs are case sensitive.
+//Channel is the slug itself if the URL doesn't have one.
+fn parse_clip(arg: &str) -> Option<(&str, &str)> {
+    let arg = arg
+        .split_once('?')
+        .map_or(arg, |s| s.0)
+        .trim_end_matches('/');
+    if let Some(slug) = arg.strip_prefix("clip:") {
+        return Some((slug, slug));
+    }
+
+    if let Some((_, slug)) = arg.split_once("clips.twitch.tv/") {
+        return Some((slug, slug));
+    }
+
+    let (head, slug) = arg.split_once("/clip/")?;
+    Some((head.rsplit_once('/').map_or(head, |s| s.1), slug))
+}
+
 fn map_if_offline(error: anyhow::Error) -> anyhow::Error {
     if StatusError::is_not_found(&error) {
         return OfflineError.into();
@@ -0,0 +1,150 @@
+use anyhow::{Context, Result, bail, ensure};
+use log::{debug, info};
+
+use super::{Args, multivariant::extract};
+use crate::{
+    constants,
+    http::{Agent, Method, Url},
+};
+
+//Clips are plain MP4 files, resolved through GQL instead of a multivariant playlist
+struct Quality<'a> {
+    name: String,
+    height: u16,
+    url: &'a str,
+}
+
+//Returns None if the streams were printed instead
+pub fn fetch(args: &Args, slug: &str, agent: &Agent) -> Result<Option<Url>> {
+    ensure!(
+        slug.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+        "Invalid clip slug: {slug}"
+    );
+
+    info!("Fetching clip {slug}");
+    let body = format!(
+        r#"{{"query":"query{{clip(slug:\"{slug}\"){{playbackAccessToken(params:{{platform:\"web\",playerBackend:\"mediaplayer\",playerType:\"site\"}}){{signature value}}videoQualities{{frameRate quality sourceURL}}}}}}"}}"#,
+    );
+
+    let mut request = agent.text();
+    request.text_fmt(
+        Method::Post,
+        &args.gql_url,
+        format_args!(
+            "Content-Type: text/plain;charset=UTF-8\r\n\
+             Client-ID: {client_id}\r\n\
+             Content-Length: {content_length}\r\n\
+             \r\n\
+             {body}",
+            client_id = args
+                .client_id
+                .as_deref()
+                .unwrap_or(constants::DEFAULT_CLIENT_ID),
+            content_length = body.len(),
+        ),
+    )?;
+
+    let mut response = request.take();
+    response.retain(|c| c != '\\');
+
+    debug!("GQL clip response: {response}");
+    if response.contains(r#""clip":null"#) {
+        bail!("Clip not found: {slug}");
+    }
+
+    let qualities = parse_qualities(&response);
+    let (Some(quality), false) = (&args.quality, args.print_streams) else {
+        print_qualities(&qualities);
+        return Ok(None);
+    };
+
+    let mut iter = qualities
+        .iter()
+        .filter(|q| args.max_height.is_none_or(|max| q.height <= max));
+
+    let chosen = match quality.as_str() {
+        "best" | "source" | "chunked" => iter.next(),
+        quality => iter.find(|q| q.name == quality || q.name.starts_with(quality)),
+    }
+    .context("Failed to find clip quality")?;
+
+    let signature = extract(&response, r#""signature":""#, r#"","value""#)
+        .context("Failed to find signature in GQL clip response")?;
+    let token = extract(&response, r#""value":""#, r#""},"videoQualities""#)
+        .context("Failed to find token in GQL clip response")?;
+
+    Ok(Some(
+        format!(
+            "{}?sig={signature}&token={}",
+            chosen.url,
+            encode_query(token)
+        )
+        .into(),
+    ))
+}
+
+// {"frameRate":60,"quality":"1080","sourceURL":"https://..."}, sorted from highest to lowest
+fn parse_qualities(response: &str) -> Vec<Quality<'_>> {
+    let Some((_, qualities)) = response.split_once(r#""videoQualities":["#) else {
+        return Vec::new();
+    };
+
+    qualities
+        .split(r#"{"frameRate":"#)
+        .skip(1)
+        .filter_map(|item| {
+            let frame_rate = item.split(',').next()?.parse::<f32>().ok()?;
+            let height = item
+                .split_once(r#""quality":""#)?
+                .1
+                .split('"')
+                .next()?
+                .parse::<u16>()
+                .ok()?;
+            let url = item.split_once(r#""sourceURL":""#)?.1.split('"').next()?;
+
+            //Same naming as live streams, frame rate is left out at 30
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let frame_rate = frame_rate.round() as u16;
+            let name = if frame_rate > 30 {
+                format!("{height}p{frame_rate}")
+            } else {
+                format!("{height}p")
+            };
+
+            Some(Quality { name, height, url })
+        })
+        .collect()
+}
+
+fn print_qualities(qualities: &[Quality]) {
+    print!("Available streams: ");
+    for (i, quality) in qualities.iter().enumerate() {
+        if i != 0 {
+            print!(", ");
+        }
+        print!("{}", quality.name);
+        if i == 0 {
+            print!(" (best)");
+        }
+    }
+    println!();
+}
+
+fn encode_query(value: &str) -> String {
+    const HEX: &[u8] = b"0123456789ABCDEF";
+
+    let mut encoded = String::with_capacity(value.len() * 3);
+    for b in value.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(b as char);
+        } else {
+            encoded.push('%');
+            encoded.push(HEX[usize::from(b >> 4)] as char);
+            encoded.push(HEX[usize::from(b & 0xF)] as char);
+        }
+    }
+
+    encoded
+}
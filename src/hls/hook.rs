@@ -0,0 +1,138 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    process::{Command, Stdio},
+    sync::mpsc::{self, Sender},
+    thread::Builder as ThreadBuilder,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use log::{debug, error};
+
+use crate::args::{Parse, Parser};
+
+#[derive(Default, Debug, Clone)]
+pub struct Args {
+    command: Option<String>,
+    events: Option<String>,
+}
+
+impl Parse for Args {
+    fn parse(&mut self, parser: &mut Parser) -> Result<()> {
+        parser.parse_opt(&mut self.command, "--ad-hook")?;
+        parser.parse_opt(&mut self.events, "--ad-events")?;
+
+        Ok(())
+    }
+}
+
+enum Event {
+    AdBreakStarted(Option<Duration>), //declared length
+    AdBreakEnded(Option<Duration>),   //actual length
+}
+
+impl Event {
+    const fn name(&self) -> &'static str {
+        match self {
+            Self::AdBreakStarted(_) => "ad_break_started",
+            Self::AdBreakEnded(_) => "ad_break_ended",
+        }
+    }
+
+    const fn duration(&self) -> Option<Duration> {
+        match self {
+            Self::AdBreakStarted(duration) | Self::AdBreakEnded(duration) => *duration,
+        }
+    }
+
+    // {"event":"ad_break_started","channel":"name","duration":30}
+    fn to_json(&self, channel: &str) -> String {
+        format!(
+            r#"{{"event":"{}","channel":"{}","duration":{}}}"#,
+            self.name(),
+            channel.replace('\\', r"\\").replace('"', r#"\""#),
+            self.duration()
+                .map_or_else(|| "null".to_owned(), |d| d.as_secs().to_string()),
+        )
+    }
+}
+
+//Runs the hook command and writes event lines on a separate thread,
+//a slow command or a FIFO without a reader never stalls the stream
+pub struct Hook {
+    sender: Sender<Event>,
+}
+
+impl Hook {
+    pub fn new(args: &Args, channel: &str) -> Result<Option<Self>> {
+        if args.command.is_none() && args.events.is_none() {
+            return Ok(None);
+        }
+
+        let command = args.command.clone();
+        let events = args.events.clone();
+        let channel = channel.to_owned();
+
+        let (sender, receiver) = mpsc::channel::<Event>();
+        ThreadBuilder::new()
+            .name("hook".to_owned())
+            .spawn(move || {
+                for event in receiver {
+                    let json = event.to_json(&channel);
+                    debug!("Hook event: {json}");
+
+                    if let Some(command) = &command
+                        && let Err(e) = run(command, &event, &channel, &json)
+                    {
+                        error!("Failed to run ad hook: {e}");
+                    }
+
+                    //Opened for every event so readers can come and go
+                    if let Some(path) = &events
+                        && let Err(e) = OpenOptions::new()
+                            .append(true)
+                            .create(true)
+                            .open(path)
+                            .and_then(|mut file| writeln!(file, "{json}"))
+                    {
+                        error!("Failed to write ad event: {e}");
+                    }
+                }
+            })
+            .context("Failed to spawn hook thread")?;
+
+        Ok(Some(Self { sender }))
+    }
+
+    pub fn ad_break_started(&self, declared: Option<Duration>) {
+        let _ = self.sender.send(Event::AdBreakStarted(declared));
+    }
+
+    pub fn ad_break_ended(&self, duration: Option<Duration>) {
+        let _ = self.sender.send(Event::AdBreakEnded(duration));
+    }
+}
+
+fn run(command: &str, event: &Event, channel: &str, json: &str) -> Result<()> {
+    let mut args = command.split_whitespace();
+    let mut command = Command::new(args.next().context("Empty ad hook command")?);
+    command
+        .args(args)
+        .env("HLS_EVENT", event.name())
+        .env("HLS_CHANNEL", channel)
+        .env("HLS_EVENT_JSON", json)
+        .stdin(Stdio::null());
+
+    if let Some(duration) = event.duration() {
+        command.env("HLS_DURATION", duration.as_secs().to_string());
+    }
+
+    //Waited for so events stay in order
+    let status = command.status().context("Failed to spawn command")?;
+    if !status.success() {
+        debug!("Ad hook exited with {status}");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use anyhow::{Context, Result};
+
+use super::{map_if_offline, multivariant::extract};
+use crate::{
+    constants,
+    http::{Agent, Method, Url},
+};
+
+pub fn fetch_playlist(channel: &str, agent: &Agent) -> Result<(Url, String)> {
+    let mut request = agent.text();
+    let url = format!("{}/{channel}/livestream", constants::KICK_CHANNELS_ENDPOINT).into();
+
+    request.text(Method::Get, &url).map_err(map_if_offline)?;
+    let response = &request.take();
+
+    request
+        .text(
+            Method::Get,
+            &extract(response, r#""playback_url":""#, r#"","thumbnail""#)
+                .context("Failed to find kick playlist URL")?
+                .replace('\\', "")
+                .into(),
+        )
+        .map_err(map_if_offline)?;
+
+    Ok((url, request.take()))
+}
@@ -0,0 +1,118 @@
+use anyhow::Result;
+use log::debug;
+
+use super::{Args, multivariant::extract};
+use crate::{
+    constants,
+    http::{Agent, Method, Url},
+};
+
+//Owned copy of what's needed to query stream info, so it can be moved to another thread
+pub struct Metadata {
+    gql_url: Url,
+    client_id: String,
+    channel: String,
+}
+
+impl Metadata {
+    //Only live Twitch channels have a title to fetch
+    pub fn new(args: &Args) -> Option<Self> {
+        if args.channel.starts_with("kick:")
+            || args.force_playlist_url.is_some()
+            || args.clip.is_some()
+            || args.playlist_url.is_some()
+        {
+            return None;
+        }
+
+        Some(Self {
+            gql_url: args.gql_url.clone(),
+            client_id: args
+                .client_id
+                .clone()
+                .unwrap_or_else(|| constants::DEFAULT_CLIENT_ID.to_owned()),
+            channel: args.channel.clone(),
+        })
+    }
+
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    pub fn fetch_title(&self, agent: &Agent) -> Result<Option<String>> {
+        let response = self.query(agent, "broadcastSettings{title}")?;
+
+        debug!("GQL title response: {response}");
+        Ok(extract(&response, r#""title":""#, "\"}}")
+            .map(unescape)
+            .filter(|t| !t.is_empty()))
+    }
+
+    //Title and game (category)
+    pub fn fetch_info(&self, agent: &Agent) -> Result<(Option<String>, Option<String>)> {
+        let response = self.query(agent, "broadcastSettings{title game{name}}")?;
+
+        debug!("GQL info response: {response}");
+        Ok((
+            extract(&response, r#""title":""#, r#"","game""#)
+                .map(unescape)
+                .filter(|t| !t.is_empty()),
+            extract(&response, r#""name":""#, "\"}")
+                .map(unescape)
+                .filter(|g| !g.is_empty()),
+        ))
+    }
+
+    fn query(&self, agent: &Agent, fields: &str) -> Result<String> {
+        let body = format!(
+            r#"{{"query":"query{{user(login:\"{}\"){{{fields}}}}}"}}"#,
+            self.channel,
+        );
+
+        let mut request = agent.text();
+        request.text_fmt(
+            Method::Post,
+            &self.gql_url,
+            format_args!(
+                "Content-Type: text/plain;charset=UTF-8\r\n\
+                 Client-ID: {client_id}\r\n\
+                 Content-Length: {content_length}\r\n\
+                 \r\n\
+                 {body}",
+                client_id = self.client_id,
+                content_length = body.len(),
+            ),
+        )?;
+
+        Ok(request.take())
+    }
+}
+
+//Undoes JSON string escapes and drops control characters, which could end the title escape early
+fn unescape(string: &str) -> String {
+    let mut unescaped = String::with_capacity(string.len());
+    let mut chars = string.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('u') => {
+                    let code = chars.by_ref().take(4).collect::<String>();
+                    u32::from_str_radix(&code, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .unwrap_or(char::REPLACEMENT_CHARACTER)
+                }
+                Some('b' | 'f' | 'n' | 'r' | 't') => ' ',
+                Some(c) => c,
+                None => break,
+            },
+            c => c,
+        };
+
+        if !c.is_control() {
+            unescaped.push(c);
+        }
+    }
+
+    unescaped
+}
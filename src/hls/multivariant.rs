@@ -3,33 +3,55 @@ use std::{
     fmt::{self, Display, Formatter},
     ops::{Deref, DerefMut},
     str::{self, Utf8Error},
+    sync::atomic::{AtomicBool, Ordering},
 };
 
-use anyhow::{Context, Result, bail};
+use anyhow::{Context, Result, bail, ensure};
 use getrandom::getrandom;
 use log::{debug, error, info};
 
-use super::{Args, OfflineError, Passthrough, cache::Cache, map_if_offline};
+#[cfg(feature = "kick")]
+use super::kick;
+use super::{
+    AccessPlatform, Args, OfflineError, Passthrough,
+    cache::Cache,
+    clip, map_if_offline,
+    parse::{playlist_iter, session_data_iter},
+};
 
 use crate::{
     constants,
     http::{Agent, Connection, Method, StatusError, Url},
 };
 
+#[allow(clippy::large_enum_variant)] //created once
 pub enum Stream {
-    Variant(Connection),
+    Variant(Connection, Option<Connection>), //and the --record-quality variant
     Passthrough(Url),
+    Clip(Url),
     Exit,
 }
 
 impl Stream {
-    pub fn new(args: &mut Args, agent: &Agent) -> Result<Self> {
-        if let Some(url) = args.force_playlist_url.take() {
+    pub fn new(args: &Args, agent: &Agent) -> Result<Self> {
+        if let Some(url) = &args.force_playlist_url {
             info!("Using forced playlist URL");
-            return Ok(Self::Variant(Connection::new(url, agent.text())));
+            return Ok(Self::Variant(
+                Connection::new(url.clone(), agent.text()),
+                None,
+            ));
+        }
+
+        if let Some(slug) = &args.clip {
+            return Ok(match clip::fetch(args, slug, agent)? {
+                Some(url) if matches!(args.passthrough, Passthrough::Disabled) => Self::Clip(url),
+                Some(url) => Self::Passthrough(url),
+                None => Self::Exit,
+            });
         }
 
-        let cache = Cache::new(&args.playlist_cache_dir, &args.channel, &args.quality);
+        let cache = Cache::new(&args.playlist_cache_dir, &args.channel, &args.quality)
+            .filter(|_| args.playlist_url.is_none());
         if let Some(conn) = cache.as_ref().and_then(|c| c.get(agent)) {
             if args.write_cache_only {
                 info!("Playlist URL is already cached, exiting...");
@@ -37,41 +59,35 @@ impl Stream {
             }
 
             info!("Using cached playlist URL");
-            return Ok(Self::Variant(conn));
+            let recording = args
+                .record_quality
+                .is_some()
+                .then(|| Self::recording(args, agent))
+                .transpose()?;
+
+            return Ok(Self::Variant(conn, recording));
         } else if args.use_cache_only {
             bail!("Playlist URL not found in cache");
         }
 
-        info!("Fetching playlist for channel {}", &args.channel);
-        let (multivariant_url, playlist) =
-            if let Some(channel) = &args.channel.strip_prefix("kick:") {
-                fetch_kick_playlist(channel, agent)?
-            } else if let Some(servers) = &args.servers {
-                fetch_proxy_playlist(
-                    !args.no_low_latency,
-                    servers,
-                    &args.codecs,
-                    &args.channel,
-                    agent,
-                )?
-            } else {
-                let response = fetch_twitch_gql(
-                    args.client_id.take(),
-                    args.auth_token.take(),
-                    &args.channel,
-                    agent,
-                )?;
-
-                fetch_twitch_playlist(
-                    &response,
-                    !args.no_low_latency,
-                    &args.codecs,
-                    &args.channel,
-                    agent,
-                )?
-            };
+        match &args.playlist_url {
+            Some(url) => info!("Fetching playlist {url}"),
+            None => info!("Fetching playlist for channel {}", &args.channel),
+        }
 
-        let Some(url) = choose_stream(&playlist, &args.quality, args.print_streams) else {
+        let (multivariant_url, playlist) = fetch_multivariant_playlist(args, agent)?;
+        let url = if is_media_playlist(&playlist) {
+            info!("Playlist has no variants, ignoring quality");
+            multivariant_url.clone()
+        } else if let Some(url) = choose_stream(
+            &playlist,
+            &multivariant_url,
+            &args.quality,
+            args.max_height,
+            args.print_streams,
+        ) {
+            url
+        } else {
             print_streams(&playlist);
             return Ok(Self::Exit);
         };
@@ -85,76 +101,281 @@ impl Stream {
             }
         }
 
+        //Chosen from the same multivariant playlist so both share the access token
+        let recording = args
+            .record_quality
+            .is_some()
+            .then(|| {
+                choose_variant(
+                    args,
+                    &args.record_quality,
+                    multivariant_url.clone(),
+                    &playlist,
+                    agent,
+                )
+            })
+            .transpose()?;
+
         match args.passthrough {
-            Passthrough::Disabled => Ok(Self::Variant(Connection::new(url, agent.text()))),
+            Passthrough::Disabled => {
+                Ok(Self::Variant(Connection::new(url, agent.text()), recording))
+            }
             Passthrough::Variant => Ok(Self::Passthrough(url)),
             Passthrough::Multivariant => Ok(Self::Passthrough(multivariant_url)),
         }
     }
+
+    //Skips the cache, the cached URL is likely the one that stopped working
+    pub fn refresh(args: &Args, agent: &Agent) -> Result<Connection> {
+        if let Some(url) = &args.force_playlist_url {
+            return Ok(Connection::new(url.clone(), agent.text()));
+        }
+
+        fetch_variant(args, &args.quality, agent)
+    }
+
+    pub fn audio(args: &Args, agent: &Agent) -> Result<Connection> {
+        ensure!(
+            args.force_playlist_url.is_none(),
+            "Audio recording requires fetching the multivariant playlist"
+        );
+
+        info!("Fetching audio only playlist for channel {}", &args.channel);
+        fetch_variant(args, &Some("audio_only".to_owned()), agent)
+    }
+
+    pub fn recording(args: &Args, agent: &Agent) -> Result<Connection> {
+        info!("Fetching recording playlist for channel {}", &args.channel);
+        fetch_variant(args, &args.record_quality, agent)
+    }
+
+    //Same variant through the playlist proxies, for ad breaks with --proxy-on-ads
+    pub fn proxy(args: &Args, agent: &Agent) -> Result<Connection> {
+        let servers = args
+            .proxy_servers()
+            .context("Playlist proxy disabled for this channel")?;
+
+        ensure!(
+            args.playlist_url.is_none() && !args.channel.starts_with("kick:"),
+            "Playlist proxies only support Twitch channels"
+        );
+
+        let (multivariant_url, playlist) = fetch_proxy_playlist(
+            !args.no_low_latency,
+            servers,
+            args.proxy_auth.as_deref(),
+            codecs(args, agent),
+            args.access_platform,
+            &args.channel,
+            agent,
+        )?;
+
+        choose_variant(args, &args.quality, multivariant_url, &playlist, agent)
+    }
+}
+
+fn fetch_variant(args: &Args, quality: &Option<String>, agent: &Agent) -> Result<Connection> {
+    let (multivariant_url, playlist) = fetch_multivariant_playlist(args, agent)?;
+    choose_variant(args, quality, multivariant_url, &playlist, agent)
+}
+
+fn choose_variant(
+    args: &Args,
+    quality: &Option<String>,
+    multivariant_url: Url,
+    playlist: &str,
+    agent: &Agent,
+) -> Result<Connection> {
+    if is_media_playlist(playlist) {
+        return Ok(Connection::new(multivariant_url, agent.text()));
+    }
+
+    let url = choose_stream(playlist, &multivariant_url, quality, args.max_height, false)
+        .context("Failed to find stream in multivariant playlist")?;
+
+    Ok(Connection::new(url, agent.text()))
+}
+
+//Where the channel is resolved to a multivariant playlist, everything after that is shared
+enum Platform<'a> {
+    Twitch(&'a str),
+    #[cfg(feature = "kick")]
+    Kick(&'a str),
+}
+
+impl<'a> Platform<'a> {
+    fn new(channel: &'a str) -> Result<Self> {
+        if let Some(channel) = channel.strip_prefix("kick:") {
+            #[cfg(feature = "kick")]
+            return Ok(Self::Kick(channel));
+
+            #[cfg(not(feature = "kick"))]
+            bail!("Kick support is not enabled in this build (channel: {channel})");
+        }
+
+        Ok(Self::Twitch(channel))
+    }
+}
+
+fn fetch_multivariant_playlist(args: &Args, agent: &Agent) -> Result<(Url, String)> {
+    //Any other HLS source, there's no token to request
+    if let Some(url) = &args.playlist_url {
+        let mut request = agent.text();
+        request.text(Method::Get, url).map_err(map_if_offline)?;
+
+        //Variant URLs are relative to where the playlist actually is
+        let url = request.redirected().unwrap_or(url).clone();
+        return Ok((url, request.take()));
+    }
+
+    match Platform::new(&args.channel)? {
+        Platform::Twitch(channel) => fetch_twitch_multivariant(args, channel, agent),
+        #[cfg(feature = "kick")]
+        Platform::Kick(channel) => kick::fetch_playlist(channel, agent),
+    }
+}
+
+fn fetch_twitch_multivariant(args: &Args, channel: &str, agent: &Agent) -> Result<(Url, String)> {
+    if let Some(servers) = args.servers() {
+        return Ok(fetch_proxy_playlist(
+            !args.no_low_latency,
+            servers,
+            args.proxy_auth.as_deref(),
+            codecs(args, agent),
+            args.access_platform,
+            channel,
+            agent,
+        )?);
+    }
+
+    let response = fetch_twitch_gql(
+        &args.gql_url,
+        args.client_id.clone(),
+        &args.auth_token,
+        args.access_platform,
+        channel,
+        agent,
+    )?;
+
+    if args.auth_token.is_some() {
+        log_entitlements(&response);
+    }
+
+    fetch_twitch_playlist(
+        &response,
+        !args.no_low_latency,
+        codecs(args, agent),
+        args.access_platform,
+        channel,
+        agent,
+    )
 }
 
+//Set once the persisted query hash gets rejected, Twitch rotates it every now and then
+static FULL_GQL_QUERY: AtomicBool = AtomicBool::new(false);
+
 fn fetch_twitch_gql(
+    gql_url: &Url,
     client_id: Option<String>,
-    auth_token: Option<String>,
+    auth_token: &Option<String>,
+    platform: AccessPlatform,
     channel: &str,
     agent: &Agent,
 ) -> Result<String> {
-    const GQL_LEN_WITHOUT_CHANNEL: usize = 267;
-
     let mut client_id_buf = ArrayString::<30>::new();
-    let client_id = choose_client_id(&mut client_id_buf, client_id, &auth_token, agent)?;
+    let client_id = choose_client_id(&mut client_id_buf, client_id, auth_token, agent)?;
 
     let mut request = agent.text();
-    request.text_fmt(
-        Method::Post,
-        &constants::TWITCH_GQL_ENDPOINT.into(),
-        format_args!(
-            "Content-Type: text/plain;charset=UTF-8\r\n\
-             X-Device-ID: {device_id}\r\n\
-             Client-ID: {client_id}\r\n\
-             {auth_token_head}{auth_token}{auth_token_tail}\
-             Content-Length: {content_length}\r\n\
-             \r\n\
-             {{\
-                \"extensions\":{{\
-                    \"persistedQuery\":{{\
-                        \"sha256Hash\":\"ed230aa1e33e07eebb8928504583da78a5173989fadfb1ac94be06a04f3cdbe9\",\
-                        \"version\":1\
-                    }}\
-                }},\
-                \"operationName\":\"PlaybackAccessToken\",\
-                \"variables\":{{\
-                    \"isLive\":true,\
-                    \"isVod\":false,\
-                    \"login\":\"{channel}\",\
-                    \"playerType\":\"site\",\
-                    \"platform\":\"site\",\
-                    \"vodID\":\"\"\
-                }}\
-             }}",
-             device_id = ArrayString::<32>::random()?,
-             content_length = GQL_LEN_WITHOUT_CHANNEL + channel.len(),
-             auth_token_head = if auth_token.is_some() { "Authorization: OAuth " } else { "" },
-             auth_token_tail = if auth_token.is_some() { "\r\n" } else { "" },
-             auth_token = auth_token.unwrap_or_default(),
-        )
-    )?;
+    loop {
+        let full_query = FULL_GQL_QUERY.load(Ordering::Relaxed);
+        let body = playback_access_token_body(channel, platform, auth_token.is_some(), full_query);
+        request.text_fmt(
+            Method::Post,
+            gql_url,
+            format_args!(
+                "Content-Type: text/plain;charset=UTF-8\r\n\
+                 X-Device-ID: {device_id}\r\n\
+                 Client-ID: {client_id}\r\n\
+                 {auth_token_head}{auth_token}{auth_token_tail}\
+                 Content-Length: {content_length}\r\n\
+                 \r\n\
+                 {body}",
+                device_id = ArrayString::<32>::random()?,
+                content_length = body.len(),
+                auth_token_head = if auth_token.is_some() {
+                    "Authorization: OAuth "
+                } else {
+                    ""
+                },
+                auth_token_tail = if auth_token.is_some() { "\r\n" } else { "" },
+                auth_token = auth_token.as_deref().unwrap_or_default(),
+            ),
+        )?;
+
+        let mut response = request.take();
+        if !full_query && response.contains("PersistedQueryNotFound") {
+            error!("GQL rejected the persisted query hash, falling back to the full query...");
+            FULL_GQL_QUERY.store(true, Ordering::Relaxed);
+            continue;
+        }
+
+        response.retain(|c| c != '\\');
 
-    let mut response = request.take();
-    response.retain(|c| c != '\\');
+        debug!("GQL response: {response}");
+        if response.contains(r#"streamPlaybackAccessToken":null"#) {
+            return Err(OfflineError.into());
+        }
 
-    debug!("GQL response: {response}");
-    if response.contains(r#"streamPlaybackAccessToken":null"#) {
-        return Err(OfflineError.into());
+        return Ok(response);
     }
+}
 
-    Ok(response)
+//The token value lists what the OAuth token's account is entitled to on this channel
+fn log_entitlements(response: &str) {
+    if response.contains(r#""show_ads":false"#) || response.contains(r#""hide_ads":true"#) {
+        let reason = if response.contains(r#""turbo":true"#) {
+            "Turbo"
+        } else if response.contains(r#""subscriber":true"#) {
+            "subscribed"
+        } else {
+            "entitled"
+        };
+
+        info!("Ad-free playback active ({reason})");
+    } else if response.contains(r#""show_ads":true"#) {
+        info!("OAuth token isn't entitled to ad-free playback on this channel");
+    }
+}
+
+fn playback_access_token_body(
+    channel: &str,
+    platform: AccessPlatform,
+    authenticated: bool,
+    full_query: bool,
+) -> String {
+    const PERSISTED_QUERY: &str = r#""extensions":{"persistedQuery":{"sha256Hash":"ed230aa1e33e07eebb8928504583da78a5173989fadfb1ac94be06a04f3cdbe9","version":1}}"#;
+    const FULL_QUERY: &str = concat!(
+        r#""query":"query PlaybackAccessToken($login:String!,$isLive:Boolean!,$vodID:ID!,$isVod:Boolean!,$playerType:String!,$platform:String!){"#,
+        r#"streamPlaybackAccessToken(channelName:$login,params:{platform:$platform,playerBackend:\"mediaplayer\",playerType:$playerType})@include(if:$isLive){value signature authorization{isForbidden forbiddenReasonCode}__typename}"#,
+        r#"videoPlaybackAccessToken(id:$vodID,params:{platform:$platform,playerBackend:\"mediaplayer\",playerType:$playerType})@include(if:$isVod){value signature __typename}}""#,
+    );
+
+    let (player_type, platform) = platform.gql(authenticated);
+    format!(
+        r#"{{{query},"operationName":"PlaybackAccessToken","variables":{{"isLive":true,"isVod":false,"login":"{channel}","playerType":"{player_type}","platform":"{platform}","vodID":""}}}}"#,
+        query = if full_query {
+            FULL_QUERY
+        } else {
+            PERSISTED_QUERY
+        },
+    )
 }
 
 fn fetch_twitch_playlist(
     gql_response: &str,
     low_latency: bool,
     codecs: &str,
+    platform: AccessPlatform,
     channel: &str,
     agent: &Agent,
 ) -> Result<(Url, String)> {
@@ -178,7 +399,7 @@ fn fetch_twitch_playlist(
         &token={token}\
         &player_version={player_version}\
         &warp={low_latency}\
-        &platform=web",
+        &platform={platform}",
         base_url = constants::TWITCH_HLS_BASE,
         p = {
             let mut buf = [0u8; 4];
@@ -198,11 +419,15 @@ fn fetch_twitch_playlist(
             &gql_response[start..end]
         },
         player_version = constants::PLAYER_VERSION,
+        platform = platform.usher(),
     )
     .into();
 
     let mut request = agent.text();
     request.text(Method::Get, &url).map_err(map_if_offline)?;
+    if let Some(cluster) = request.header("x-tv-twitch-cluster") {
+        debug!("Usher cluster: {cluster}");
+    }
 
     Ok((url, request.take()))
 }
@@ -210,7 +435,9 @@ fn fetch_twitch_playlist(
 fn fetch_proxy_playlist(
     low_latency: bool,
     servers: &[Url],
+    proxy_auth: Option<&str>,
     codecs: &str,
+    platform: AccessPlatform,
     channel: &str,
     agent: &Agent,
 ) -> Result<(Url, String), OfflineError> {
@@ -228,12 +455,22 @@ fn fetch_proxy_playlist(
             &fast_bread={low_latency}\
             &warp={low_latency}\
             &supported_codecs={codecs}\
-            &platform=web",
+            &platform={platform}",
             &server.replace("[channel]", channel),
+            platform = platform.usher(),
         )
         .into();
 
-        match request.text_no_retry(Method::Get, &url) {
+        let result = match proxy_auth {
+            Some(auth) => request.text_fmt_no_retry(
+                Method::Get,
+                &url,
+                format_args!("Proxy-Authorization: {auth}\r\n\r\n"),
+            ),
+            None => request.text_no_retry(Method::Get, &url),
+        };
+
+        match result {
             Ok(()) => {
                 let playlist = request.take();
                 if playlist.is_empty() {
@@ -250,101 +487,90 @@ fn fetch_proxy_playlist(
     Err(OfflineError)
 }
 
-fn fetch_kick_playlist(channel: &str, agent: &Agent) -> Result<(Url, String)> {
-    let mut request = agent.text();
-    let url = format!("{}/{channel}/livestream", constants::KICK_CHANNELS_ENDPOINT).into();
-
-    request.text(Method::Get, &url).map_err(map_if_offline)?;
-    let response = &request.take();
-
-    request
-        .text(
-            Method::Get,
-            &extract(response, r#""playback_url":""#, r#"","thumbnail""#)
-                .context("Failed to find kick playlist URL")?
-                .replace('\\', "")
-                .into(),
-        )
-        .map_err(map_if_offline)?;
+fn choose_stream(
+    playlist: &str,
+    base: &Url,
+    quality: &Option<String>,
+    max_height: Option<u16>,
+    should_print: bool,
+) -> Option<Url> {
+    debug!("Multivariant playlist:\n{playlist}");
+    for data in session_data_iter(playlist) {
+        debug!("Session data: {}={}", data.id, data.value);
+    }
 
-    Ok((url, request.take()))
-}
+    let (Some(quality), false) = (quality, should_print) else {
+        return None;
+    };
 
-#[derive(PartialEq, Eq)]
-struct PlaylistItem<'a> {
-    name: &'a str,
-    url: &'a str,
-    resolution: Option<(u16, u16)>,
-}
+    let mut iter = playlist_iter(playlist)
+        .filter(|it| max_height.is_none_or(|max| it.height().is_none_or(|height| height <= max)));
 
-impl<'a> PlaylistItem<'a> {
-    pub fn parse(media: &'a str, stream_inf: &'a str, url: &'a str) -> Option<Self> {
-        // #EXT-X-MEDIA:TYPE=VIDEO,GROUP-ID="720p30",NAME="720p",AUTOSELECT=YES,DEFAULT=YES
-        let name = media
-            .split_once("NAME=\"")
-            .map(|s| s.1.split('"'))
-            .and_then(|mut s| s.next())
-            .map(|s| s.strip_suffix(" (source)").unwrap_or(s))?;
-        // #EXT-X-STREAM-INF:BANDWIDTH=2373000,RESOLUTION=1280x720,CODECS="avc1.4D401F,mp4a.40.2",VIDEO="720p30",FRAME-RATE=30.000
-        let resolution = stream_inf
-            .split_once("RESOLUTION=")
-            .and_then(|(_, tail)| tail.split_once(','))
-            .and_then(|(head, _)| head.split_once('x'))
-            .and_then(|(width, height)| {
-                if let (Ok(width), Ok(height)) = (width.parse(), height.parse()) {
-                    Some((width, height))
-                } else {
-                    None
-                }
-            });
+    match quality.as_str() {
+        "best" => return iter.max().map(|it| base.join(it.url)),
+        //Lowest video variant, audio only has no resolution
+        "worst" => {
+            return iter
+                .min_by(|a, b| (a.resolution.is_none(), a).cmp(&(b.resolution.is_none(), b)))
+                .map(|it| base.join(it.url));
+        }
+        "source" | "chunked" => return iter.find(|it| it.is_source).map(|it| base.join(it.url)),
+        _ => (),
+    }
 
-        Some(Self {
-            name,
-            url,
-            resolution,
-        })
+    //WIDTHxHEIGHT
+    if let Some(resolution) = quality
+        .split_once('x')
+        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+    {
+        return iter
+            .find(|it| it.resolution == Some(resolution))
+            .map(|it| base.join(it.url));
     }
-}
 
-impl PartialOrd for PlaylistItem<'_> {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
+    //<=HEIGHTp or <=HEIGHTpFPS, best variant that isn't above it
+    if let Some((height, frame_rate)) = quality.strip_prefix("<=").and_then(parse_height) {
+        return iter
+            .filter(|it| {
+                it.height().is_some_and(|h| h <= height)
+                    && frame_rate.is_none_or(|max| it.frame_rate.is_none_or(|f| f <= max))
+            })
+            .max()
+            .map(|it| base.join(it.url));
     }
-}
 
-impl Ord for PlaylistItem<'_> {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.resolution
-            .unwrap_or_default()
-            .cmp(&other.resolution.unwrap_or_default())
+    let items = iter.collect::<Vec<_>>();
+    if let Some(it) = items.iter().find(|it| it.name == *quality) {
+        return Some(base.join(it.url));
     }
-}
 
-fn playlist_iter(playlist: &str) -> impl Iterator<Item = PlaylistItem<'_>> {
-    playlist
-        .lines()
-        .filter(|l| l.starts_with("#EXT-X-MEDIA"))
-        .zip(playlist.lines().filter(|l| l.starts_with("http")))
-        .zip(
-            playlist
-                .lines()
-                .filter(|l| l.starts_with("#EXT-X-STREAM-INF")),
-        )
-        .filter_map(|((media, url), stream_inf)| PlaylistItem::parse(media, stream_inf, url))
+    //Names don't always include the frame rate, so 1080p also matches a 1080p60 variant
+    let (height, frame_rate) = parse_height(quality)?;
+    items
+        .into_iter()
+        .filter(|it| {
+            it.height() == Some(height) && frame_rate.is_none_or(|f| it.frame_rate == Some(f))
+        })
+        .max()
+        .map(|it| base.join(it.url))
 }
 
-fn choose_stream(playlist: &str, quality: &Option<String>, should_print: bool) -> Option<Url> {
-    debug!("Multivariant playlist:\n{playlist}");
-    let (Some(quality), false) = (quality, should_print) else {
-        return None;
-    };
-
-    let mut iter = playlist_iter(playlist);
-    if quality == "best" {
-        return iter.max().map(|it| it.url.into());
-    }
+//HEIGHTp or HEIGHTpFPS
+fn parse_height(quality: &str) -> Option<(u16, Option<u16>)> {
+    let (height, frame_rate) = quality.split_once('p')?;
+    Some((
+        height.parse().ok()?,
+        if frame_rate.is_empty() {
+            None
+        } else {
+            Some(frame_rate.parse().ok()?)
+        },
+    ))
+}
 
-    iter.find(|it| it.name == quality).map(|it| it.url.into())
+//Media playlists from other HLS sources can be used directly
+fn is_media_playlist(playlist: &str) -> bool {
+    !playlist.contains("#EXT-X-STREAM-INF") && playlist.contains("#EXTINF")
 }
 
 fn print_streams(playlist: &str) {
@@ -365,6 +591,21 @@ fn print_streams(playlist: &str) {
         }
     }
     println!();
+
+    let mut session_data = session_data_iter(playlist).peekable();
+    if session_data.peek().is_some() {
+        println!("Session data:");
+        for data in session_data {
+            println!("    {}: {}", data.id, data.value);
+        }
+    }
+}
+
+//Same as the browser the headers are from unless overridden
+fn codecs<'a>(args: &'a Args, agent: &Agent) -> &'a str {
+    args.codecs
+        .as_deref()
+        .unwrap_or_else(|| agent.header_profile().codecs())
 }
 
 fn choose_client_id<'a>(
@@ -398,7 +639,7 @@ fn choose_client_id<'a>(
     }
 }
 
-fn extract<'a>(data: &'a str, start: &'a str, end: &'a str) -> Option<&'a str> {
+pub(super) fn extract<'a>(data: &'a str, start: &'a str, end: &'a str) -> Option<&'a str> {
     let start = data.find(start)? + start.len();
     let end = data.find(end)?;
 
@@ -0,0 +1,598 @@
+//Playlist parsing without any network or file access
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    io::{self, Write},
+    mem,
+    str::{self, FromStr},
+    time::{self, SystemTime},
+};
+
+use anyhow::{Context, Result};
+
+use crate::http::Url;
+
+//Media playlist parser, fed line by line while the playlist is being downloaded.
+//New segments are kept here until the whole playlist is in, the previous state stays untouched.
+#[derive(Default)]
+pub struct MediaParser {
+    base: Url, //for relative segment URLs
+    line: Vec<u8>,
+    pub raw: Option<String>, //whole playlist, only kept for debug logging and dumps
+
+    known_sequence: usize,
+    known_segments: usize,
+
+    pub sequence: Option<usize>,
+    pub prev_segment_count: usize,
+    pub total_segments: usize,
+    pub segments: Vec<Segment>,
+    next_duration: Option<Duration>, //segment URL is on the line after #EXTINF
+    pub header: Option<Url>,
+    pub program_time: Option<SystemTime>,
+    pub can_skip: bool,
+    pub is_incomplete: bool,
+    pub is_ended: bool,
+
+    segment_time: Option<SystemTime>, //program time of the next segment
+    elapsed: Option<time::Duration>,  //stream uptime at the first segment
+    pub stream_start: Option<SystemTime>,
+    ad_ranges: Vec<AdRange>, //kept between reloads, delta updates skip old date ranges
+}
+
+//Ad break declared by #EXT-X-DATERANGE, segments inside it are ads
+struct AdRange {
+    id: String,
+    start: SystemTime,
+    end: Option<SystemTime>, //open until a later date range with the same ID closes it
+}
+
+impl AdRange {
+    fn contains(&self, time: SystemTime) -> bool {
+        time >= self.start && self.end.is_none_or(|end| time < end)
+    }
+
+    fn duration(&self) -> Option<time::Duration> {
+        self.end?.duration_since(self.start).ok()
+    }
+}
+
+impl Write for MediaParser {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        unreachable!();
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_all(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while let Some(pos) = buf.iter().position(|&b| b == b'\n') {
+            self.line.extend_from_slice(&buf[..pos]);
+            buf = &buf[pos + 1..];
+
+            let line = mem::take(&mut self.line);
+            self.parse_line(&line)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            self.line = line;
+            self.line.clear();
+        }
+
+        self.line.extend_from_slice(buf);
+        Ok(())
+    }
+}
+
+impl MediaParser {
+    pub fn new(base: Url) -> Self {
+        Self {
+            base,
+            ..Self::default()
+        }
+    }
+
+    pub fn set_base(&mut self, base: Url) {
+        self.base = base;
+    }
+
+    pub fn begin(&mut self, known_sequence: usize, known_segments: usize, keep_raw: bool) {
+        self.known_sequence = known_sequence;
+        self.known_segments = known_segments;
+        self.raw = keep_raw.then(String::new);
+        self.reset();
+    }
+
+    //Last line may not end with a newline
+    pub fn finish(&mut self) -> Result<()> {
+        let line = mem::take(&mut self.line);
+        self.parse_line(&line)?;
+        self.line = line;
+        self.line.clear();
+
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.line.clear();
+        if let Some(raw) = &mut self.raw {
+            raw.clear();
+        }
+
+        self.sequence = None;
+        self.prev_segment_count = self.known_segments;
+        self.total_segments = 0;
+        self.segments.clear();
+        self.next_duration = None;
+        self.header = None;
+        self.program_time = None;
+        self.can_skip = false;
+        self.is_incomplete = false;
+        self.is_ended = false;
+        self.segment_time = None;
+        self.elapsed = None;
+    }
+
+    fn parse_line(&mut self, line: &[u8]) -> Result<()> {
+        let line = str::from_utf8(line).context("Playlist wasn't valid utf-8")?;
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        //Start of a new body, either the first or one from a retried request
+        if line == "#EXTM3U" {
+            self.reset();
+        }
+
+        if let Some(raw) = &mut self.raw {
+            raw.push_str(line);
+            raw.push('\n');
+        }
+
+        if let Some(duration) = self.next_duration.take() {
+            self.segments
+                .push(Segment::Normal(duration, self.base.join(line)));
+            return Ok(());
+        }
+
+        if self.is_incomplete {
+            return Ok(());
+        }
+
+        if line.trim() == "#EXT-X-ENDLIST" {
+            self.is_ended = true;
+            return Ok(());
+        }
+
+        let Some((tag, value)) = line.split_once(':') else {
+            return Ok(());
+        };
+
+        match tag {
+            "#EXT-X-MEDIA-SEQUENCE" => {
+                let sequence: usize = value.parse()?;
+                if sequence > 0 {
+                    let removed = sequence.saturating_sub(self.known_sequence);
+                    self.prev_segment_count = self.known_segments.saturating_sub(removed);
+                }
+
+                self.sequence = Some(sequence);
+            }
+            "#EXT-X-MAP" if self.header.is_none() => {
+                self.header = Some(
+                    self.base.join(
+                        value
+                            .split_once('=')
+                            .context("Failed to parse segment header")?
+                            .1
+                            .trim_matches('"'),
+                    ),
+                );
+            }
+            "#EXT-X-SERVER-CONTROL" => {
+                self.can_skip = value.contains("CAN-SKIP-UNTIL=");
+            }
+            "#EXT-X-SKIP" => {
+                let skipped = parse_skipped(value)?;
+                if skipped > self.prev_segment_count {
+                    self.is_incomplete = true;
+                    return Ok(());
+                }
+
+                self.total_segments += skipped;
+                self.segment_time = None;
+            }
+            "#EXT-X-PROGRAM-DATE-TIME" => {
+                self.program_time = parse_date_time(value);
+                self.segment_time = self.program_time;
+            }
+            "#EXT-X-DATERANGE" => self.parse_date_range(value),
+            "#EXT-X-TWITCH-ELAPSED-SECS" => self.elapsed = parse_secs(value),
+            "#EXTINF" => {
+                if let (Some(elapsed), Some(time)) = (self.elapsed.take(), self.segment_time) {
+                    self.stream_start = time.checked_sub(elapsed);
+                }
+
+                let mut duration: Duration = value.parse()?;
+                if let Some(time) = self.segment_time
+                    && let Some(range) = self.ad_ranges.iter().find(|r| r.contains(time))
+                {
+                    duration.is_ad = true;
+                    duration.ad_break = range.duration();
+                    duration.ad_remaining = range.end.and_then(|end| end.duration_since(time).ok());
+                }
+
+                //Segments without their own program time follow the previous one
+                self.segment_time = self.segment_time.map(|t| t + duration.inner);
+
+                self.total_segments += 1;
+                if self.total_segments > self.prev_segment_count {
+                    self.next_duration = Some(duration);
+                }
+            }
+            "#EXT-X-TWITCH-PREFETCH" | "#EXT-X-PREFETCH" => {
+                self.total_segments += 1;
+                if self.total_segments > self.prev_segment_count {
+                    self.segments.push(Segment::Prefetch(self.base.join(value)));
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    // #EXT-X-DATERANGE:ID="stitched-ad-123",CLASS="twitch-stitched-ad",START-DATE="2024-01-01T00:00:00.000Z",DURATION=30.000
+    fn parse_date_range(&mut self, value: &str) {
+        const MAX_AD_RANGES: usize = 16;
+
+        let (mut id, mut start, mut end) = (None, None, None);
+        let (mut duration, mut planned_duration) = (None, None);
+        let mut is_ad = false;
+        for (key, value) in attributes(value) {
+            match key {
+                "ID" => id = Some(value),
+                "CLASS" => is_ad |= value == "twitch-stitched-ad",
+                "START-DATE" => start = parse_date_time(value),
+                "END-DATE" => end = parse_date_time(value),
+                "DURATION" => duration = parse_secs(value),
+                "PLANNED-DURATION" => planned_duration = parse_secs(value),
+                "SCTE35-OUT" | "SCTE35-CMD" => is_ad = true,
+                _ => (),
+            }
+        }
+
+        let Some(id) = id else {
+            return;
+        };
+
+        let end = end.or_else(|| Some(start? + duration.or(planned_duration)?));
+        if let Some(range) = self.ad_ranges.iter_mut().find(|r| r.id == id) {
+            //Same ID again, possibly with SCTE35-IN and the actual end
+            if end.is_some() {
+                range.end = end;
+            }
+
+            return;
+        }
+
+        let (true, Some(start)) = (is_ad, start) else {
+            return;
+        };
+
+        if self.ad_ranges.len() >= MAX_AD_RANGES {
+            self.ad_ranges.remove(0);
+        }
+
+        self.ad_ranges.push(AdRange {
+            id: id.to_owned(),
+            start,
+            end,
+        });
+    }
+}
+
+#[derive(PartialEq, Eq)]
+pub struct PlaylistItem<'a> {
+    pub name: Cow<'a, str>,
+    pub url: &'a str,
+    pub resolution: Option<(u16, u16)>,
+    pub frame_rate: Option<u16>,
+    pub bandwidth: Option<u64>,
+    pub codecs: Option<&'a str>,
+    pub is_source: bool,
+}
+
+impl<'a> PlaylistItem<'a> {
+    pub fn parse(media: Option<&'a str>, stream_inf: &'a str, url: &'a str) -> Option<Self> {
+        let mut item = Self {
+            name: Cow::default(),
+            url,
+            resolution: None,
+            frame_rate: None,
+            bandwidth: None,
+            codecs: None,
+            is_source: false,
+        };
+
+        // #EXT-X-STREAM-INF:BANDWIDTH=2373000,RESOLUTION=1280x720,CODECS="avc1.4D401F,mp4a.40.2",VIDEO="720p30",FRAME-RATE=30.000
+        for (key, value) in attributes(stream_inf.split_once(':')?.1) {
+            match key {
+                "RESOLUTION" => {
+                    item.resolution = value
+                        .split_once('x')
+                        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)));
+                }
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                "FRAME-RATE" => {
+                    item.frame_rate = value.parse::<f32>().ok().map(|f| f.round() as u16);
+                }
+                "BANDWIDTH" => item.bandwidth = value.parse().ok(),
+                "CODECS" => item.codecs = Some(value),
+                _ => (),
+            }
+        }
+
+        // #EXT-X-MEDIA:TYPE=VIDEO,GROUP-ID="720p30",NAME="720p",AUTOSELECT=YES,DEFAULT=YES
+        if let Some(media) = media {
+            let mut is_chunked = false;
+            for (key, value) in attributes(media.split_once(':')?.1) {
+                match key {
+                    "NAME" => {
+                        let name = value.strip_suffix(" (source)");
+                        item.is_source |= name.is_some();
+                        item.name = name.unwrap_or(value).into();
+                    }
+                    "GROUP-ID" => is_chunked = value == "chunked",
+                    _ => (),
+                }
+            }
+
+            item.is_source |= is_chunked;
+        }
+
+        //Other HLS sources don't name their variants
+        if item.name.is_empty() {
+            item.name = match (item.resolution, item.frame_rate, item.bandwidth) {
+                (Some((_, height)), Some(frame_rate), _) if frame_rate > 30 => {
+                    format!("{height}p{frame_rate}").into()
+                }
+                (Some((_, height)), _, _) => format!("{height}p").into(),
+                (None, _, Some(bandwidth)) => format!("{}k", bandwidth / 1000).into(),
+                (None, _, None) => return None,
+            };
+        }
+
+        Some(item)
+    }
+
+    pub const fn height(&self) -> Option<u16> {
+        match self.resolution {
+            Some((_, height)) => Some(height),
+            None => None,
+        }
+    }
+}
+
+//Higher resolution first, then frame rate and bandwidth for variants with the same resolution
+impl PartialOrd for PlaylistItem<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PlaylistItem<'_> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (
+            self.resolution.unwrap_or_default(),
+            self.frame_rate.unwrap_or_default(),
+            self.bandwidth.unwrap_or_default(),
+        )
+            .cmp(&(
+                other.resolution.unwrap_or_default(),
+                other.frame_rate.unwrap_or_default(),
+                other.bandwidth.unwrap_or_default(),
+            ))
+    }
+}
+
+pub struct SessionData<'a> {
+    pub id: &'a str,
+    pub value: &'a str,
+}
+
+impl<'a> SessionData<'a> {
+    fn parse(line: &'a str) -> Option<Self> {
+        // #EXT-X-SESSION-DATA:DATA-ID="NODE",VALUE="video-edge-123abc.sea01"
+        let mut id = None;
+        let mut value = None;
+        for (key, val) in attributes(line) {
+            match key {
+                "DATA-ID" => id = Some(val),
+                "VALUE" => value = Some(val),
+                _ => (),
+            }
+        }
+
+        Some(Self {
+            id: id?,
+            value: value.unwrap_or_default(),
+        })
+    }
+}
+
+//KEY=VALUE,KEY="QUOTED,VALUE", quotes are stripped from the values
+pub fn attributes(list: &str) -> impl Iterator<Item = (&str, &str)> {
+    let mut rest = list.trim();
+    std::iter::from_fn(move || {
+        let (key, tail) = rest.split_once('=')?;
+        let (value, tail) = tail.strip_prefix('"').map_or_else(
+            || tail.split_once(',').unwrap_or((tail, "")),
+            |quoted| {
+                let (value, tail) = quoted.split_once('"').unwrap_or((quoted, ""));
+                (value, tail.strip_prefix(',').unwrap_or(tail))
+            },
+        );
+
+        rest = tail;
+        Some((key.trim(), value))
+    })
+}
+
+pub fn session_data_iter(playlist: &str) -> impl Iterator<Item = SessionData<'_>> {
+    playlist
+        .lines()
+        .filter_map(|l| l.strip_prefix("#EXT-X-SESSION-DATA:"))
+        .filter_map(SessionData::parse)
+}
+
+pub fn playlist_iter(playlist: &str) -> impl Iterator<Item = PlaylistItem<'_>> {
+    //Renditions aren't necessarily listed right before the variants that use them
+    let media = playlist
+        .lines()
+        .filter(|l| l.starts_with("#EXT-X-MEDIA:"))
+        .collect::<Vec<_>>();
+
+    let mut stream_inf = None;
+    playlist.lines().filter_map(move |line| {
+        if line.starts_with("#EXT-X-STREAM-INF") {
+            stream_inf = Some(line);
+        } else if !line.starts_with('#') && !line.trim().is_empty() {
+            let stream_inf = stream_inf.take()?;
+            return PlaylistItem::parse(find_media(&media, stream_inf), stream_inf, line);
+        }
+
+        None
+    })
+}
+
+//Rendition in the variant's VIDEO group, or its AUDIO group if it's audio only
+fn find_media<'a>(media: &[&'a str], stream_inf: &str) -> Option<&'a str> {
+    let (kind, group) = match (
+        tag_attribute(stream_inf, "VIDEO"),
+        tag_attribute(stream_inf, "AUDIO"),
+    ) {
+        (Some(group), _) => ("VIDEO", group),
+        (None, Some(group)) if tag_attribute(stream_inf, "RESOLUTION").is_none() => {
+            ("AUDIO", group)
+        }
+        _ => return None,
+    };
+
+    let mut renditions = media.iter().copied().filter(|line| {
+        tag_attribute(line, "TYPE") == Some(kind) && tag_attribute(line, "GROUP-ID") == Some(group)
+    });
+
+    //Groups with several renditions are named after the default one
+    renditions
+        .clone()
+        .find(|line| tag_attribute(line, "DEFAULT") == Some("YES"))
+        .or_else(|| renditions.next())
+}
+
+fn tag_attribute<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    attributes(line.split_once(':')?.1).find_map(|(key, value)| (key == name).then_some(value))
+}
+
+// #EXT-X-SKIP:SKIPPED-SEGMENTS=12
+fn parse_skipped(attributes: &str) -> Result<usize> {
+    Ok(attributes
+        .split_once("SKIPPED-SEGMENTS=")
+        .and_then(|(_, tail)| tail.split(',').next())
+        .context("Failed to parse skipped segments")?
+        .parse()?)
+}
+
+fn parse_secs(secs: &str) -> Option<time::Duration> {
+    time::Duration::try_from_secs_f64(secs.parse().ok()?).ok()
+}
+
+//Parses ISO 8601 timestamps as used by EXT-X-PROGRAM-DATE-TIME (2024-01-01T00:00:00.000Z)
+pub fn parse_date_time(date_time: &str) -> Option<SystemTime> {
+    let (date, time) = date_time.trim().split_once('T')?;
+
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+
+    let (time, offset) = if let Some(time) = time.strip_suffix('Z') {
+        (time, 0)
+    } else {
+        let pos = time.rfind(['+', '-'])?;
+        let (hours, minutes) = time[pos + 1..].split_once(':')?;
+        let offset = hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60;
+
+        (
+            &time[..pos],
+            if time.as_bytes()[pos] == b'-' {
+                -offset
+            } else {
+                offset
+            },
+        )
+    };
+
+    let mut time = time.splitn(3, ':');
+    let (hours, minutes) = (
+        time.next()?.parse::<i64>().ok()?,
+        time.next()?.parse::<i64>().ok()?,
+    );
+    let seconds = time.next()?.parse::<f64>().ok()?;
+
+    //Days since the unix epoch from a civil date (proleptic gregorian)
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let secs = u64::try_from(days * 86_400 + hours * 3600 + minutes * 60 - offset).ok()?;
+    SystemTime::UNIX_EPOCH.checked_add(
+        time::Duration::from_secs(secs) + time::Duration::try_from_secs_f64(seconds).ok()?,
+    )
+}
+
+#[derive(Debug)]
+pub enum Segment {
+    Normal(Duration, Url),
+    Prefetch(Url),
+}
+
+#[derive(Default, Copy, Clone, Debug)]
+pub struct Duration {
+    pub(super) is_ad: bool,
+    pub(super) ad_break: Option<time::Duration>, //declared length of the ad break
+    pub(super) ad_remaining: Option<time::Duration>, //until the end of the ad break, from this segment
+    pub(super) inner: time::Duration,
+}
+
+impl FromStr for Duration {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self {
+            is_ad: false,
+            ad_break: None,
+            ad_remaining: None,
+            inner: time::Duration::try_from_secs_f32(
+                s.split_once(',')
+                    .map(|d| d.0)
+                    .and_then(|d| d.parse().ok())
+                    .context("Invalid segment duration")?,
+            )
+            .context("Failed to parse segment duration")?,
+        })
+    }
+}
+
+impl PartialEq for Duration {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl PartialOrd for Duration {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.inner.cmp(&other.inner))
+    }
+}
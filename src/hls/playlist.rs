@@ -1,47 +1,51 @@
 use std::{
-    collections::{VecDeque, vec_deque::IterMut},
-    env,
+    env, fs,
+    path::PathBuf,
+    str,
+    time::{self, SystemTime},
 };
 
-use anyhow::{Context, Result, ensure};
-use log::debug;
+use anyhow::{Context, Result};
+use log::{debug, error};
 
 use super::{
     OfflineError, map_if_offline,
-    segment::{Duration, Segment},
+    parse::{Duration, MediaParser},
+    state::{QueueRange, State, Update},
 };
 
 use crate::{
-    http::{Connection, Url},
+    http::{Connection, Method, Request, Url},
     logger,
 };
 
-pub enum QueueRange<'a> {
-    Partial(IterMut<'a, Segment>),
-    Back(Option<&'a mut Segment>),
-    Empty,
-}
-
 pub struct Playlist {
-    pub header: Option<Url>, //used for av1/hevc streams
-
-    conn: Connection,
-    segments: VecDeque<Segment>,
+    url: Url,
+    request: Request<MediaParser>,
+    delta_url: Option<Url>,
+    moved: bool, //replaced by a redirect target, only done once in case they go in circles
     should_debug_log: bool,
-
-    sequence: usize,
-    added: usize,
+    dump_dir: Option<PathBuf>,
+    state: State,
+    clock_offset: Option<f64>, //server clock minus the local one in seconds, from Date headers
 }
 
 impl Playlist {
-    pub fn new(conn: Connection) -> Result<Self> {
+    pub fn new(conn: Connection, dump_dir: Option<&str>) -> Result<Self> {
+        let dump_dir = dump_dir.map(PathBuf::from);
+        if let Some(dir) = &dump_dir {
+            fs::create_dir_all(dir).context("Failed to create playlist dump directory")?;
+        }
+
         let mut playlist = Self {
-            conn,
-            segments: VecDeque::with_capacity(16),
+            request: conn.request.with_writer(MediaParser::new(conn.url.clone())),
+            url: conn.url,
+            delta_url: Option::default(),
+            moved: bool::default(),
             should_debug_log: logger::is_debug() && env::var_os("DEBUG_NO_PLAYLIST").is_none(),
-            header: Option::default(),
-            sequence: usize::default(),
-            added: usize::default(),
+            dump_dir,
+            state: State::new(),
+            clock_offset: Option::default(),
         };
 
         playlist.reload()?;
@@ -49,119 +53,165 @@ impl Playlist {
     }
 
     pub fn reload(&mut self) -> Result<()> {
-        let playlist = self.conn.text().map_err(map_if_offline)?;
-        if self.should_debug_log {
-            debug!("Playlist:\n{playlist}");
+        self.request.get_mut().begin(
+            self.state.sequence(),
+            self.state.known_segments(),
+            self.should_debug_log || self.dump_dir.is_some(),
+        );
+
+        //Delta updates need the skipped segments from a previous reload
+        match &self.delta_url {
+            Some(url) if !self.state.is_empty() => self.request.call(Method::Get, url),
+            _ => self.request.call(Method::Get, &self.url),
         }
+        .map_err(map_if_offline)?;
+        self.update_clock_offset();
 
-        if playlist
-            .lines()
-            .next_back()
-            .is_some_and(|l| l.trim() == "#EXT-X-ENDLIST")
+        //Segment URLs are relative to where the playlist actually is
+        if !self.moved
+            && let Some(url) = self.request.redirected().cloned()
         {
-            return Err(OfflineError.into());
+            debug!("Playlist moved to {url}");
+            self.request.get_mut().set_base(url.clone());
+            self.url = url;
+            self.moved = true;
+            self.delta_url = None;
+
+            self.reset();
+            return self.reload();
         }
 
-        let mut prefetch_removed = Self::remove_prefetch(&mut self.segments);
-        let mut prev_segment_count = self.segments.len();
-        let mut total_segments = 0;
-        let mut lines = playlist.lines();
-        while let Some(line) = lines.next() {
-            let Some(split) = line.split_once(':') else {
-                continue;
-            };
-
-            match split.0 {
-                "#EXT-X-MEDIA-SEQUENCE" => {
-                    let sequence = split.1.parse()?;
-                    ensure!(sequence >= self.sequence, "Sequence went backwards");
-
-                    if sequence > 0 {
-                        let removed = sequence - self.sequence;
-                        if removed < self.segments.len() {
-                            self.segments.drain(..removed);
-                            prev_segment_count = self.segments.len();
-
-                            debug!("Segments removed: {removed}");
-                        } else {
-                            self.segments.clear();
-                            prev_segment_count = 0;
-                            prefetch_removed = 0;
-
-                            debug!("All segments removed");
-                        }
-                    }
-
-                    self.sequence = sequence;
-                }
-                "#EXT-X-MAP" if self.header.is_none() => {
-                    self.header = Some(
-                        split
-                            .1
-                            .split_once('=')
-                            .context("Failed to parse segment header")?
-                            .1
-                            .trim_matches('"')
-                            .into(),
-                    );
-                }
-                "#EXTINF" => {
-                    total_segments += 1;
-                    if total_segments > prev_segment_count
-                        && let Some(url) = lines.next()
-                    {
-                        self.segments
-                            .push_back(Segment::Normal(split.1.parse()?, url.into()));
-                    }
-                }
-                "#EXT-X-TWITCH-PREFETCH" | "#EXT-X-PREFETCH" => {
-                    total_segments += 1;
-                    if total_segments > prev_segment_count {
-                        self.segments.push_back(Segment::Prefetch(split.1.into()));
-                    }
-                }
-                _ => (),
+        let parser = self.request.get_mut();
+        parser.finish()?;
+
+        if let Some(raw) = &parser.raw {
+            if self.should_debug_log {
+                debug!("Playlist:\n{raw}");
             }
+
+            dump(&mut self.dump_dir, raw);
         }
 
-        self.added = total_segments - (prev_segment_count + prefetch_removed);
-        debug!("Segments added: {}", self.added);
+        if parser.is_ended {
+            return Err(OfflineError.into());
+        }
 
+        let can_skip = match self.state.update(parser)? {
+            Update::Applied { can_skip } => can_skip,
+            Update::Incomplete => {
+                //Some of the skipped segments were never seen, start over with a full reload
+                debug!("Delta update skipped unknown segments");
+                self.reset();
+                return self.reload();
+            }
+        };
+
+        self.update_delta_url(can_skip);
         Ok(())
     }
 
     pub fn reset(&mut self) {
         debug!("Resetting playlist...");
-        self.segments.clear();
-        self.sequence = 0;
-        self.added = 0;
+        self.state.reset();
     }
 
-    pub(super) fn segment_queue(&mut self) -> QueueRange<'_> {
-        if self.added == 0 {
-            QueueRange::Empty
-        } else if self.added == self.segments.len() {
-            QueueRange::Back(self.segments.back_mut())
+    //Used for av1/hevc streams
+    pub const fn header(&self) -> Option<&Url> {
+        self.state.header.as_ref()
+    }
+
+    //Estimated from the program time of the newest segment, on the server's clock
+    pub fn latency(&self) -> Option<time::Duration> {
+        let offset = self.clock_offset.unwrap_or_default();
+        let now = if offset < 0.0 {
+            SystemTime::now().checked_sub(time::Duration::from_secs_f64(-offset))?
         } else {
-            QueueRange::Partial(self.segments.range_mut(self.segments.len() - self.added..))
-        }
+            SystemTime::now().checked_add(time::Duration::from_secs_f64(offset))?
+        };
+
+        now.duration_since(self.state.program_time?).ok()
+    }
+
+    //Twitch only, from the stream time of the oldest segment
+    pub fn uptime(&self) -> Option<time::Duration> {
+        self.state
+            .program_time?
+            .duration_since(self.request.get_ref().stream_start?)
+            .ok()
+    }
+
+    pub(super) fn newest_sequence(&self) -> usize {
+        self.state.newest_sequence()
+    }
+
+    pub(super) fn segment_queue(&mut self) -> QueueRange<'_> {
+        self.state.segment_queue()
+    }
+
+    pub(super) fn backfill(&mut self, count: usize) -> QueueRange<'_> {
+        self.state.backfill(count)
     }
 
     pub(super) fn last_duration(&self) -> Option<Duration> {
-        self.segments
-            .iter()
-            .rev()
-            .find_map(|s| match s {
-                Segment::Normal(duration, _) => Some(duration),
-                Segment::Prefetch(_) => None,
-            })
-            .copied()
+        self.state.last_duration()
+    }
+
+    //Date headers only have whole seconds, so the offset is averaged over reloads
+    fn update_clock_offset(&mut self) {
+        const WEIGHT: f64 = 0.1;
+
+        let Some(date) = self.request.date() else {
+            return;
+        };
+
+        //Date is truncated, the middle of the second is the best guess
+        let server = date + time::Duration::from_millis(500);
+        let sample = match server.duration_since(SystemTime::now()) {
+            Ok(ahead) => ahead.as_secs_f64(),
+            Err(behind) => -behind.duration().as_secs_f64(),
+        };
+
+        let Some(offset) = self.clock_offset else {
+            if sample.abs() >= 1.0 {
+                debug!("Clock offset from server: {sample:.1}s");
+            }
+
+            self.clock_offset = Some(sample);
+            return;
+        };
+
+        self.clock_offset = Some((sample - offset).mul_add(WEIGHT, offset));
     }
 
-    fn remove_prefetch(segments: &mut VecDeque<Segment>) -> usize {
-        let before = segments.len();
-        segments.retain(|s| matches!(*s, Segment::Normal(_, _)));
+    fn update_delta_url(&mut self, can_skip: bool) {
+        if !can_skip {
+            self.delta_url = None;
+        } else if self.delta_url.is_none() {
+            debug!("Server supports delta updates");
+            self.delta_url = Some(
+                format!(
+                    "{url}{separator}_HLS_skip=YES",
+                    url = self.url,
+                    separator = if self.url.contains('?') { '&' } else { '?' },
+                )
+                .into(),
+            );
+        }
+    }
+}
+
+fn dump(dump_dir: &mut Option<PathBuf>, playlist: &str) {
+    let Some(dir) = dump_dir else {
+        return;
+    };
+
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
 
-        before - segments.len()
+    if let Err(e) = fs::write(dir.join(format!("{timestamp}.m3u8")), playlist) {
+        error!("Failed to dump playlist, disabling: {e}");
+        *dump_dir = None;
     }
 }
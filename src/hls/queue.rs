@@ -0,0 +1,162 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+};
+
+use anyhow::{Result, bail};
+
+//A few playlist reloads worth of segments
+pub const DEFAULT_SIZE: usize = 8;
+
+//What happens to new segments when the worker falls behind
+#[derive(Default, Copy, Clone, Debug)]
+pub enum Policy {
+    #[default]
+    Block, //wait for the worker, nothing is dropped
+    DropOldest,
+    SkipToLive, //drop everything that's queued
+}
+
+impl Policy {
+    pub fn new(arg: &str) -> Result<Self> {
+        match arg {
+            "block" => Ok(Self::Block),
+            "drop-oldest" => Ok(Self::DropOldest),
+            "skip-to-live" => Ok(Self::SkipToLive),
+            _ => bail!("Invalid queue policy"),
+        }
+    }
+}
+
+//Bounded single consumer queue, closed when either side is dropped
+pub fn bounded<T>(capacity: usize, policy: Policy) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::with_capacity(capacity),
+            closed: false,
+        }),
+        changed: Condvar::new(),
+        capacity,
+        policy,
+    });
+
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver { shared },
+    )
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    changed: Condvar,
+    capacity: usize,
+    policy: Policy,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    closed: bool,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.changed.notify_all();
+    }
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    //Items dropped to make room are passed to evicted, gives the item back if the receiver is gone
+    pub fn send(&self, item: T, mut evicted: impl FnMut(T)) -> Result<(), T> {
+        let shared = &self.shared;
+        let mut state = shared.lock();
+        if state.items.len() >= shared.capacity {
+            match shared.policy {
+                Policy::Block => {
+                    state = shared
+                        .changed
+                        .wait_while(state, |s| !s.closed && s.items.len() >= shared.capacity)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+                Policy::DropOldest => {
+                    while state.items.len() >= shared.capacity {
+                        let Some(oldest) = state.items.pop_front() else {
+                            break;
+                        };
+                        evicted(oldest);
+                    }
+                }
+                Policy::SkipToLive => state.items.drain(..).for_each(evicted),
+            }
+        }
+
+        if state.closed {
+            return Err(item);
+        }
+
+        state.items.push_back(item);
+        drop(state);
+
+        shared.changed.notify_all();
+        Ok(())
+    }
+
+    //Drops everything that's queued, each dropped item is passed to evicted
+    pub fn clear(&self, evicted: impl FnMut(T)) {
+        let items = self.shared.lock().items.drain(..).collect::<Vec<_>>();
+        self.shared.changed.notify_all();
+
+        items.into_iter().for_each(evicted);
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.close();
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    //None once the sender is gone and everything queued has been received
+    pub fn recv(&self) -> Option<T> {
+        let shared = &self.shared;
+        let mut state = shared
+            .changed
+            .wait_while(shared.lock(), |s| !s.closed && s.items.is_empty())
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let item = state.items.pop_front();
+        drop(state);
+
+        shared.changed.notify_all();
+        item
+    }
+
+    //Drops everything that's queued, returns how many there were
+    pub fn clear(&self) -> usize {
+        let count = self.shared.lock().items.drain(..).count();
+        self.shared.changed.notify_all();
+
+        count
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.close();
+    }
+}
@@ -1,20 +1,34 @@
 use std::{
-    cmp::Ordering,
     fmt::{self, Display, Formatter},
+    fs::OpenOptions,
+    io::{self, Write},
     mem,
-    str::FromStr,
-    sync::mpsc::{self, Sender},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, Ordering as AtomicOrdering},
+        mpsc,
+    },
     thread::{self, Builder as ThreadBuilder, JoinHandle},
-    time::{self, Instant},
+    time::{self, Instant, SystemTime},
 };
 
-use anyhow::{Context, Result, bail};
-use log::{debug, info};
+use anyhow::{Context, Result};
+use log::{debug, error, info, warn};
 
-use super::playlist::{Playlist, QueueRange};
+use super::{
+    Args,
+    hook::Hook,
+    parse::{Duration, Segment},
+    playlist::Playlist,
+    queue::{self, Policy as QueuePolicy, Receiver, Sender},
+    state::QueueRange,
+};
 use crate::{
-    http::{Agent, Method, Request, StatusError, Url},
-    output::{Output, Writer},
+    http::{Agent, DeadlineError, Method, NonMediaError, Request, StallError, StatusError, Url},
+    obs::Obs,
+    output::{Output, SERVE, Writer, mpv_buffered, mpv_drop_buffers},
+    replay::REPLAY,
+    stats::{AUDIO_STATS, RECORD_STATS, STATS, SegmentStats, Stats, format_date_time},
 };
 
 #[derive(Debug)]
@@ -28,38 +42,211 @@ impl Display for ResetError {
     }
 }
 
+#[derive(Debug)]
+pub struct RefreshError;
+
+impl std::error::Error for RefreshError {}
+
+impl Display for RefreshError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("Segments keep returning not found")
+    }
+}
+
 pub struct Handler {
     worker: Option<Worker>,
+    feedback: Arc<Feedback>,
+    stats: &'static Stats,
     init: bool,
+    in_ad_break: bool,
+    filter_ads: bool,
+    alternate_active: bool, //another variant is written instead of the slate
+    last_sequence: usize,
+    worker_args: WorkerArgs,
+    start_buffer: usize, //only used on the first reload
+
+    latency_target: Option<time::Duration>,
+    segment_timeout: Option<f32>,
+    mpv_ipc: Option<String>,
+    obs: Option<Obs>,
+    hook: Option<Hook>,
+    ad_log: Option<String>,
+    ad_break_start: Option<(SystemTime, Option<time::Duration>)>, //wall clock and stream uptime
+    last_catch_up: Option<Instant>,
 }
 
 impl Handler {
-    pub fn new(writer: Writer, agent: &Agent) -> Result<Self> {
+    //Give the player time to settle before catching up again
+    const CATCH_UP_COOLDOWN: time::Duration = time::Duration::from_secs(10);
+
+    //Consecutive segment 404s before the variant URL is re-resolved
+    const NOT_FOUND_LIMIT: u32 = 3;
+
+    pub fn new(mut writer: Writer, agent: &Agent, args: &Args) -> Result<Self> {
+        if REPLAY.is_enabled() || SERVE.is_enabled() {
+            writer.enable_capture();
+        }
+
+        let worker_args = WorkerArgs {
+            queue_size: args.queue_size,
+            queue_policy: args.queue_policy,
+            parallel_prefetch: args.parallel_prefetch,
+            max_segment_lag: args.max_segment_lag,
+        };
+
+        let mut handler = Self::with_stats(writer, agent, &STATS, worker_args)?;
+        handler.start_buffer = args.start_buffer;
+        handler.latency_target = args.latency_target;
+        handler.segment_timeout = args.segment_timeout;
+        handler.mpv_ipc.clone_from(&args.mpv_ipc);
+        handler.filter_ads = !args.no_ad_filter;
+        handler.obs = Obs::new(&args.obs)?;
+        handler.hook = Hook::new(&args.hook, &args.channel)?;
+        handler.ad_log.clone_from(&args.ad_log);
+
+        Ok(handler)
+    }
+
+    //Secondary audio only recording, kept out of the main stats.
+    //Recordings never drop queued segments.
+    pub fn audio(writer: Writer, agent: &Agent) -> Result<Self> {
+        Self::with_stats(writer, agent, &AUDIO_STATS, WorkerArgs::default())
+    }
+
+    //Recording of a different quality than the one being watched
+    pub fn recording(writer: Writer, agent: &Agent) -> Result<Self> {
+        Self::with_stats(writer, agent, &RECORD_STATS, WorkerArgs::default())
+    }
+
+    fn with_stats(
+        writer: Writer,
+        agent: &Agent,
+        stats: &'static Stats,
+        worker_args: WorkerArgs,
+    ) -> Result<Self> {
+        let feedback = Arc::default();
         Ok(Self {
-            worker: Some(Worker::spawn(agent.binary(writer))?),
+            worker: Some(Worker::spawn(
+                agent.binary(writer),
+                Arc::clone(&feedback),
+                stats,
+                worker_args,
+            )?),
+            feedback,
+            stats,
             init: true,
+            in_ad_break: false,
+            alternate_active: false,
+            filter_ads: true,
+            last_sequence: usize::default(),
+            worker_args,
+            start_buffer: usize::default(),
+            latency_target: Option::default(),
+            segment_timeout: Option::default(),
+            mpv_ipc: Option::default(),
+            obs: Option::default(),
+            hook: Option::default(),
+            ad_log: Option::default(),
+            ad_break_start: Option::default(),
+            last_catch_up: Option::default(),
         })
     }
 
+    //Waits for queued segments to finish and returns the writer
+    pub fn into_writer(mut self) -> Result<Writer> {
+        Ok(self
+            .worker
+            .take()
+            .expect("Missing worker while joining")
+            .join()?
+            .into_writer())
+    }
+
     pub fn process(&mut self, playlist: &mut Playlist, time: Instant) -> Result<()> {
+        //Playlist still updates but its segments are gone, likely moved to another edge
+        let not_found = &self.feedback.not_found;
+        if not_found.load(AtomicOrdering::Relaxed) >= Self::NOT_FOUND_LIMIT {
+            not_found.store(0, AtomicOrdering::Relaxed);
+            self.init = true;
+
+            return Err(RefreshError.into());
+        }
+
         let last_duration = playlist
             .last_duration()
             .context("Failed to find last segment duration")?;
 
         if last_duration.is_ad {
-            info!("Filtering ad segment...");
-            last_duration.sleep(time.elapsed());
+            if !self.in_ad_break {
+                self.in_ad_break = true;
+                self.stats.ad_break_started();
+                if let Some(ad_break) = last_duration.ad_break {
+                    info!("Ad break started, {}s declared", ad_break.as_secs());
+                }
 
-            return Ok(());
+                if let Some(obs) = &self.obs {
+                    obs.ad_break_started();
+                }
+
+                if let Some(hook) = &self.hook {
+                    hook.ad_break_started(last_duration.ad_break);
+                }
+
+                self.ad_break_start = Some((SystemTime::now(), playlist.uptime()));
+            }
+
+            //Ad breaks are still tracked when ads are passed through
+            if self.filter_ads {
+                match last_duration.ad_remaining {
+                    Some(remaining) => info!("Ad break: {}s remaining...", remaining.as_secs()),
+                    None => info!("Filtering ad segment..."),
+                }
+
+                if !self.alternate_active {
+                    self.worker
+                        .as_ref()
+                        .expect("Missing worker while sending slate")
+                        .send_slate();
+                }
+
+                last_duration.sleep(time.elapsed());
+
+                return Ok(());
+            }
+        } else if self.in_ad_break {
+            self.end_ad_break();
         }
 
-        match playlist.segment_queue() {
+        //Resets start from the newest segment again
+        let start_buffer = mem::take(&mut self.start_buffer);
+        let lagging = self.feedback.lagging.swap(false, AtomicOrdering::Relaxed);
+        let latency = (start_buffer == 0)
+            .then(|| self.latency_above_target(playlist, last_duration))
+            .flatten();
+        let behind = start_buffer == 0 && (latency.is_some() || lagging);
+        let newest_sequence = playlist.newest_sequence();
+        let mut queue = if start_buffer > 0 {
+            debug!("Buffering up to {start_buffer} segments before the newest");
+            playlist.backfill(start_buffer)
+        } else {
+            playlist.segment_queue()
+        };
+
+        match queue {
+            QueueRange::Partial(ref mut segments) if behind => {
+                self.catch_up(latency, segments.len() - 1);
+
+                let newest = segments.last().context("Failed to find newest segment")?;
+                debug!("Processing newest segment:\n{newest:?}");
+                self.dispatch(newest, newest_sequence)?;
+
+                last_duration.sleep(time.elapsed());
+            }
             QueueRange::Partial(ref mut segments) => {
-                for segment in segments {
+                let first_sequence = newest_sequence + 1 - segments.len();
+                for (sequence, segment) in (first_sequence..).zip(segments) {
                     debug!("Processing segment:\n{segment:?}");
-                    match segment {
-                        Segment::Normal(_, url) | Segment::Prefetch(url) => self.dispatch(url)?,
-                    }
+                    self.dispatch(segment, sequence)?;
                 }
 
                 last_duration.sleep(time.elapsed());
@@ -68,17 +255,21 @@ impl Handler {
             QueueRange::Back(newest) => {
                 if !self.init {
                     info!("Failed to find next segment, skipping to newest...");
+                    self.stats
+                        .segments_skipped(newest_sequence.saturating_sub(self.last_sequence + 1));
                 }
 
                 let newest = newest.context("Failed to find newest segment")?;
                 debug!("Processing newest segment:\n{newest:?}");
 
-                match newest {
-                    Segment::Normal(duration, url) => {
-                        self.dispatch(url)?;
-                        duration.sleep(time.elapsed());
-                    }
-                    Segment::Prefetch(url) => self.dispatch(url)?,
+                let duration = match newest {
+                    Segment::Normal(duration, _) => Some(*duration),
+                    Segment::Prefetch(_) => None,
+                };
+
+                self.dispatch(newest, newest_sequence)?;
+                if let Some(duration) = duration {
+                    duration.sleep(time.elapsed());
                 }
             }
             QueueRange::Empty => {
@@ -93,12 +284,156 @@ impl Handler {
         Ok(())
     }
 
-    fn dispatch(&mut self, url: &mut Url) -> Result<()> {
+    fn end_ad_break(&mut self) {
+        self.in_ad_break = false;
+        self.alternate_active = false;
+        if let Some(obs) = &self.obs {
+            obs.ad_break_ended();
+        }
+
+        let duration = self.stats.ad_break_ended();
+        if let Some(duration) = duration {
+            info!("Ad break ended after {}s", duration.as_secs());
+        }
+
+        if let Some(hook) = &self.hook {
+            hook.ad_break_ended(duration);
+        }
+
+        if let (Some(path), Some((start, uptime))) = (&self.ad_log, self.ad_break_start.take())
+            && let Err(e) = log_ad_break(path, start, uptime, duration.unwrap_or_default())
+        {
+            error!("Failed to write ad log: {e}");
+        }
+    }
+
+    pub const fn is_filtering_ads(&self) -> bool {
+        self.in_ad_break && self.filter_ads
+    }
+
+    //Back to the slate for the rest of the ad break
+    pub const fn alternate_stopped(&mut self) {
+        self.alternate_active = false;
+    }
+
+    //Writes new segments from another variant while the main one is filtered, without sleeping
+    pub fn process_alternate(&mut self, playlist: &mut Playlist) -> Result<()> {
+        let last_duration = playlist
+            .last_duration()
+            .context("Failed to find last segment duration")?;
+
+        //The slate is written again while the alternate has ads of its own
+        self.alternate_active = !last_duration.is_ad;
+        if last_duration.is_ad {
+            return Ok(());
+        }
+
+        //Keep tracking the main playlist's sequence
+        let last_sequence = self.last_sequence;
+        let newest_sequence = playlist.newest_sequence();
+        match playlist.segment_queue() {
+            QueueRange::Partial(segments) => {
+                let first_sequence = newest_sequence + 1 - segments.len();
+                for (sequence, segment) in (first_sequence..).zip(segments) {
+                    debug!("Processing alternate segment:\n{segment:?}");
+                    self.dispatch(segment, sequence)?;
+                }
+            }
+            QueueRange::Back(Some(newest)) => {
+                debug!("Processing alternate segment:\n{newest:?}");
+                self.dispatch(newest, newest_sequence)?;
+            }
+            QueueRange::Back(None) | QueueRange::Empty => (),
+        }
+
+        self.last_sequence = last_sequence;
+        Ok(())
+    }
+
+    //Estimated from the playlist's latency plus what's still waiting to be downloaded,
+    //and what mpv has buffered if --mpv-ipc is set
+    fn latency_above_target(
+        &self,
+        playlist: &Playlist,
+        duration: Duration,
+    ) -> Option<time::Duration> {
+        let (Some(target), Some(latency)) = (self.latency_target, playlist.latency()) else {
+            return None;
+        };
+
+        if self
+            .last_catch_up
+            .is_some_and(|t| t.elapsed() < Self::CATCH_UP_COOLDOWN)
+        {
+            return None;
+        }
+
+        let mut latency = latency
+            + duration
+                .inner
+                .saturating_mul(u32::try_from(self.stats.queued()).unwrap_or(u32::MAX));
+
+        //Only asked for when it can make a difference
+        if latency <= target
+            && let Some(path) = &self.mpv_ipc
+        {
+            match mpv_buffered(path) {
+                Ok(buffered) => latency += buffered,
+                Err(e) => debug!("Failed to get player buffer: {e}"),
+            }
+        }
+
+        (latency > target).then_some(latency)
+    }
+
+    //Skips everything before the newest segment, including what's queued for the worker.
+    //The player's buffer is dropped too if it's the latency that's behind.
+    fn catch_up(&mut self, latency: Option<time::Duration>, skipped: usize) {
+        self.stats.segments_skipped(skipped);
+        self.worker
+            .as_ref()
+            .expect("Missing worker while catching up")
+            .clear();
+
+        let Some(latency) = latency else {
+            return;
+        };
+
+        info!(
+            "Latency ({:.1}s) above target, catching up...",
+            latency.as_secs_f64()
+        );
+        self.last_catch_up = Some(Instant::now());
+
+        if let Some(path) = &self.mpv_ipc
+            && let Err(e) = mpv_drop_buffers(path)
+        {
+            error!("Failed to drop player buffers: {e}");
+        }
+    }
+
+    fn dispatch(&mut self, segment: &mut Segment, sequence: usize) -> Result<()> {
+        self.last_sequence = sequence;
+        let (url, duration) = match segment {
+            Segment::Normal(duration, url) => (mem::take(url), Some(duration.inner)),
+            Segment::Prefetch(url) => (mem::take(url), None),
+        };
+
+        let job = Job {
+            url,
+            sequence,
+            duration,
+            //Prefetch segments are still being produced and arrive in realtime
+            timeout: duration
+                .zip(self.segment_timeout)
+                .map(|(duration, multiple)| duration.mul_f32(multiple)),
+        };
+
         if !self
             .worker
             .as_mut()
             .expect("Missing worker while sending URL")
-            .send(mem::take(url))
+            .send(job)
         {
             let mut request = self
                 .worker
@@ -107,7 +442,12 @@ impl Handler {
                 .join()?;
 
             request.get_mut().wait_for_output()?;
-            self.worker = Some(Worker::spawn(request)?);
+            self.worker = Some(Worker::spawn(
+                request,
+                Arc::clone(&self.feedback),
+                self.stats,
+                self.worker_args,
+            )?);
 
             self.init = true;
             return Err(ResetError.into());
@@ -117,43 +457,319 @@ impl Handler {
     }
 }
 
+// 2024-01-01 00:00:00 UTC uptime=1:23:45 duration=30s
+fn log_ad_break(
+    path: &str,
+    start: SystemTime,
+    uptime: Option<time::Duration>,
+    duration: time::Duration,
+) -> std::io::Result<()> {
+    let uptime = uptime.map_or_else(
+        || "unknown".to_owned(),
+        |uptime| {
+            let secs = uptime.as_secs();
+            format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+        },
+    );
+
+    writeln!(
+        OpenOptions::new().append(true).create(true).open(path)?,
+        "{} uptime={uptime} duration={}s",
+        format_date_time(start),
+        duration.as_secs(),
+    )
+}
+
+#[derive(Clone)]
+struct Job {
+    url: Url,
+    sequence: usize,
+    duration: Option<time::Duration>,
+    timeout: Option<time::Duration>,
+}
+
+enum Task {
+    Download(Job),
+    Lookahead(Job, mpsc::Receiver<Chunk>), //already downloading on the lookahead connection
+    Slate,
+}
+
+//Set by the worker, acted on by the handler on its next playlist reload
+#[derive(Default)]
+struct Feedback {
+    not_found: AtomicU32, //consecutive segment 404s
+    lagging: AtomicBool,  //fell more than --max-segment-lag behind
+}
+
+//Kept to respawn the worker after a reset
+#[derive(Copy, Clone)]
+struct WorkerArgs {
+    queue_size: usize,
+    queue_policy: QueuePolicy,
+    parallel_prefetch: bool,
+    max_segment_lag: Option<time::Duration>,
+}
+
+impl Default for WorkerArgs {
+    fn default() -> Self {
+        Self {
+            queue_size: queue::DEFAULT_SIZE,
+            queue_policy: QueuePolicy::Block,
+            parallel_prefetch: false,
+            max_segment_lag: None,
+        }
+    }
+}
+
+struct Downloaded {
+    bytes: u64,
+    elapsed: time::Duration,
+    write_time: time::Duration,
+    cache: Option<String>, //only kept for lookahead downloads
+}
+
 struct Worker {
     handle: JoinHandle<Result<Request<Writer>>>,
-    sender: Sender<Url>,
+    sender: Sender<Task>,
+    busy: Arc<AtomicBool>, //between receiving a segment and finishing it
+    lookahead: Option<Lookahead>,
+    stats: &'static Stats,
 }
 
 impl Worker {
-    fn spawn(mut request: Request<Writer>) -> Result<Self> {
-        let (sender, receiver) = mpsc::channel::<Url>();
-        let handle = ThreadBuilder::new()
-            .name("hls worker".to_owned())
-            .spawn(move || -> Result<Request<Writer>> {
-                loop {
-                    let Ok(url) = receiver.recv() else {
-                        bail!("Worker died unexpectantly");
-                    };
+    fn spawn(
+        request: Request<Writer>,
+        feedback: Arc<Feedback>,
+        stats: &'static Stats,
+        args: WorkerArgs,
+    ) -> Result<Self> {
+        let (sender, receiver) = queue::bounded::<Task>(args.queue_size, args.queue_policy);
+        let lookahead = if args.parallel_prefetch {
+            Some(Lookahead::spawn(
+                request.agent().binary(ChunkWriter::default()),
+            )?)
+        } else {
+            None
+        };
+
+        let busy = Arc::<AtomicBool>::default();
+        let handle = {
+            let busy = Arc::clone(&busy);
+            ThreadBuilder::new()
+                .name("hls worker".to_owned())
+                .spawn(move || {
+                    Self::run(
+                        request,
+                        &receiver,
+                        &busy,
+                        &feedback,
+                        stats,
+                        RealtimeMonitor::new(args.max_segment_lag),
+                    )
+                })
+                .context("Failed to spawn worker")?
+        };
 
-                    match request.call(Method::Get, &url) {
-                        Ok(()) => (),
-                        Err(e) if StatusError::is_not_found(&e) => {
-                            info!("Segment not found, skipping ahead...");
-                            receiver.try_iter().for_each(drop);
-                        }
-                        Err(e) => return Err(e),
+        Ok(Self {
+            handle,
+            sender,
+            busy,
+            lookahead,
+            stats,
+        })
+    }
+
+    fn run(
+        mut request: Request<Writer>,
+        receiver: &Receiver<Task>,
+        busy: &AtomicBool,
+        feedback: &Feedback,
+        stats: &Stats,
+        mut realtime: RealtimeMonitor,
+    ) -> Result<Request<Writer>> {
+        loop {
+            busy.store(false, AtomicOrdering::Relaxed);
+
+            //Handler was dropped, hand the request back
+            let (job, chunks) = match receiver.recv() {
+                Some(Task::Download(job)) => (job, None),
+                Some(Task::Lookahead(job, chunks)) => (job, Some(chunks)),
+                Some(Task::Slate) => {
+                    if let Err(e) = request.get_mut().write_slate() {
+                        error!("Failed to write ad slate: {e}");
+                    }
+
+                    continue;
+                }
+                None => return Ok(request),
+            };
+            busy.store(true, AtomicOrdering::Relaxed);
+
+            //Partial segment left behind by a failed download
+            let writer = request.get_mut();
+            writer.take_capture();
+            writer.hold(job.timeout.is_some());
+
+            let is_lookahead = chunks.is_some();
+            let result = if let Some(chunks) = chunks {
+                Self::write_lookahead(&mut request, &chunks)
+            } else {
+                let time = Instant::now();
+                request.set_body_timeout(job.timeout);
+                #[cfg(target_os = "linux")]
+                request.splice_to(request.get_ref().pipe());
+                request.call(Method::Get, &job.url).map(|()| Downloaded {
+                    bytes: request.written(),
+                    elapsed: time.elapsed(),
+                    write_time: request.write_time(),
+                    cache: None,
+                })
+            };
+
+            match result {
+                Ok(downloaded) => {
+                    feedback.not_found.store(0, AtomicOrdering::Relaxed);
+                    if let Some(data) = request.get_mut().take_capture() {
+                        let data: Arc<[u8]> = data.into();
+                        REPLAY.push(job.duration, Arc::clone(&data));
+                        SERVE.push(job.duration, data);
                     }
 
-                    if request.get_ref().should_wait() {
-                        return Ok(request);
+                    stats.segment_done(&SegmentStats {
+                        sequence: job.sequence,
+                        duration: job.duration,
+                        bytes: downloaded.bytes,
+                        elapsed: downloaded.elapsed,
+                        write_time: downloaded.write_time,
+                    });
+
+                    let cache = if is_lookahead {
+                        downloaded.cache.as_deref()
+                    } else {
+                        request.header("x-cache")
+                    };
+                    if realtime.check(&downloaded, job.duration, cache, stats) {
+                        info!(
+                            "Downloads fell too far behind the stream, skipping to newest segment..."
+                        );
+                        stats.segments_dropped(receiver.clear());
+                        feedback.lagging.store(true, AtomicOrdering::Relaxed);
                     }
                 }
-            })
-            .context("Failed to spawn worker")?;
+                Err(e) if StatusError::is_not_found(&e) => {
+                    feedback.not_found.fetch_add(1, AtomicOrdering::Relaxed);
+
+                    info!("Segment not found, skipping ahead...");
+                    stats.segments_dropped(1 + receiver.clear());
+                }
+                Err(e) if e.is::<DeadlineError>() => {
+                    info!("Segment download took too long, skipping ahead...");
+                    stats.segments_dropped(1 + receiver.clear());
+                }
+                Err(e) if e.is::<StallError>() => {
+                    error!("{e}, skipping ahead...");
+                    stats.segments_dropped(1 + receiver.clear());
+                }
+                Err(e) if e.is::<NonMediaError>() => {
+                    error!("{e}, skipping segment...");
+                    stats.segments_dropped(1);
+                }
+                Err(e) => return Err(e),
+            }
+
+            if request.get_ref().should_wait() {
+                stats.segments_dropped(receiver.clear());
+                return Ok(request);
+            }
+        }
+    }
+
+    //Writes the segment as the lookahead connection downloads it, timed from when the
+    //worker gets to it so waiting for the previous segment doesn't count
+    fn write_lookahead(
+        request: &mut Request<Writer>,
+        chunks: &mpsc::Receiver<Chunk>,
+    ) -> Result<Downloaded> {
+        let time = Instant::now();
+        let writer = request.get_mut();
+
+        let mut bytes = 0;
+        let mut write_time = time::Duration::ZERO;
+        loop {
+            match chunks.recv().context("Lookahead connection stopped")? {
+                Chunk::Data(data) => {
+                    let write_start = Instant::now();
+                    writer.write_all(&data)?;
+                    write_time += write_start.elapsed();
+                    bytes += data.len() as u64;
+                }
+                Chunk::Done(result) => {
+                    let cache = result?;
+                    writer.flush()?;
+
+                    return Ok(Downloaded {
+                        bytes,
+                        elapsed: time.elapsed(),
+                        write_time,
+                        cache,
+                    });
+                }
+            }
+        }
+    }
+
+    fn send(&self, job: Job) -> bool {
+        self.stats.segment_queued();
+
+        //Only worth it while the worker is still busy with the previous segment
+        let task = match &self.lookahead {
+            Some(lookahead) if self.busy.load(AtomicOrdering::Relaxed) => lookahead.start(job),
+            _ => Task::Download(job),
+        };
+
+        if self.push(task).is_err() {
+            self.stats.segments_dropped(1);
+            return false;
+        }
+
+        true
+    }
+
+    //Queued segments are dropped, the one being downloaded is still finished
+    fn clear(&self) {
+        let mut dropped = 0;
+        self.sender.clear(|task| {
+            if matches!(task, Task::Download(_) | Task::Lookahead(..)) {
+                dropped += 1;
+            }
+        });
 
-        Ok(Self { handle, sender })
+        if dropped > 0 {
+            debug!("Dropped {dropped} queued segments");
+            self.stats.segments_dropped(dropped);
+        }
+    }
+
+    //Worker errors show up on the next segment
+    fn send_slate(&self) {
+        let _ = self.push(Task::Slate);
     }
 
-    fn send(&self, url: Url) -> bool {
-        self.sender.send(url).is_ok()
+    fn push(&self, task: Task) -> Result<(), Task> {
+        let mut evicted = 0;
+        let result = self.sender.send(task, |task| {
+            if matches!(task, Task::Download(_) | Task::Lookahead(..)) {
+                evicted += 1;
+            }
+        });
+
+        if evicted > 0 {
+            info!("Download queue is full, skipping ahead...");
+            debug!("Dropped {evicted} queued segments");
+            self.stats.segments_dropped(evicted);
+        }
+
+        result
     }
 
     fn join(self) -> Result<Request<Writer>> {
@@ -162,44 +778,176 @@ impl Worker {
     }
 }
 
-#[derive(Debug)]
-pub enum Segment {
-    Normal(Duration, Url),
-    Prefetch(Url),
+//Second connection that starts on the next segment while the worker is still writing the
+//current one, so the time to first byte of live segments overlaps with the previous one
+struct Lookahead {
+    sender: mpsc::Sender<(Job, mpsc::SyncSender<Chunk>)>,
+    busy: Arc<AtomicBool>,
 }
 
-#[derive(Default, Copy, Clone, Debug)]
-pub struct Duration {
-    is_ad: bool,
-    inner: time::Duration,
-}
+impl Lookahead {
+    //Chunks buffered ahead of the worker, the download waits once it's this far ahead
+    const MAX_CHUNKS: usize = 64;
 
-impl FromStr for Duration {
-    type Err = anyhow::Error;
+    fn spawn(mut request: Request<ChunkWriter>) -> Result<Self> {
+        let (sender, jobs) = mpsc::channel::<(Job, mpsc::SyncSender<Chunk>)>();
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Self {
-            is_ad: s.contains('|'),
-            inner: time::Duration::try_from_secs_f32(
-                s.split_once(',')
-                    .map(|d| d.0)
-                    .and_then(|d| d.parse().ok())
-                    .context("Invalid segment duration")?,
-            )
-            .context("Failed to parse segment duration")?,
-        })
+        let busy = Arc::<AtomicBool>::default();
+        {
+            let busy = Arc::clone(&busy);
+            ThreadBuilder::new()
+                .name("hls lookahead".to_owned())
+                .spawn(move || {
+                    for (job, chunks) in jobs {
+                        request.get_mut().0 = Some(chunks);
+                        request.set_body_timeout(job.timeout);
+
+                        let result = request
+                            .call(Method::Get, &job.url)
+                            .map(|()| request.header("x-cache").map(str::to_owned));
+
+                        busy.store(false, AtomicOrdering::Relaxed);
+                        let chunks = request.get_mut().0.take();
+
+                        //Rest of the body may still be in the connection
+                        if result.is_err() {
+                            request = request.agent().clone().binary(ChunkWriter::default());
+                        }
+
+                        //Segment was dropped from the queue if nobody is listening
+                        if let Some(chunks) = chunks {
+                            let _ = chunks.send(Chunk::Done(result));
+                        }
+                    }
+                })
+                .context("Failed to spawn lookahead")?;
+        }
+
+        Ok(Self { sender, busy })
+    }
+
+    fn start(&self, job: Job) -> Task {
+        if self.busy.swap(true, AtomicOrdering::Relaxed) {
+            return Task::Download(job);
+        }
+
+        let (chunks_sender, chunks) = mpsc::sync_channel(Self::MAX_CHUNKS);
+        if self.sender.send((job.clone(), chunks_sender)).is_err() {
+            return Task::Download(job);
+        }
+
+        debug!(
+            "Downloading segment {} on the lookahead connection",
+            job.sequence
+        );
+        Task::Lookahead(job, chunks)
     }
 }
 
-impl PartialEq for Duration {
-    fn eq(&self, other: &Self) -> bool {
-        self.inner == other.inner
+enum Chunk {
+    Data(Vec<u8>),
+    Done(Result<Option<String>>), //X-Cache header of the response
+}
+
+//Hands the body to the worker as it's read
+#[derive(Default)]
+struct ChunkWriter(Option<mpsc::SyncSender<Chunk>>);
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let sent = self
+            .0
+            .as_ref()
+            .is_some_and(|chunks| chunks.send(Chunk::Data(buf.to_vec())).is_ok());
+
+        //Other isn't retried
+        if !sent {
+            return Err(io::Error::other("Lookahead segment was dropped"));
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
 }
 
-impl PartialOrd for Duration {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.inner.cmp(&other.inner))
+//Warns when downloads consistently take longer than the segments they contain
+struct RealtimeMonitor {
+    streak: u32,
+    lag: time::Duration, //download time in excess of the segments' durations
+    max_lag: Option<time::Duration>,
+}
+
+impl RealtimeMonitor {
+    const WARN_STREAK: u32 = 3;
+
+    const fn new(max_lag: Option<time::Duration>) -> Self {
+        Self {
+            streak: 0,
+            lag: time::Duration::ZERO,
+            max_lag,
+        }
+    }
+
+    //True if downloads fell more than max_lag behind and should skip to the newest segment
+    fn check(
+        &mut self,
+        downloaded: &Downloaded,
+        duration: Option<time::Duration>, //none for prefetch segments
+        cache: Option<&str>,              //X-Cache header, misses have to come from origin
+        stats: &Stats,
+    ) -> bool {
+        //Prefetch segments are sent as they're produced, so they always take about as long as
+        //they last even at live. Time spent writing is the player pushing back, not the download.
+        let Some(duration) = duration else {
+            return false;
+        };
+
+        let elapsed = downloaded.elapsed.saturating_sub(downloaded.write_time);
+        self.warn(elapsed, duration, cache, stats);
+
+        //Faster downloads earn back the lag
+        if elapsed <= duration {
+            self.lag = self.lag.saturating_sub(duration.saturating_sub(elapsed));
+            return false;
+        }
+        self.lag += elapsed.saturating_sub(duration);
+
+        if self.max_lag.is_some_and(|max| self.lag > max) {
+            self.lag = time::Duration::ZERO;
+            return true;
+        }
+
+        false
+    }
+
+    fn warn(
+        &mut self,
+        elapsed: time::Duration,
+        duration: time::Duration,
+        cache: Option<&str>,
+        stats: &Stats,
+    ) {
+        if elapsed <= duration {
+            self.streak = 0;
+            return;
+        }
+
+        self.streak += 1;
+        let total = stats.segment_slow();
+        debug!(
+            "Segment download slower than realtime: {elapsed:?} > {duration:?} (cache: {})",
+            cache.unwrap_or("unknown")
+        );
+
+        if self.streak == Self::WARN_STREAK {
+            warn!(
+                "Segment downloads are slower than realtime ({total} total), \
+                 the connection or proxy may not be able to sustain this quality"
+            );
+        }
     }
 }
 
@@ -207,6 +955,8 @@ impl Duration {
     //Can't wait too long or the server will close the socket
     const MAX: Self = Self {
         is_ad: false,
+        ad_break: None,
+        ad_remaining: None,
         inner: time::Duration::from_secs(3),
     };
 
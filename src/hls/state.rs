@@ -0,0 +1,155 @@
+//Segments of the media playlist across reloads, without any network access
+use std::{
+    collections::{VecDeque, vec_deque::IterMut},
+    time::SystemTime,
+};
+
+use anyhow::{Result, ensure};
+use log::debug;
+
+use super::parse::{Duration, MediaParser, Segment};
+use crate::http::Url;
+
+pub enum QueueRange<'a> {
+    Partial(IterMut<'a, Segment>),
+    Back(Option<&'a mut Segment>),
+    Empty,
+}
+
+//Result of applying a parsed reload
+pub enum Update {
+    Applied { can_skip: bool },
+    Incomplete, //delta update skipped segments that were never seen, needs a full reload
+}
+
+#[derive(Default)]
+pub struct State {
+    pub header: Option<Url>, //used for av1/hevc streams
+    pub program_time: Option<SystemTime>,
+
+    segments: VecDeque<Segment>,
+    sequence: usize,
+    added: usize,
+}
+
+impl State {
+    pub fn new() -> Self {
+        Self {
+            segments: VecDeque::with_capacity(16),
+            ..Self::default()
+        }
+    }
+
+    //Passed to MediaParser::begin before a reload
+    pub const fn sequence(&self) -> usize {
+        self.sequence
+    }
+
+    pub fn known_segments(&self) -> usize {
+        self.segments
+            .iter()
+            .filter(|s| matches!(s, Segment::Normal(_, _)))
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    //Takes the new segments out of a finished parser
+    pub fn update(&mut self, parser: &mut MediaParser) -> Result<Update> {
+        let mut prefetch_removed = Self::remove_prefetch(&mut self.segments);
+        if let Some(sequence) = parser.sequence {
+            ensure!(sequence >= self.sequence, "Sequence went backwards");
+
+            if sequence > 0 {
+                let removed = sequence - self.sequence;
+                if removed < self.segments.len() {
+                    self.segments.drain(..removed);
+                    debug!("Segments removed: {removed}");
+                } else {
+                    self.segments.clear();
+                    prefetch_removed = 0;
+                    debug!("All segments removed");
+                }
+            }
+
+            self.sequence = sequence;
+        }
+
+        if self.header.is_none() {
+            self.header = parser.header.take();
+        }
+
+        if parser.program_time.is_some() {
+            self.program_time = parser.program_time;
+        }
+
+        if parser.is_incomplete {
+            return Ok(Update::Incomplete);
+        }
+
+        self.added = parser.total_segments - (parser.prev_segment_count + prefetch_removed);
+        self.segments.extend(parser.segments.drain(..));
+        debug!("Segments added: {}", self.added);
+
+        Ok(Update::Applied {
+            can_skip: parser.can_skip,
+        })
+    }
+
+    pub fn reset(&mut self) {
+        self.segments.clear();
+        self.sequence = 0;
+        self.added = 0;
+    }
+
+    pub fn newest_sequence(&self) -> usize {
+        self.sequence + self.segments.len().saturating_sub(1)
+    }
+
+    pub fn segment_queue(&mut self) -> QueueRange<'_> {
+        if self.added == 0 {
+            QueueRange::Empty
+        } else if self.added == self.segments.len() {
+            QueueRange::Back(self.segments.back_mut())
+        } else {
+            QueueRange::Partial(self.segments.range_mut(self.segments.len() - self.added..))
+        }
+    }
+
+    //Newest segment plus up to count already listed before it, even if they were seen before.
+    //Prefetch segments are all kept and don't count, the count is taken before the first one.
+    pub fn backfill(&mut self, count: usize) -> QueueRange<'_> {
+        if self.segments.is_empty() {
+            return QueueRange::Empty;
+        }
+
+        let newest = self
+            .segments
+            .iter()
+            .position(|s| matches!(s, Segment::Prefetch(_)))
+            .unwrap_or(self.segments.len() - 1);
+
+        let start = newest.saturating_sub(count);
+        QueueRange::Partial(self.segments.range_mut(start..))
+    }
+
+    pub fn last_duration(&self) -> Option<Duration> {
+        self.segments
+            .iter()
+            .rev()
+            .find_map(|s| match s {
+                Segment::Normal(duration, _) => Some(duration),
+                Segment::Prefetch(_) => None,
+            })
+            .copied()
+    }
+
+    fn remove_prefetch(segments: &mut VecDeque<Segment>) -> usize {
+        let before = segments.len();
+        segments.retain(|s| matches!(*s, Segment::Normal(_, _)));
+
+        before - segments.len()
+    }
+}
@@ -1,29 +1,52 @@
+mod buffers;
+mod cookies;
 mod decoder;
+mod pool;
+mod profile;
 mod request;
+mod session;
+mod shared;
 mod socks5;
+mod tls;
 mod url;
 
 pub use request::{Request, TextRequest};
 pub use url::{Scheme, Url};
 
 use std::{
-    borrow::Cow,
     fmt::{self, Display, Formatter},
     io::Write,
-    net::{SocketAddr, ToSocketAddrs},
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    str::FromStr,
     sync::Arc,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
-use anyhow::Result;
-use log::{debug, error};
-use rustls::{ClientConfig, RootCertStore};
+use anyhow::{Context, Result, bail, ensure};
+use log::{debug, error, warn};
+use rustls::{
+    ClientConfig, RootCertStore,
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, pem::PemObject},
+};
+
+use buffers::BufferPool;
+use cookies::CookieJar;
+use pool::Pool;
+use profile::HeaderProfile;
+use session::Session;
+use shared::SharedConnection;
+use socks5::Credentials;
+use tls::PartialVerifier;
 
 use crate::{
     args::{Parse, Parser},
     constants,
 };
 
+#[cfg(not(any(feature = "ring", feature = "aws-lc-rs")))]
+compile_error!("At least one of the ring or aws-lc-rs features must be enabled");
+
 #[derive(Debug)]
 pub struct StatusError(u16, Url);
 
@@ -43,15 +66,90 @@ impl StatusError {
     }
 }
 
+#[derive(Debug)]
+pub struct DeadlineError(Url);
+
+impl std::error::Error for DeadlineError {}
+
+impl Display for DeadlineError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Deadline exceeded on {}", self.0)
+    }
+}
+
+//Followed by the request, only seen when there are too many
+#[derive(Debug)]
+struct RedirectError(u16, Url);
+
+impl std::error::Error for RedirectError {}
+
+impl Display for RedirectError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Too many redirects, last one ({}) to {}", self.0, self.1)
+    }
+}
+
+#[derive(Debug)]
+pub struct StallError(u64, Url);
+
+impl std::error::Error for StallError {}
+
+impl Display for StallError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Transfer slower than {} bytes/s on {}", self.0, self.1)
+    }
+}
+
+#[derive(Debug)]
+pub struct NonMediaError(&'static str, Url);
+
+impl std::error::Error for NonMediaError {}
+
+impl Display for NonMediaError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Received {} instead of media on {}", self.0, self.1)
+    }
+}
+
+impl NonMediaError {
+    //Error pages from CDNs and captive portals, media never starts with these
+    fn check(data: &[u8], url: &Url) -> Result<(), Self> {
+        match data.trim_ascii_start().first() {
+            Some(b'<') => Err(Self("HTML", url.clone())),
+            Some(b'{' | b'[') => Err(Self("JSON", url.clone())),
+            _ => Ok(()),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Args {
     force_https: bool,
     force_ipv4: bool,
     retries: u64,
     timeout: Duration,
-    user_agent: Cow<'static, str>,
+    user_agent: Option<String>,
+    header_profile: HeaderProfile,
+    headers: Vec<Header>,
     socks5: Option<Vec<SocketAddr>>,
     socks5_restrict: Option<Vec<String>>,
+    socks5_auth: Option<Credentials>,
+    tls_provider: TlsProvider,
+    tls_ca: Option<String>,
+    tls_no_verify: bool,
+    resolve: Option<Vec<Resolve>>,
+    interface: Option<Interface>,
+    shared_connection: bool,
+    socket_rcvbuf: Option<usize>,
+    socket_sndbuf: Option<usize>,
+    tcp_keepalive: Option<Duration>,
+    tcp_fastopen: bool,
+    read_chunk_size: usize,
+    max_headers_size: usize,
+    min_speed: Option<u64>,
+    min_speed_time: Duration,
+    http_record: Option<String>,
+    http_replay: Option<String>,
 }
 
 impl Default for Args {
@@ -59,11 +157,30 @@ impl Default for Args {
         Self {
             retries: 3,
             timeout: Duration::from_secs(10),
-            user_agent: constants::USER_AGENT.into(),
+            user_agent: Option::default(),
+            header_profile: HeaderProfile::default(),
+            headers: Vec::default(),
             force_https: bool::default(),
             force_ipv4: bool::default(),
             socks5: Option::default(),
             socks5_restrict: Option::default(),
+            socks5_auth: Option::default(),
+            tls_provider: TlsProvider::default(),
+            tls_ca: Option::default(),
+            tls_no_verify: bool::default(),
+            resolve: Option::default(),
+            interface: Option::default(),
+            shared_connection: bool::default(),
+            socket_rcvbuf: Option::default(),
+            socket_sndbuf: Option::default(),
+            tcp_keepalive: Option::default(),
+            tcp_fastopen: bool::default(),
+            read_chunk_size: 64 * 1024,
+            max_headers_size: 32 * 1024,
+            min_speed: Option::default(),
+            min_speed_time: Duration::from_secs(5),
+            http_record: Option::default(),
+            http_replay: Option::default(),
         }
     }
 }
@@ -74,16 +191,233 @@ impl Parse for Args {
         parser.parse_switch(&mut self.force_ipv4, "--force-ipv4")?;
         parser.parse(&mut self.retries, "--http-retries")?;
         parser.parse_duration(&mut self.timeout, "--http-timeout")?;
-        parser.parse_cow_string(&mut self.user_agent, "--user-agent")?;
+        parser.parse_opt(&mut self.user_agent, "--user-agent")?;
+        parser.parse_fn(
+            &mut self.header_profile,
+            "--header-profile",
+            HeaderProfile::new,
+        )?;
+        parser.parse_repeated(&mut self.headers, "--http-header", str::parse)?;
         parser.parse_fn(&mut self.socks5, "--socks5", |arg| {
             Ok(Some(arg.to_socket_addrs()?.collect()))
         })?;
         parser.parse_comma_list(&mut self.socks5_restrict, "--socks5-restrict")?;
+        parser.parse_fn(&mut self.socks5_auth, "--socks5-auth", Credentials::new)?;
+        parser.parse_fn(&mut self.tls_provider, "--tls-provider", TlsProvider::new)?;
+        parser.parse_opt(&mut self.tls_ca, "--tls-ca")?;
+        parser.parse_switch(&mut self.tls_no_verify, "--tls-no-verify")?;
+        parser.parse_fn(&mut self.interface, "--interface", Interface::new)?;
+        parser.parse_switch(&mut self.shared_connection, "--shared-connection")?;
+        parser.parse_opt(&mut self.socket_rcvbuf, "--socket-rcvbuf")?;
+        parser.parse_opt(&mut self.socket_sndbuf, "--socket-sndbuf")?;
+        parser.parse_fn(&mut self.tcp_keepalive, "--tcp-keepalive", |arg| {
+            let time = Duration::try_from_secs_f64(arg.parse()?)?;
+            ensure!(
+                time.as_secs() > 0,
+                "--tcp-keepalive must be at least 1 second"
+            );
+
+            Ok(Some(time))
+        })?;
+        parser.parse_switch(&mut self.tcp_fastopen, "--tcp-fastopen")?;
+        ensure!(
+            !self.tcp_fastopen || cfg!(any(target_os = "android", target_os = "linux")),
+            "--tcp-fastopen is only supported on Linux"
+        );
+        parser.parse_fn(&mut self.read_chunk_size, "--read-chunk-size", |arg| {
+            let size = arg.parse()?;
+            ensure!(size > 0, "Read chunk size must be greater than 0");
+
+            Ok(size)
+        })?;
+        parser.parse_fn(&mut self.max_headers_size, "--max-headers-size", |arg| {
+            let size = arg.parse()?;
+            ensure!(size >= 1024, "Max headers size must be at least 1024 bytes");
+
+            Ok(size)
+        })?;
+        parser.parse_opt(&mut self.min_speed, "--min-speed")?;
+        parser.parse_duration(&mut self.min_speed_time, "--min-speed-time")?;
+        parser.parse_opt(&mut self.http_record, "--http-record")?;
+        parser.parse_opt(&mut self.http_replay, "--http-replay")?;
+
+        ensure!(
+            !(self.http_record.is_some() && self.http_replay.is_some()),
+            "--http-record and --http-replay cannot be used together"
+        );
+        parser.parse_fn(&mut self.resolve, "--resolve", |arg| {
+            Ok(Some(
+                arg.split(',').map(str::parse).collect::<Result<_, _>>()?,
+            ))
+        })?;
 
         Ok(())
     }
 }
 
+//HOST:PORT:ADDRESS, IPv6 addresses in brackets
+#[derive(Debug, Clone)]
+struct Resolve {
+    host: String,
+    port: u16,
+    addr: IpAddr,
+}
+
+impl FromStr for Resolve {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(3, ':');
+        let (Some(host), Some(port), Some(addr)) = (split.next(), split.next(), split.next())
+        else {
+            bail!("Invalid resolve entry, expected <HOST:PORT:ADDRESS>: {s}");
+        };
+
+        Ok(Self {
+            host: host.to_owned(),
+            port: port.parse().context("Invalid port in resolve entry")?,
+            addr: addr
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse()
+                .context("Invalid address in resolve entry")?,
+        })
+    }
+}
+
+//[HOST=]NAME: VALUE, only sent to HOST and its subdomains if it's given
+#[derive(Debug, Clone)]
+struct Header {
+    host: Option<String>,
+    line: String,
+}
+
+impl FromStr for Header {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((name, value)) = s.split_once(':') else {
+            bail!("Invalid HTTP header, expected <[HOST=]NAME: VALUE>: {s}");
+        };
+
+        //Header names can't contain '=', so it always separates the host
+        let (host, name) = name
+            .split_once('=')
+            .map_or((None, name), |(h, n)| (Some(h.to_ascii_lowercase()), n));
+
+        let (name, value) = (name.trim(), value.trim());
+        ensure!(
+            !name.is_empty() && !name.contains(char::is_whitespace),
+            "Invalid HTTP header name: {name}"
+        );
+        ensure!(
+            !value.contains(['\r', '\n']),
+            "HTTP header value can't contain line breaks: {name}"
+        );
+
+        Ok(Self {
+            host,
+            line: format!("{name}: {value}\r\n"),
+        })
+    }
+}
+
+impl Header {
+    fn applies_to(&self, host: &str) -> bool {
+        self.host.as_deref().is_none_or(|h| is_domain(host, h))
+    }
+}
+
+//Source address or network interface of outgoing connections
+#[derive(Debug, Clone)]
+enum Interface {
+    Addr(IpAddr),
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    Name(String),
+}
+
+impl Interface {
+    fn new(arg: &str) -> Result<Option<Self>> {
+        if let Ok(addr) = arg.trim_start_matches('[').trim_end_matches(']').parse() {
+            return Ok(Some(Self::Addr(addr)));
+        }
+
+        #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+        return Ok(Some(Self::Name(arg.to_owned())));
+
+        #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+        bail!("Interface names are only supported on Linux, use the interface's address instead");
+    }
+
+    //A source address only works for its own address family
+    const fn can_reach(&self, addr: &SocketAddr) -> bool {
+        match self {
+            Self::Addr(source) => source.is_ipv4() == addr.is_ipv4(),
+            #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+            Self::Name(_) => true,
+        }
+    }
+}
+
+#[derive(Default, Copy, Clone, Debug)]
+enum TlsProvider {
+    #[cfg(feature = "ring")]
+    #[default]
+    Ring,
+
+    #[cfg(feature = "aws-lc-rs")]
+    #[cfg_attr(not(feature = "ring"), default)]
+    AwsLcRs,
+}
+
+impl TlsProvider {
+    fn new(arg: &str) -> Result<Self> {
+        match arg {
+            #[cfg(feature = "ring")]
+            "ring" => Ok(Self::Ring),
+            #[cfg(feature = "aws-lc-rs")]
+            "aws-lc-rs" => Ok(Self::AwsLcRs),
+            _ => bail!("Invalid or unsupported TLS provider"),
+        }
+    }
+
+    fn crypto_provider(self) -> CryptoProvider {
+        match self {
+            #[cfg(feature = "ring")]
+            Self::Ring => rustls::crypto::ring::default_provider(),
+            #[cfg(feature = "aws-lc-rs")]
+            Self::AwsLcRs => rustls::crypto::aws_lc_rs::default_provider(),
+        }
+    }
+}
+
+//IMF-fixdate from RFC 9110, e.g. Sun, 06 Nov 1994 08:49:37 GMT
+fn http_date(date: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+
+    let mut parts = date.split_whitespace().skip(1); //weekday
+    let day = parts.next()?.parse::<u64>().ok()?;
+    let month = parts.next()?.to_ascii_lowercase();
+    let month = MONTHS.iter().position(|m| *m == month)? as u64 + 1;
+    let year = parts.next()?.parse::<u64>().ok()?;
+
+    let mut time = parts.next()?.split(':').map(|p| p.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+
+    //Days since the unix epoch from a civil date (proleptic gregorian), see stats::format_date_time
+    let year = year.checked_sub(u64::from(month <= 2))?;
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * if month > 2 { month - 3 } else { month + 9 } + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146_097 + day_of_era).checked_sub(719_468)?;
+
+    let secs = days * 86_400 + hours * 3600 + minutes * 60 + seconds;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
 #[derive(Copy, Clone)]
 pub enum Method {
     Get,
@@ -101,14 +435,25 @@ impl Display for Method {
     }
 }
 
+//Cheap to clone, clones share the same config and connections across threads
 #[derive(Clone)]
 pub struct Agent {
     args: Arc<Args>,
     tls_config: Arc<ClientConfig>,
+    pool: Arc<Pool>,
+    buffers: Arc<BufferPool>,
+    cookies: Arc<CookieJar>,
+    shared: Option<Arc<SharedConnection>>,
+    session: Option<Arc<Session>>,
 }
 
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Agent>();
+};
+
 impl Agent {
-    pub fn new(args: Args) -> Self {
+    pub fn new(args: Args) -> Result<Self> {
         let mut roots = RootCertStore::empty();
         let res = rustls_native_certs::load_native_certs();
 
@@ -123,14 +468,56 @@ impl Agent {
             }
         }
 
-        Self {
-            args: Arc::new(args),
-            tls_config: Arc::new(
-                ClientConfig::builder()
-                    .with_root_certificates(Arc::new(roots))
-                    .with_no_client_auth(),
-            ),
+        //Private CAs of self-hosted servers, on top of the system ones
+        if let Some(path) = &args.tls_ca {
+            let certs = CertificateDer::pem_file_iter(path)
+                .with_context(|| format!("Failed to open CA bundle: {path}"))?
+                .collect::<Result<Vec<_>, _>>()
+                .context("Failed to parse CA bundle")?;
+
+            let (added, _) = roots.add_parsable_certificates(certs);
+            ensure!(
+                added > 0,
+                "No valid certificates found in CA bundle: {path}"
+            );
+            debug!("Added {added} certificates from {path}");
         }
+
+        let provider = Arc::new(args.tls_provider.crypto_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .context("Failed to create TLS config")?;
+
+        let mut tls_config = if args.tls_no_verify {
+            warn!("TLS certificates of non-Twitch hosts won't be verified");
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PartialVerifier::new(roots, provider)?))
+        } else {
+            builder.with_root_certificates(Arc::new(roots))
+        }
+        .with_no_client_auth();
+        tls_config.enable_early_data = true;
+
+        let session = match (&args.http_record, &args.http_replay) {
+            (Some(dir), _) => Some(Arc::new(Session::record(dir)?)),
+            (None, Some(dir)) => Some(Arc::new(Session::replay(dir)?)),
+            (None, None) => None,
+        };
+
+        Ok(Self {
+            session,
+            pool: Arc::default(),
+            buffers: Arc::default(),
+            cookies: Arc::default(),
+            shared: args.shared_connection.then(Arc::default),
+            args: Arc::new(args),
+            tls_config: Arc::new(tls_config),
+        })
+    }
+
+    pub fn header_profile(&self) -> HeaderProfile {
+        self.args.header_profile
     }
 
     pub fn text(&self) -> TextRequest {
@@ -161,8 +548,44 @@ impl Connection {
     pub const fn new(url: Url, request: TextRequest) -> Self {
         Self { url, request }
     }
+}
+
+//The domain itself or any subdomain of it
+fn is_domain(host: &str, domain: &str) -> bool {
+    let host = host.trim_end_matches('.');
+    host.eq_ignore_ascii_case(domain)
+        || host
+            .to_ascii_lowercase()
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
 
-    pub fn text(&mut self) -> Result<&str> {
-        self.request.text(Method::Get, &self.url)
+fn is_twitch_host(host: &str) -> bool {
+    constants::TWITCH_DOMAINS
+        .iter()
+        .any(|domain| is_domain(host, domain))
+}
+
+pub fn encode_base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or_default(),
+            chunk.get(2).copied().unwrap_or_default(),
+        ];
+        let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
     }
+
+    encoded
 }
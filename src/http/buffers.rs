@@ -0,0 +1,86 @@
+use std::{
+    mem,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+//Byte buffers of dropped requests, shared by every request made through clones of an agent
+#[derive(Default)]
+pub struct BufferPool {
+    free: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    const MAX_FREE: usize = 16;
+
+    //Zeroed buffer of the given length, from the pool if one is big enough
+    pub fn take(self: &Arc<Self>, len: usize) -> Buffer {
+        let buf = {
+            let mut free = self.lock();
+
+            //Smallest one that fits, so small buffers don't take the big body ones
+            free.iter()
+                .enumerate()
+                .filter(|(_, b)| b.capacity() >= len)
+                .min_by_key(|(_, b)| b.capacity())
+                .map(|(pos, _)| pos)
+                .map(|pos| free.swap_remove(pos))
+        };
+
+        let buf = buf.map_or_else(
+            || vec![0u8; len],
+            |mut buf| {
+                buf.clear();
+                buf.resize(len, 0);
+                buf
+            },
+        );
+
+        Buffer {
+            buf,
+            pool: self.clone(),
+        }
+    }
+
+    fn put(&self, buf: Vec<u8>) {
+        if buf.capacity() == 0 {
+            return;
+        }
+
+        let mut free = self.lock();
+        if free.len() < Self::MAX_FREE {
+            free.push(buf);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Vec<u8>>> {
+        //Nothing in the pool can be left half updated
+        self.free.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+//Goes back to the pool when dropped
+pub struct Buffer {
+    buf: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl Deref for Buffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf
+    }
+}
+
+impl DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buf
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        self.pool.put(mem::take(&mut self.buf));
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, SystemTime},
+};
+
+use log::debug;
+
+use super::{Scheme, http_date};
+
+//Cookies set by servers, shared by every request made through clones of an agent
+#[derive(Default)]
+pub struct CookieJar {
+    cookies: Mutex<Vec<Cookie>>,
+}
+
+struct Cookie {
+    name: String,
+    value: String,
+    domain: String,
+    host_only: bool, //no Domain attribute, only sent back to the same host
+    secure: bool,
+    expires: Option<SystemTime>, //session cookie if none
+}
+
+impl Cookie {
+    //name=value; Domain=example.com; Max-Age=60; Secure; ...
+    fn parse(host: &str, line: &str) -> Option<Self> {
+        let mut attributes = line.split(';');
+        let (name, value) = attributes.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut cookie = Self {
+            name: name.to_owned(),
+            value: value.trim().trim_matches('"').to_owned(),
+            domain: host.to_ascii_lowercase(),
+            host_only: true,
+            secure: false,
+            expires: None,
+        };
+
+        let mut max_age = None;
+        for attribute in attributes {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "domain" if !value.is_empty() => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    if !Self::domain_matches(&cookie.domain, &domain) {
+                        debug!("Ignoring cookie {name} for foreign domain {domain}");
+                        return None;
+                    }
+
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+                "max-age" => max_age = value.parse::<i64>().ok(),
+                "expires" => cookie.expires = cookie.expires.or_else(|| http_date(value)),
+                "secure" => cookie.secure = true,
+                _ => (), //paths aren't tracked, requests only go to a few endpoints per host
+            }
+        }
+
+        //Max-Age takes precedence over Expires
+        if let Some(max_age) = max_age {
+            cookie.expires = Some(
+                u64::try_from(max_age).map_or(SystemTime::UNIX_EPOCH, |secs| {
+                    SystemTime::now() + Duration::from_secs(secs)
+                }),
+            );
+        }
+
+        Some(cookie)
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|e| e <= now)
+    }
+
+    fn matches(&self, host: &str, scheme: Scheme) -> bool {
+        if self.secure && scheme != Scheme::Https {
+            return false;
+        }
+
+        if self.host_only {
+            self.domain.eq_ignore_ascii_case(host)
+        } else {
+            Self::domain_matches(host, &self.domain)
+        }
+    }
+
+    fn domain_matches(host: &str, domain: &str) -> bool {
+        let Some(prefix_len) = host.len().checked_sub(domain.len()) else {
+            return false;
+        };
+
+        let (prefix, suffix) = host.as_bytes().split_at(prefix_len);
+        suffix.eq_ignore_ascii_case(domain.as_bytes())
+            && (prefix.is_empty() || prefix.ends_with(b"."))
+    }
+}
+
+impl CookieJar {
+    const MAX_COOKIES: usize = 64;
+
+    //Headers of the response as sent, cookie values are case sensitive
+    pub fn store(&self, host: &str, headers: &[u8]) {
+        let headers = String::from_utf8_lossy(headers);
+        let set_cookies = headers.lines().filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("set-cookie")
+                .then(|| Cookie::parse(host, value))?
+        });
+
+        let now = SystemTime::now();
+        let mut cookies = self.lock();
+        for cookie in set_cookies {
+            cookies.retain(|c| {
+                !(c.name == cookie.name
+                    && c.domain == cookie.domain
+                    && c.host_only == cookie.host_only)
+            });
+
+            //Expired cookies are how servers delete them
+            if cookie.is_expired(now) {
+                debug!("Removed cookie {} for {}", cookie.name, cookie.domain);
+            } else {
+                debug!("Storing cookie {} for {}", cookie.name, cookie.domain);
+                cookies.push(cookie);
+            }
+        }
+
+        cookies.retain(|c| !c.is_expired(now));
+        if cookies.len() > Self::MAX_COOKIES {
+            let excess = cookies.len() - Self::MAX_COOKIES;
+            cookies.drain(..excess);
+        }
+    }
+
+    //Appends the Cookie: header line for the host, nothing if there's nothing to send
+    pub fn write_header(&self, request: &mut Vec<u8>, host: &str, scheme: Scheme) {
+        let now = SystemTime::now();
+        let cookies = self.lock();
+        let mut matching = cookies
+            .iter()
+            .filter(|c| !c.is_expired(now) && c.matches(host, scheme))
+            .peekable();
+
+        if matching.peek().is_none() {
+            return;
+        }
+
+        request.extend_from_slice(b"Cookie: ");
+        for (i, cookie) in matching.enumerate() {
+            if i > 0 {
+                request.extend_from_slice(b"; ");
+            }
+
+            request.extend_from_slice(cookie.name.as_bytes());
+            request.push(b'=');
+            request.extend_from_slice(cookie.value.as_bytes());
+        }
+        drop(cookies);
+
+        request.extend_from_slice(b"\r\n");
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Cookie>> {
+        self.cookies.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -21,6 +21,10 @@ impl<R: Read> Read for Decoder<R> {
         match &mut self.kind {
             Encoding::Unencoded(reader, length) => {
                 let consumed = reader.take(*length - self.consumed).read(buf)?;
+                if consumed == 0 && self.consumed < *length && !buf.is_empty() {
+                    //Connection closed early, the body can be resumed
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
                 self.consumed += consumed as u64;
 
                 Ok(consumed)
@@ -41,24 +45,38 @@ impl<R: Read> Read for Decoder<R> {
 }
 
 impl<R: Read> Decoder<R> {
+    //Unencoded bodies can be read around the decoder, what's read is passed to consume
+    #[cfg(target_os = "linux")]
+    pub const fn unencoded(&mut self) -> Option<(&mut R, u64)> {
+        match &mut self.kind {
+            Encoding::Unencoded(reader, length) => Some((reader, *length - self.consumed)),
+            _ => None,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub const fn consume(&mut self, len: u64) {
+        self.consumed += len;
+    }
+
     pub fn new(reader: R, headers: &str) -> Result<Self> {
         let mut content_length = None;
         let mut is_chunked = false;
         let mut is_gzipped = false;
 
+        //Names and these values are case insensitive
         for line in headers.lines() {
-            let mut split = line.split_whitespace();
-            match split.next() {
-                Some("content-encoding:") => {
-                    is_gzipped = split.next().is_some_and(|h| h == "gzip");
-                }
-                Some("transfer-encoding:") => {
-                    is_chunked = split.next().is_some_and(|h| h == "chunked");
-                }
-                Some("content-length:") => {
-                    content_length = split.next().and_then(|h| h.parse().ok());
-                }
-                _ => (),
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-encoding") {
+                is_gzipped = value.eq_ignore_ascii_case("gzip");
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                is_chunked = value.eq_ignore_ascii_case("chunked");
+            } else if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse().ok();
             }
         }
 
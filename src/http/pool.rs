@@ -0,0 +1,59 @@
+use std::{
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use super::{Scheme, request::Transport};
+
+//Idle keep-alive connections, shared by every request made through clones of an agent
+#[derive(Default)]
+pub struct Pool {
+    idle: Mutex<Vec<Idle>>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Key {
+    pub host_hash: u64,
+    pub scheme: Scheme,
+    pub tuned: bool, //segment sockets have bigger buffers
+}
+
+struct Idle {
+    key: Key,
+    transport: Transport,
+    since: Instant,
+}
+
+impl Pool {
+    const MAX_IDLE: usize = 8;
+
+    //Servers usually close idle connections on their own after this
+    const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub fn take(&self, key: Key) -> Option<Transport> {
+        let mut idle = self.lock();
+        idle.retain(|i| i.since.elapsed() < Self::IDLE_TIMEOUT);
+
+        //Most recently used first, it's the least likely to have been closed
+        let pos = idle.iter().rposition(|i| i.key == key)?;
+        Some(idle.remove(pos).transport)
+    }
+
+    pub fn put(&self, key: Key, transport: Transport) {
+        let mut idle = self.lock();
+        if idle.len() >= Self::MAX_IDLE {
+            idle.remove(0);
+        }
+
+        idle.push(Idle {
+            key,
+            transport,
+            since: Instant::now(),
+        });
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Idle>> {
+        //Nothing in the pool can be left half updated
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,92 @@
+use anyhow::{Result, bail};
+
+use super::{is_domain, is_twitch_host};
+use crate::constants;
+
+macro_rules! chrome_headers {
+    () => {
+        "sec-ch-ua: \"Chromium\";v=\"147\", \"Google Chrome\";v=\"147\", \"Not.A/Brand\";v=\"99\"\r\n\
+         sec-ch-ua-mobile: ?0\r\n\
+         Accept: */*\r\n\
+         Accept-Language: en-US,en;q=0.9\r\n"
+    };
+}
+
+//What the web player's requests look like from www.twitch.tv, other hosts would see a
+//browser that claims to be on Twitch
+macro_rules! twitch_headers {
+    ($site:literal) => {
+        concat!(
+            "Origin: https://www.twitch.tv\r\n\
+             Referer: https://www.twitch.tv/\r\n\
+             Sec-Fetch-Dest: empty\r\n\
+             Sec-Fetch-Mode: cors\r\n\
+             Sec-Fetch-Site: ",
+            $site,
+            "\r\n"
+        )
+    };
+}
+
+//Headers and usher parameters sent with every request so they stay consistent with the user agent
+#[derive(Default, Copy, Clone, Debug)]
+pub enum HeaderProfile {
+    #[default]
+    FirefoxWindows,
+    FirefoxLinux,
+    ChromeWindows,
+    ChromeLinux,
+}
+
+impl HeaderProfile {
+    pub fn new(arg: &str) -> Result<Self> {
+        match arg {
+            "firefox-windows" => Ok(Self::FirefoxWindows),
+            "firefox-linux" => Ok(Self::FirefoxLinux),
+            "chrome-windows" => Ok(Self::ChromeWindows),
+            "chrome-linux" => Ok(Self::ChromeLinux),
+            _ => bail!("Invalid header profile"),
+        }
+    }
+
+    pub const fn user_agent(self) -> &'static str {
+        match self {
+            Self::FirefoxWindows => constants::USER_AGENT,
+            Self::FirefoxLinux => constants::FIREFOX_LINUX_USER_AGENT,
+            Self::ChromeWindows => constants::CHROME_WINDOWS_USER_AGENT,
+            Self::ChromeLinux => constants::CHROME_LINUX_USER_AGENT,
+        }
+    }
+
+    pub const fn headers(self) -> &'static str {
+        match self {
+            Self::FirefoxWindows | Self::FirefoxLinux => {
+                "Accept: */*\r\n\
+                 Accept-Language: en-US,en;q=0.5\r\n"
+            }
+            Self::ChromeWindows => {
+                concat!("sec-ch-ua-platform: \"Windows\"\r\n", chrome_headers!())
+            }
+            Self::ChromeLinux => concat!("sec-ch-ua-platform: \"Linux\"\r\n", chrome_headers!()),
+        }
+    }
+
+    //Only for Twitch hosts, gql and id are on the player's own site
+    pub fn twitch_headers(host: &str) -> &'static str {
+        if !is_twitch_host(host) {
+            ""
+        } else if is_domain(host, "twitch.tv") {
+            twitch_headers!("same-site")
+        } else {
+            twitch_headers!("cross-site")
+        }
+    }
+
+    //Codecs the browser's media stack reports to usher
+    pub const fn codecs(self) -> &'static str {
+        match self {
+            Self::FirefoxWindows | Self::FirefoxLinux => "av1,h264",
+            Self::ChromeWindows | Self::ChromeLinux => "av1,h265,h264",
+        }
+    }
+}
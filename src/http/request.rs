@@ -1,17 +1,35 @@
+#[cfg(target_os = "linux")]
+use std::os::fd::OwnedFd;
 use std::{
-    fmt::Arguments,
+    fmt::{Arguments, Write as _},
+    fs,
     hash::{DefaultHasher, Hasher},
     io::{self, Read, Write},
     mem,
     net::{SocketAddr, TcpStream, ToSocketAddrs},
-    str,
+    str, thread,
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::{Context, Result, bail, ensure};
 use log::{debug, error};
 use rustls::{ClientConnection, StreamOwned};
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
 
-use super::{Agent, Method, Scheme, StatusError, Url, decoder::Decoder, socks5};
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use nix::sys::socket::{setsockopt, sockopt::TcpFastOpenConnect};
+#[cfg(target_os = "linux")]
+use nix::{
+    errno::Errno,
+    fcntl::{self, SpliceFFlags},
+};
+
+use super::{
+    Agent, Args, DeadlineError, Interface, Method, NonMediaError, RedirectError, Scheme,
+    StallError, StatusError, Url, buffers::Buffer, decoder::Decoder, http_date, pool::Key,
+    profile::HeaderProfile, session::Session, socks5,
+};
+use crate::stats::{ConnectTiming, STATS};
 
 pub struct Request<W: Write> {
     writer: W,
@@ -20,27 +38,59 @@ pub struct Request<W: Write> {
     scheme: Scheme,
     host_hash: u64,
 
-    headers_buf: Box<[u8]>,
-    decode_buf: Box<[u8]>,
+    send_buf: Buffer,
+    headers_buf: Buffer, //grows up to --max-headers-size
+    decode_buf: Buffer,
+    written: u64,
+    write_time: Duration,
 
+    tune_socket: bool,
+    check_media: bool,
+    body_timeout: Option<Duration>,
+    #[cfg(target_os = "linux")]
+    splice: Option<OwnedFd>, //see splice_to
+    capture: Option<Vec<u8>>,
+    redirected: Option<Url>,       //final URL of the last call
+    response_headers: String,      //of the last response, as sent
+    retry_after: Option<Duration>, //from the last 429 response
+    resume_from: u64,              //body bytes already written when a retry starts
     retries: u64,
     agent: Agent,
 }
 
 impl<W: Write> Request<W> {
+    const SEND_BUF_SIZE: usize = 1024;
     const HEADERS_BUF_SIZE: usize = 4 * 1024;
-    const DECODE_BUF_SIZE: usize = 16 * 1024;
+
+    //Longer waits aren't worth blocking the stream for, the error is returned instead
+    const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
 
     pub fn new(writer: W, agent: Agent) -> Self {
         Self {
             writer,
-            headers_buf: vec![0u8; Self::HEADERS_BUF_SIZE].into_boxed_slice(),
-            decode_buf: vec![0u8; Self::DECODE_BUF_SIZE].into_boxed_slice(),
+            //Smallest first so each gets the best fitting buffer from the pool
+            send_buf: agent.buffers.take(Self::SEND_BUF_SIZE),
+            headers_buf: agent
+                .buffers
+                .take(Self::HEADERS_BUF_SIZE.min(agent.args.max_headers_size)),
+            decode_buf: agent.buffers.take(agent.args.read_chunk_size),
+            capture: matches!(agent.session.as_deref(), Some(Session::Record(_))).then(Vec::new),
             retries: agent.args.retries,
             agent,
+            redirected: Option::default(),
+            response_headers: String::default(),
+            retry_after: Option::default(),
+            resume_from: u64::default(),
             stream: Option::default(),
             scheme: Scheme::default(),
             host_hash: u64::default(),
+            written: u64::default(),
+            write_time: Duration::default(),
+            tune_socket: true,
+            check_media: true,
+            body_timeout: Option::default(),
+            #[cfg(target_os = "linux")]
+            splice: Option::default(),
         }
     }
 
@@ -48,6 +98,33 @@ impl<W: Write> Request<W> {
         self.writer
     }
 
+    //Keeps the connection and settings, only the body goes somewhere else
+    pub fn with_writer<T: Write>(self, writer: T) -> Request<T> {
+        Request {
+            writer,
+            stream: self.stream,
+            scheme: self.scheme,
+            host_hash: self.host_hash,
+            send_buf: self.send_buf,
+            headers_buf: self.headers_buf,
+            decode_buf: self.decode_buf,
+            written: self.written,
+            write_time: self.write_time,
+            tune_socket: self.tune_socket,
+            check_media: self.check_media,
+            body_timeout: self.body_timeout,
+            #[cfg(target_os = "linux")]
+            splice: self.splice,
+            capture: self.capture,
+            redirected: self.redirected,
+            response_headers: self.response_headers,
+            retry_after: self.retry_after,
+            resume_from: self.resume_from,
+            retries: self.retries,
+            agent: self.agent,
+        }
+    }
+
     pub const fn get_ref(&self) -> &W {
         &self.writer
     }
@@ -56,13 +133,183 @@ impl<W: Write> Request<W> {
         &mut self.writer
     }
 
+    //Bytes of the response body written by the last call
+    pub const fn written(&self) -> u64 {
+        self.written
+    }
+
+    //Time spent writing the response body of the last call
+    pub const fn write_time(&self) -> Duration {
+        self.write_time
+    }
+
+    pub const fn agent(&self) -> &Agent {
+        &self.agent
+    }
+
+    //Where the last call ended up if it was redirected, for resolving relative URLs
+    pub const fn redirected(&self) -> Option<&Url> {
+        self.redirected.as_ref()
+    }
+
+    //Header of the last response, names are case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.response_headers.lines().skip(1).find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+
+    //Server time of the last response, only accurate to the second
+    pub fn date(&self) -> Option<SystemTime> {
+        http_date(self.header("date")?)
+    }
+
+    //Aborts the response body of following calls if it takes longer than this.
+    //Counted from the response headers, live segments are held by the server until they exist.
+    pub const fn set_body_timeout(&mut self, timeout: Option<Duration>) {
+        self.body_timeout = timeout;
+    }
+
+    //Unencoded bodies of following calls over plain HTTP are moved from the socket to this
+    //pipe by the kernel, see Writer::pipe. Segments from Twitch are always encrypted.
+    #[cfg(target_os = "linux")]
+    pub fn splice_to(&mut self, pipe: Option<OwnedFd>) {
+        self.splice = pipe.filter(|_| self.capture.is_none());
+    }
+
     pub fn call(&mut self, method: Method, url: &Url) -> Result<()> {
         self.call_impl(method, url, None)
     }
 
     fn call_impl(&mut self, method: Method, url: &Url, args: Option<Arguments>) -> Result<()> {
+        if url.scheme == Scheme::File {
+            return self.call_file(method, url);
+        }
+
+        let session = self.agent.session.clone();
+        if let Some(Session::Replay(replayer)) = session.as_deref() {
+            let (status, body) = replayer.next(method, url)?;
+            if status != 200 {
+                return Err(StatusError(status, url.clone()).into());
+            }
+
+            return self.write_body(&body[..]);
+        }
+
+        let result = self.call_redirected(method, url, args);
+        if let Some(Session::Record(recorder)) = session.as_deref() {
+            let status = match &result {
+                Ok(()) => Some(200),
+                Err(e) => e.downcast_ref::<StatusError>().map(|e| e.0),
+            };
+
+            let body = self.capture.as_mut().map(mem::take).unwrap_or_default();
+            if let Some(status) = status
+                && let Err(e) = recorder.save(method, url, status, &body)
+            {
+                error!("Failed to record HTTP response: {e}");
+            }
+        }
+
+        result
+    }
+
+    //Some playlist proxies are behind redirects
+    fn call_redirected(
+        &mut self,
+        mut method: Method,
+        url: &Url,
+        mut args: Option<Arguments>,
+    ) -> Result<()> {
+        const MAX_REDIRECTS: usize = 5;
+
+        self.redirected = None;
+        let mut result = self.call_net(method, url, args);
+        for _ in 0..MAX_REDIRECTS {
+            let Some(RedirectError(code, location)) =
+                result.as_ref().err().and_then(|e| e.downcast_ref())
+            else {
+                return result;
+            };
+
+            debug!("Redirected ({code}) to {location}");
+            if matches!(method, Method::Post) && !matches!(code, 307 | 308) {
+                method = Method::Get;
+                args = None;
+            }
+
+            let location = location.clone();
+            result = self.call_net(method, &location, args);
+            self.redirected = Some(location);
+        }
+
+        result
+    }
+
+    fn call_net(&mut self, method: Method, url: &Url, args: Option<Arguments>) -> Result<()> {
         let host = url.host()?;
         let hash = Self::hash(host);
+
+        let Some(lease) = self.agent.shared.as_ref().map(|s| s.lease(hash)) else {
+            return self.call_pooled(method, url, host, hash, args);
+        };
+
+        (self.stream, self.scheme) = lease.take();
+        self.host_hash = hash;
+
+        let result = self.call_host(method, url, host, hash, args);
+        if result.is_ok() {
+            lease.put(self.stream.take(), self.scheme);
+        } else {
+            self.stream = None;
+        }
+
+        result
+    }
+
+    //Connections go back to the agent's pool after each response, so other requests can reuse them
+    fn call_pooled(
+        &mut self,
+        method: Method,
+        url: &Url,
+        host: &str,
+        hash: u64,
+        args: Option<Arguments>,
+    ) -> Result<()> {
+        let key = Key {
+            host_hash: hash,
+            scheme: url.scheme,
+            tuned: self.tune_socket,
+        };
+
+        if self.stream.is_none()
+            && let Some(transport) = self.agent.pool.take(key)
+        {
+            debug!("Reusing connection to {host}");
+            self.stream = Some(transport);
+            self.scheme = url.scheme;
+            self.host_hash = hash;
+        }
+
+        let result = self.call_host(method, url, host, hash, args);
+        if result.is_ok()
+            && let Some(transport) = self.stream.take()
+        {
+            self.agent.pool.put(key, transport);
+        }
+
+        result
+    }
+
+    fn call_host(
+        &mut self,
+        method: Method,
+        url: &Url,
+        host: &str,
+        hash: u64,
+        args: Option<Arguments>,
+    ) -> Result<()> {
         if self.stream.is_none() || self.host_hash != hash || self.scheme != url.scheme {
             self.connect(url, host, hash)?;
         }
@@ -72,14 +319,39 @@ impl<W: Write> Request<W> {
             match self.converse(method, host, url, args) {
                 Ok(()) => break,
                 Err(error) if retries < self.retries && Self::should_retry(&error) => {
-                    if retries > 0 {
+                    //Retrying right away after a 429 can get the IP temporarily banned
+                    if let Some(delay) = self.retry_after.take() {
+                        if delay > Self::MAX_RETRY_AFTER {
+                            self.stream = None;
+                            return Err(error);
+                        }
+
+                        error!("http: {error}, retrying in {}s...", delay.as_secs());
+                        thread::sleep(delay);
+                    } else if retries > 0 {
                         error!("http: {error}, retrying...");
                     }
 
                     retries += 1;
                     self.connect(url, host, hash)?;
+
+                    //Continue the body instead of writing it again from the start
+                    if matches!(method, Method::Get) {
+                        self.resume_from = self.written;
+                    }
+                }
+                Err(e) => {
+                    //Rest of the body is still in the stream
+                    if e.is::<DeadlineError>()
+                        || e.is::<StallError>()
+                        || e.is::<NonMediaError>()
+                        || e.is::<RedirectError>()
+                    {
+                        self.stream = None;
+                    }
+
+                    return Err(e);
                 }
-                Err(e) => return Err(e),
             }
         }
 
@@ -94,80 +366,313 @@ impl<W: Write> Request<W> {
         url: &Url,
         args: Option<Arguments>,
     ) -> Result<()> {
-        let mut stream = self.stream.as_mut().expect("Missing stream while writing");
-        write!(
-            stream,
-            "{method} /{path} HTTP/1.1\r\n\
-             Host: {host}\r\n\
-             User-Agent: {user_agent}\r\n\
-             Accept: */*\r\n\
-             Accept-Language: en-US\r\n\
-             Accept-Encoding: gzip\r\n\
-             Connection: keep-alive\r\n\
-             {args}",
-            path = url.path()?,
-            user_agent = &self.agent.args.user_agent,
-            args = args.unwrap_or_else(|| format_args!("\r\n"))
-        )?;
-        stream.flush()?;
-
-        //Read response headers and separate headers from body if needed
-        let mut written = 0;
-        let (headers, body) = loop {
-            let read = stream.read(&mut self.headers_buf[written..])?;
-            if read == 0 {
-                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        let resume = mem::take(&mut self.resume_from);
+        self.written = resume;
+        self.retry_after = None;
+        self.response_headers.clear();
+        if resume == 0 {
+            self.write_time = Duration::ZERO;
+            if let Some(capture) = &mut self.capture {
+                capture.clear();
             }
-            written += read;
-
-            if let Some((headers, body)) = self
-                .headers_buf
-                .windows(4)
-                .position(|w| w == b"\r\n\r\n")
-                .and_then(|p| {
-                    self.headers_buf[..written].split_at_mut_checked(p + 4 /* pass \r\n\r\n */)
-                })
-            {
-                headers.make_ascii_lowercase();
-                break (str::from_utf8(headers)?, body);
-            }
-        };
-        debug!("Response:\n{headers}");
+        }
 
-        let code = headers
-            .split_whitespace()
-            .nth(1)
-            .and_then(|s| s.parse().ok())
-            .context("Failed to parse HTTP status code")?;
+        self.write_request(method, host, url, args, resume)?;
+        let (code, body_start, filled) = self.read_head()?;
+        debug!("Response:\n{}", self.response_headers);
 
-        if code != 200 {
-            return Err(StatusError(code, url.clone()).into());
+        self.agent
+            .cookies
+            .store(host, self.response_headers.as_bytes());
+
+        self.check_status(code, url, resume)?;
+
+        //Servers without range support send the whole body again
+        let mut skip = if code == 200 { resume } else { 0 };
+        if resume > 0 {
+            debug!("Resuming body at {resume} bytes (status {code})");
         }
 
         match method {
             Method::Get | Method::Post => {
-                let mut decoder = Decoder::new(body.chain(&mut stream), headers)?;
+                let mut watchdog = SpeedWatchdog::new(&self.agent.args);
+                let stream = self.stream.as_mut().expect("Missing stream while reading");
+                let mut deadline = self
+                    .body_timeout
+                    .map(|t| BodyDeadline::new(stream, t, self.agent.args.timeout))
+                    .transpose()?;
+
+                let body = &self.headers_buf[body_start..filled];
+                let mut decoder = Decoder::new(body.chain(&mut *stream), &self.response_headers)?;
+
                 loop {
-                    let read = decoder.read(&mut self.decode_buf)?;
+                    if let Some(deadline) = &mut deadline
+                        && !deadline.shorten_timeout()?
+                    {
+                        return Err(DeadlineError(url.clone()).into());
+                    }
+
+                    //The first chunk is always read to check it, resumed bodies need to see
+                    //every byte
+                    #[cfg(target_os = "linux")]
+                    let spliced = if self.written > 0 && skip == 0 {
+                        splice(&mut decoder, &mut self.splice, self.decode_buf.len())
+                    } else {
+                        Ok(None)
+                    };
+                    #[cfg(not(target_os = "linux"))]
+                    let spliced = Ok(None);
+
+                    let is_spliced = matches!(spliced, Ok(Some(_)));
+                    let read = match spliced
+                        .transpose()
+                        .unwrap_or_else(|| decoder.read(&mut self.decode_buf))
+                    {
+                        Err(e) if deadline.as_ref().is_some_and(BodyDeadline::is_reached) => {
+                            debug!("Read interrupted by deadline: {e}");
+                            return Err(DeadlineError(url.clone()).into());
+                        }
+                        read => read?,
+                    };
+
                     if read == 0 {
+                        if let Some(deadline) = deadline {
+                            deadline.restore_timeout()?;
+                        }
+
                         break Ok(());
                     }
 
-                    self.writer.write_all(&self.decode_buf[..read])?;
+                    if let Some(watchdog) = &mut watchdog
+                        && watchdog.is_stalled(read)
+                    {
+                        return Err(StallError(watchdog.min_speed, url.clone()).into());
+                    }
+
+                    if is_spliced {
+                        self.written += read as u64;
+                        continue;
+                    }
+
+                    let skipped = usize::try_from(skip).map_or(read, |s| s.min(read));
+                    skip -= skipped as u64;
+
+                    let data = &self.decode_buf[skipped..read];
+                    if data.is_empty() {
+                        continue;
+                    }
+
+                    if self.check_media && self.written == 0 {
+                        NonMediaError::check(data, url)?;
+                    }
+
+                    let time = Instant::now();
+                    self.writer.write_all(data)?;
+                    self.write_time += time.elapsed();
+                    self.written += data.len() as u64;
+
+                    if let Some(capture) = &mut self.capture {
+                        capture.extend_from_slice(data);
+                    }
                 }
             }
             Method::Head => Ok(()),
         }
     }
 
+    fn check_status(&mut self, code: u16, url: &Url, resume: u64) -> Result<()> {
+        if matches!(code, 301 | 302 | 303 | 307 | 308)
+            && let Some(location) = self.header("location")
+        {
+            return Err(RedirectError(code, url.join(location)).into());
+        }
+
+        if code == 429 {
+            self.retry_after = Some(Self::retry_after(self.header("retry-after")));
+        }
+
+        if code != 200 && !(code == 206 && resume > 0) {
+            return Err(StatusError(code, url.clone()).into());
+        }
+
+        Ok(())
+    }
+
+    //Reads until the end of the response headers, returns the status code and where the body starts
+    fn read_head(&mut self) -> Result<(u16, usize, usize)> {
+        const MAX_HEADERS: usize = 128;
+
+        let stream = self.stream.as_mut().expect("Missing stream while reading");
+        let max_size = self.agent.args.max_headers_size;
+
+        let mut filled = 0;
+        loop {
+            if filled == self.headers_buf.len() {
+                ensure!(
+                    filled < max_size,
+                    "Response headers are larger than {max_size} bytes"
+                );
+
+                let size = (filled * 2).min(max_size);
+                self.headers_buf.resize(size, 0);
+            }
+
+            let read = stream.read(&mut self.headers_buf[filled..])?;
+            if read == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            filled += read;
+
+            let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+            let mut response = httparse::Response::new(&mut headers);
+            let status = httparse::ParserConfig::default()
+                .allow_obsolete_multiline_headers_in_responses(true)
+                .allow_spaces_after_header_name_in_responses(true)
+                .parse_response(&mut response, &self.headers_buf[..filled])
+                .context("Failed to parse HTTP response headers")?;
+
+            let httparse::Status::Complete(body_start) = status else {
+                continue;
+            };
+            let code = response.code.context("Failed to parse HTTP status code")?;
+
+            self.response_headers.clear();
+            let _ = write!(
+                self.response_headers,
+                "HTTP/1.1 {code} {}",
+                response.reason.unwrap_or_default()
+            );
+
+            for header in response.headers.iter() {
+                //Folded values are joined into one line
+                let value = String::from_utf8_lossy(header.value);
+                let _ = write!(self.response_headers, "\n{}:", header.name);
+                for part in value.split(['\r', '\n']).filter(|p| !p.trim().is_empty()) {
+                    let _ = write!(self.response_headers, " {}", part.trim());
+                }
+            }
+
+            return Ok((code, body_start, filled));
+        }
+    }
+
+    fn write_request(
+        &mut self,
+        method: Method,
+        host: &str,
+        url: &Url,
+        args: Option<Arguments>,
+        resume: u64,
+    ) -> Result<()> {
+        //Reused between calls, nothing is allocated once it has grown to fit
+        let request = &mut *self.send_buf;
+        request.clear();
+        write!(
+            request,
+            "{method} /{path} HTTP/1.1\r\n\
+             Host: {host}\r\n\
+             User-Agent: {user_agent}\r\n\
+             {profile_headers}\
+             {twitch_headers}",
+            path = url.path()?,
+            user_agent = self.agent.args.user_agent.as_deref().unwrap_or_else(|| self
+                .agent
+                .args
+                .header_profile
+                .user_agent()),
+            profile_headers = self.agent.args.header_profile.headers(),
+            twitch_headers = HeaderProfile::twitch_headers(host),
+        )?;
+
+        self.agent.cookies.write_header(request, host, url.scheme);
+        for header in self
+            .agent
+            .args
+            .headers
+            .iter()
+            .filter(|h| h.applies_to(host))
+        {
+            request.extend_from_slice(header.line.as_bytes());
+        }
+
+        //Ranges of compressed bodies don't line up with the bytes already written
+        if resume > 0 {
+            write!(
+                request,
+                "Accept-Encoding: identity\r\n\
+                 Connection: keep-alive\r\n\
+                 Range: bytes={resume}-\r\n"
+            )?;
+        } else {
+            request.extend_from_slice(b"Accept-Encoding: gzip\r\nConnection: keep-alive\r\n");
+        }
+        request.write_fmt(args.unwrap_or_else(|| format_args!("\r\n")))?;
+
+        //Replayed early data is harmless for requests without side effects
+        let stream = self.stream.as_mut().expect("Missing stream while writing");
+        stream.send(&self.send_buf, matches!(method, Method::Get | Method::Head))?;
+
+        Ok(())
+    }
+
+    //Local playlists and segments, for replaying captured sessions
+    fn call_file(&mut self, method: Method, url: &Url) -> Result<()> {
+        let path = url.strip_prefix("file://").context("Invalid file URL")?;
+        let path = path.split_once('?').map_or(path, |(p, _)| p);
+
+        let mut file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(StatusError(404, url.clone()).into());
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to open {path}")),
+        };
+
+        if matches!(method, Method::Head) {
+            return self.write_body(io::empty());
+        }
+
+        self.write_body(&mut file)
+    }
+
+    //Body of a response that didn't come from the network
+    fn write_body(&mut self, mut reader: impl Read) -> Result<()> {
+        self.written = 0;
+        self.write_time = Duration::ZERO;
+
+        loop {
+            let read = reader.read(&mut self.decode_buf)?;
+            if read == 0 {
+                break;
+            }
+
+            let time = Instant::now();
+            self.writer.write_all(&self.decode_buf[..read])?;
+            self.write_time += time.elapsed();
+            self.written += read as u64;
+        }
+
+        self.writer.flush()?;
+        Ok(())
+    }
+
     fn connect(&mut self, url: &Url, host: &str, host_hash: u64) -> Result<()> {
-        self.stream = Some(Transport::new(url, host, &self.agent)?);
+        self.stream = Some(Transport::new(url, host, &self.agent, self.tune_socket)?);
         self.scheme = url.scheme;
         self.host_hash = host_hash;
 
         Ok(())
     }
 
+    //Only the delay in seconds form, HTTP dates get a short default delay
+    fn retry_after(value: Option<&str>) -> Duration {
+        const DEFAULT: Duration = Duration::from_secs(1);
+
+        value
+            .and_then(|v| v.parse().ok())
+            .map_or(DEFAULT, Duration::from_secs)
+    }
+
     fn hash(host: &str) -> u64 {
         let mut hasher = DefaultHasher::new();
         hasher.write(host.as_bytes());
@@ -178,6 +683,7 @@ impl<W: Write> Request<W> {
     //Retry if not 404 or io::ErrorKind::Other (used for internal errors)
     fn should_retry(error: &anyhow::Error) -> bool {
         error.is::<StatusError>() && !StatusError::is_not_found(error)
+            || error.is::<NonMediaError>()
             || error
                 .downcast_ref::<io::Error>()
                 .is_some_and(|e| e.kind() != io::ErrorKind::Other)
@@ -188,7 +694,23 @@ pub struct TextRequest(Request<StringWriter>);
 
 impl TextRequest {
     pub fn new(agent: Agent) -> Self {
-        Self(Request::new(StringWriter::default(), agent))
+        let mut request = Request::new(StringWriter::default(), agent);
+        request.tune_socket = false; //socket buffers are only for segments
+        request.check_media = false;
+
+        Self(request)
+    }
+
+    pub fn with_writer<W: Write>(self, writer: W) -> Request<W> {
+        self.0.with_writer(writer)
+    }
+
+    pub const fn redirected(&self) -> Option<&Url> {
+        self.0.redirected()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.0.header(name)
     }
 
     pub fn take(&mut self) -> String {
@@ -200,19 +722,32 @@ impl TextRequest {
     }
 
     pub fn text_no_retry(&mut self, method: Method, url: &Url) -> Result<()> {
+        self.text_no_retry_impl(method, url, None)
+    }
+
+    pub fn text_fmt_no_retry(&mut self, method: Method, url: &Url, args: Arguments) -> Result<()> {
+        self.text_no_retry_impl(method, url, Some(args))
+    }
+
+    pub fn text_fmt(&mut self, method: Method, url: &Url, args: Arguments) -> Result<&str> {
+        self.text_impl(method, url, Some(args))
+    }
+
+    fn text_no_retry_impl(
+        &mut self,
+        method: Method,
+        url: &Url,
+        data: Option<Arguments>,
+    ) -> Result<()> {
         let retries = self.0.retries;
         self.0.retries = 0;
 
-        self.text_impl(method, url, None)?;
+        self.text_impl(method, url, data)?;
 
         self.0.retries = retries;
         Ok(())
     }
 
-    pub fn text_fmt(&mut self, method: Method, url: &Url, args: Arguments) -> Result<&str> {
-        self.text_impl(method, url, Some(args))
-    }
-
     fn text_impl(&mut self, method: Method, url: &Url, data: Option<Arguments>) -> Result<&str> {
         self.0.writer.0.clear();
         self.0.call_impl(method, url, data)?;
@@ -221,7 +756,7 @@ impl TextRequest {
     }
 }
 
-enum Transport {
+pub(super) enum Transport {
     Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
     Unencrypted(TcpStream),
 }
@@ -256,13 +791,45 @@ impl Write for Transport {
 }
 
 impl Transport {
-    fn new(url: &Url, host: &str, agent: &Agent) -> Result<Self> {
+    const fn socket(&self) -> &TcpStream {
+        match self {
+            Self::Tls(stream) => &stream.sock,
+            Self::Unencrypted(sock) => sock,
+        }
+    }
+
+    //Sent with the ClientHello as early data if the TLS session is resumed, saving a round trip
+    fn send(&mut self, request: &[u8], can_send_early: bool) -> io::Result<()> {
+        let mut sent = 0;
+        if can_send_early
+            && let Self::Tls(stream) = self
+            && let Some(mut early_data) = stream.conn.early_data()
+        {
+            sent = early_data.write(request)?;
+            while stream.conn.is_handshaking() {
+                stream.conn.complete_io(&mut stream.sock)?;
+            }
+
+            if stream.conn.is_early_data_accepted() {
+                debug!("Sent {sent} bytes of early data");
+            } else {
+                debug!("Early data rejected, sending request again");
+                sent = 0;
+            }
+        }
+
+        self.write_all(&request[sent..])?;
+        self.flush()
+    }
+
+    fn new(url: &Url, host: &str, agent: &Agent, tune_socket: bool) -> Result<Self> {
         ensure!(
             !agent.args.force_https || url.scheme == Scheme::Https,
             "URL protocol is not HTTPS and --force-https is enabled: {url}",
         );
 
-        let sock = if let Some(addrs) = &agent.args.socks5
+        let mut timing = ConnectTiming::default();
+        let mut sock = if let Some(addrs) = &agent.args.socks5
             && agent
                 .args
                 .socks5_restrict
@@ -270,44 +837,100 @@ impl Transport {
                 .is_none_or(|w| w.iter().any(|w| w == host))
         {
             debug!("Connecting to {host} via socks5 proxy...");
-            socks5::connect(Self::connect(addrs, agent)?, host, url.port()?)?
+            let time = Instant::now();
+            let sock = socks5::connect(
+                Self::connect(addrs, agent, tune_socket)?,
+                host,
+                url.port()?,
+                agent.args.socks5_auth.as_ref(),
+            )?;
+            timing.connect = time.elapsed();
+
+            sock
         } else {
+            let time = Instant::now();
+            let addrs = Self::resolve(host, url.port()?, agent)?;
+            timing.dns = time.elapsed();
+
             debug!("Connecting to {host}...");
-            Self::connect(
-                &(host, url.port()?)
-                    .to_socket_addrs()?
-                    .collect::<Vec<SocketAddr>>(),
-                agent,
-            )?
+            let time = Instant::now();
+            let sock = Self::connect(&addrs, agent, tune_socket)?;
+            timing.connect = time.elapsed();
+
+            sock
         };
 
-        match url.scheme {
-            Scheme::Http => Ok(Self::Unencrypted(sock)),
-            Scheme::Https => Ok(Self::Tls(Box::new(StreamOwned::new(
-                ClientConnection::new(agent.tls_config.clone(), host.to_owned().try_into()?)?,
-                sock,
-            )))),
-            Scheme::Unknown => bail!("Unsupported protocol"),
-        }
+        let transport = match url.scheme {
+            Scheme::Http => Self::Unencrypted(sock),
+            Scheme::Https => {
+                let mut conn =
+                    ClientConnection::new(agent.tls_config.clone(), host.to_owned().try_into()?)?;
+
+                //Handshake up front so it can be timed separately from the first request,
+                //unless the session is resumed and the request can go out with the ClientHello
+                if conn.early_data().is_none() {
+                    let time = Instant::now();
+                    while conn.is_handshaking() {
+                        conn.complete_io(&mut sock)?;
+                    }
+                    timing.tls = Some(time.elapsed());
+                }
+
+                Self::Tls(Box::new(StreamOwned::new(conn, sock)))
+            }
+            Scheme::File | Scheme::Unknown => bail!("Unsupported protocol"),
+        };
+
+        debug!(
+            "Connected to {host} (dns: {:?}, connect: {:?}, tls: {:?})",
+            timing.dns, timing.connect, timing.tls,
+        );
+        STATS.connected(&timing);
+
+        Ok(transport)
     }
 
-    fn connect(addrs: &[SocketAddr], agent: &Agent) -> Result<TcpStream> {
-        ensure!(!addrs.is_empty(), "Failed to resolve socket address");
+    fn resolve(host: &str, port: u16, agent: &Agent) -> Result<Vec<SocketAddr>> {
+        if let Some(resolve) = &agent.args.resolve {
+            let addrs = resolve
+                .iter()
+                .filter(|r| r.host == host && r.port == port)
+                .map(|r| SocketAddr::new(r.addr, port))
+                .collect::<Vec<_>>();
+
+            if !addrs.is_empty() {
+                debug!("Using resolve override for {host}:{port}");
+                return Ok(addrs);
+            }
+        }
+
+        Ok((host, port).to_socket_addrs()?.collect())
+    }
 
+    fn connect(addrs: &[SocketAddr], agent: &Agent, tune_socket: bool) -> Result<TcpStream> {
         let mut io_error = None;
-        for addr in addrs
-            .iter()
-            .filter(|a| !agent.args.force_ipv4 || SocketAddr::is_ipv4(a))
-        {
-            match TcpStream::connect_timeout(addr, agent.args.timeout) {
+        for addr in Self::interleave(addrs, &agent.args)? {
+            let sock = if tune_socket || agent.args.interface.is_some() || agent.args.tcp_fastopen {
+                Self::connect_tuned(&addr, agent, tune_socket)
+            } else {
+                TcpStream::connect_timeout(&addr, agent.args.timeout)
+            };
+
+            match sock {
                 Ok(sock) => {
                     sock.set_nodelay(true)?;
                     sock.set_read_timeout(Some(agent.args.timeout))?;
                     sock.set_write_timeout(Some(agent.args.timeout))?;
+                    if let Some(time) = agent.args.tcp_keepalive {
+                        Self::set_keepalive(&sock, time)?;
+                    }
 
                     return Ok(sock);
                 }
-                Err(e) => io_error = Some(e),
+                Err(e) => {
+                    debug!("Failed to connect to {addr}: {e}");
+                    io_error = Some(e);
+                }
             }
         }
 
@@ -315,6 +938,164 @@ impl Transport {
             .expect("Missing IO error while connection failed")
             .into())
     }
+
+    //Idle connections are probed so NAT routers keep them open
+    fn set_keepalive(sock: &TcpStream, time: Duration) -> io::Result<()> {
+        let keepalive = TcpKeepalive::new().with_time(time);
+
+        #[cfg(any(
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "windows"
+        ))]
+        let keepalive = keepalive.with_interval(time);
+
+        SockRef::from(sock).set_tcp_keepalive(&keepalive)
+    }
+
+    //Buffer sizes have to be set before connecting to affect the TCP window scale,
+    //same with binding to an interface and fast open
+    fn connect_tuned(addr: &SocketAddr, agent: &Agent, tune_socket: bool) -> io::Result<TcpStream> {
+        let socket = Socket::new(
+            Domain::for_address(*addr),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )?;
+        match &agent.args.interface {
+            Some(Interface::Addr(source)) => socket.bind(&SocketAddr::new(*source, 0).into())?,
+            #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+            Some(Interface::Name(name)) => socket.bind_device(Some(name.as_bytes()))?,
+            None => (),
+        }
+
+        if tune_socket && let Some(size) = agent.args.socket_rcvbuf {
+            socket.set_recv_buffer_size(size)?;
+        }
+
+        if tune_socket && let Some(size) = agent.args.socket_sndbuf {
+            socket.set_send_buffer_size(size)?;
+        }
+
+        //The first write goes out with the SYN once the server has handed out a cookie
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        if agent.args.tcp_fastopen {
+            setsockopt(&socket, TcpFastOpenConnect, &true)?;
+        }
+
+        socket.connect_timeout(&(*addr).into(), agent.args.timeout)?;
+        Ok(socket.into())
+    }
+
+    //Alternate between IPv6 and IPv4 so a broken address family
+    //doesn't have to time out on every one of its addresses first
+    fn interleave(addrs: &[SocketAddr], args: &Args) -> Result<Vec<SocketAddr>> {
+        let addrs = addrs
+            .iter()
+            .filter(|a| !args.force_ipv4 || a.is_ipv4())
+            .filter(|a| args.interface.as_ref().is_none_or(|i| i.can_reach(a)))
+            .copied()
+            .collect::<Vec<_>>();
+
+        let first = addrs.first().context("Failed to resolve socket address")?;
+        let (preferred, other): (Vec<_>, Vec<_>) =
+            addrs.iter().partition(|a| a.is_ipv6() == first.is_ipv6());
+
+        let mut other = other.into_iter();
+        let mut interleaved = Vec::with_capacity(addrs.len());
+        for addr in preferred {
+            interleaved.push(addr);
+            interleaved.extend(other.next());
+        }
+        interleaved.extend(other);
+
+        Ok(interleaved)
+    }
+}
+
+//Reads are cut short with the socket's read timeout instead of only checking after each one
+struct BodyDeadline {
+    at: Instant,
+    sock: TcpStream, //same socket as the stream
+    timeout: Duration,
+    shortened: bool,
+}
+
+impl BodyDeadline {
+    fn new(stream: &Transport, timeout: Duration, read_timeout: Duration) -> io::Result<Self> {
+        Ok(Self {
+            at: Instant::now() + timeout,
+            sock: stream.socket().try_clone()?,
+            timeout: read_timeout,
+            shortened: false,
+        })
+    }
+
+    //False once the deadline passed
+    fn shorten_timeout(&mut self) -> io::Result<bool> {
+        let remaining = self.at.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(false);
+        }
+
+        if remaining < self.timeout {
+            self.sock.set_read_timeout(Some(remaining))?;
+            self.shortened = true;
+        }
+
+        Ok(true)
+    }
+
+    fn is_reached(&self) -> bool {
+        Instant::now() >= self.at
+    }
+
+    //Connection goes back to the pool
+    fn restore_timeout(self) -> io::Result<()> {
+        if self.shortened {
+            self.sock.set_read_timeout(Some(self.timeout))?;
+        }
+
+        Ok(())
+    }
+}
+
+//Socket timeouts never fire while bytes keep trickling in
+struct SpeedWatchdog {
+    min_speed: u64,
+    period: Duration,
+    start: Instant,
+    bytes: u64,
+}
+
+impl SpeedWatchdog {
+    fn new(args: &Args) -> Option<Self> {
+        Some(Self {
+            min_speed: args.min_speed?,
+            period: args.min_speed_time,
+            start: Instant::now(),
+            bytes: 0,
+        })
+    }
+
+    //Average speed over each --min-speed-time period
+    fn is_stalled(&mut self, read: usize) -> bool {
+        self.bytes += read as u64;
+
+        let elapsed = self.start.elapsed();
+        if elapsed < self.period {
+            return false;
+        }
+
+        if u128::from(self.bytes) * 1000 < u128::from(self.min_speed) * elapsed.as_millis() {
+            return true;
+        }
+
+        self.start = Instant::now();
+        self.bytes = 0;
+        false
+    }
 }
 
 #[derive(Default)]
@@ -338,3 +1119,44 @@ impl Write for StringWriter {
         Ok(())
     }
 }
+
+//Moves the next part of an unencoded body from the socket to the pipe without copying it
+//through userspace. None when it can't, the caller reads it instead.
+#[cfg(target_os = "linux")]
+fn splice(
+    decoder: &mut Decoder<io::Chain<&[u8], &mut Transport>>,
+    pipe: &mut Option<OwnedFd>,
+    max: usize,
+) -> io::Result<Option<usize>> {
+    let Some(out) = pipe else {
+        return Ok(None);
+    };
+    let Some((reader, remaining)) = decoder.unencoded() else {
+        return Ok(None);
+    };
+
+    //Part of the body came in with the headers
+    let (buffered, Transport::Unencrypted(sock)) = reader.get_mut() else {
+        return Ok(None);
+    };
+    if !buffered.is_empty() || remaining == 0 {
+        return Ok(None);
+    }
+
+    let len = usize::try_from(remaining).map_or(max, |remaining| remaining.min(max));
+    match fcntl::splice(&*sock, None, &*out, None, len, SpliceFFlags::SPLICE_F_MOVE) {
+        //Connection closed early, the body can be resumed
+        Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+        Ok(spliced) => {
+            decoder.consume(spliced as u64);
+            Ok(Some(spliced))
+        }
+        //Closed player or a pipe that can't be spliced to, the writer reports it
+        Err(e @ (Errno::EPIPE | Errno::EINVAL)) => {
+            debug!("Failed to splice body, copying instead: {e}");
+            *pipe = None;
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
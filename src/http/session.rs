@@ -0,0 +1,141 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use log::debug;
+
+use super::{Method, Url};
+
+//Records every HTTP response of a session to a directory, or serves them back with the
+//original timing. Each response is a line in the index and its body is stored as <ID>.body.
+pub enum Session {
+    Record(Recorder),
+    Replay(Replayer),
+}
+
+impl Session {
+    const INDEX: &'static str = "index";
+
+    pub fn record(dir: &str) -> Result<Self> {
+        fs::create_dir_all(dir).context("Failed to create HTTP record directory")?;
+
+        let dir = PathBuf::from(dir);
+        let index = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(dir.join(Self::INDEX))
+            .context("Failed to create HTTP record index")?;
+
+        Ok(Self::Record(Recorder {
+            dir,
+            start: Instant::now(),
+            next_id: AtomicU64::default(),
+            index: Mutex::new(index),
+        }))
+    }
+
+    pub fn replay(dir: &str) -> Result<Self> {
+        let dir = PathBuf::from(dir);
+        let index = fs::read_to_string(dir.join(Self::INDEX))
+            .context("Failed to read HTTP record index")?;
+
+        let mut responses = HashMap::<String, VecDeque<Response>>::new();
+        for line in index.lines() {
+            //<ID> <ELAPSED_MS> <METHOD> <STATUS> <URL>
+            let mut split = line.splitn(5, ' ');
+            let mut next = || split.next().context("Invalid HTTP record index");
+
+            let id = next()?.parse()?;
+            let elapsed = Duration::from_millis(next()?.parse()?);
+            let method = next()?;
+            let status = next()?.parse()?;
+            let url = next()?;
+
+            responses
+                .entry(format!("{method} {}", strip_query(url)))
+                .or_default()
+                .push_back(Response {
+                    id,
+                    elapsed,
+                    status,
+                });
+        }
+
+        Ok(Self::Replay(Replayer {
+            dir,
+            start: Instant::now(),
+            responses: Mutex::new(responses),
+        }))
+    }
+}
+
+pub struct Recorder {
+    dir: PathBuf,
+    start: Instant,
+    next_id: AtomicU64,
+    index: Mutex<fs::File>,
+}
+
+impl Recorder {
+    pub fn save(&self, method: Method, url: &Url, status: u16, body: &[u8]) -> io::Result<()> {
+        let elapsed = self.start.elapsed().as_millis();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        fs::write(self.dir.join(format!("{id}.body")), body)?;
+        writeln!(
+            self.index.lock().unwrap_or_else(PoisonError::into_inner),
+            "{id} {elapsed} {method} {status} {url}"
+        )
+    }
+}
+
+pub struct Replayer {
+    dir: PathBuf,
+    start: Instant,
+    responses: Mutex<HashMap<String, VecDeque<Response>>>,
+}
+
+impl Replayer {
+    //Responses are matched by method and URL without the query, which has random parameters.
+    //Once they run out it's reported as not found, so the session ends like the stream went offline.
+    pub fn next(&self, method: Method, url: &Url) -> io::Result<(u16, Vec<u8>)> {
+        let Some(response) = self
+            .responses
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_mut(&format!("{method} {}", strip_query(url)))
+            .and_then(VecDeque::pop_front)
+        else {
+            debug!("No recorded response left for {url}");
+            return Ok((404, Vec::new()));
+        };
+
+        if let Some(wait) = response.elapsed.checked_sub(self.start.elapsed()) {
+            thread::sleep(wait);
+        }
+
+        let body = fs::read(self.dir.join(format!("{}.body", response.id)))?;
+        Ok((response.status, body))
+    }
+}
+
+struct Response {
+    id: u64,
+    elapsed: Duration,
+    status: u16,
+}
+
+fn strip_query(url: &str) -> &str {
+    url.split_once('?').map_or(url, |(u, _)| u)
+}
@@ -0,0 +1,101 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+
+use super::{Scheme, request::Transport};
+
+//One keep-alive connection per host, shared by every request to that host.
+//Requests take turns in the order they arrived so neither playlist nor segments starve.
+#[derive(Default)]
+pub struct SharedConnection {
+    state: Mutex<Vec<Slot>>,
+    turn: Condvar,
+}
+
+struct Slot {
+    host_hash: u64,
+    scheme: Scheme,
+    transport: Option<Transport>,
+
+    next_ticket: u64,
+    serving: u64,
+}
+
+impl SharedConnection {
+    //Blocks until it's this request's turn on the host's connection
+    pub fn lease(self: &Arc<Self>, host_hash: u64) -> Lease {
+        let mut slots = self.lock();
+        let ticket = {
+            let slot = Self::slot(&mut slots, host_hash);
+            slot.next_ticket += 1;
+            slot.next_ticket - 1
+        };
+
+        while Self::slot(&mut slots, host_hash).serving != ticket {
+            slots = self
+                .turn
+                .wait(slots)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        drop(slots);
+
+        Lease {
+            shared: Arc::clone(self),
+            host_hash,
+        }
+    }
+
+    fn slot(slots: &mut Vec<Slot>, host_hash: u64) -> &mut Slot {
+        let index = slots
+            .iter()
+            .position(|s| s.host_hash == host_hash)
+            .unwrap_or_else(|| {
+                slots.push(Slot {
+                    host_hash,
+                    scheme: Scheme::default(),
+                    transport: None,
+                    next_ticket: 0,
+                    serving: 0,
+                });
+
+                slots.len() - 1
+            });
+
+        &mut slots[index]
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Slot>> {
+        //Nothing in the state can be left half updated
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub struct Lease {
+    shared: Arc<SharedConnection>,
+    host_hash: u64,
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.with_slot(|slot| slot.serving += 1);
+        self.shared.turn.notify_all();
+    }
+}
+
+impl Lease {
+    pub fn take(&self) -> (Option<Transport>, Scheme) {
+        self.with_slot(|slot| (slot.transport.take(), slot.scheme))
+    }
+
+    pub fn put(self, transport: Option<Transport>, scheme: Scheme) {
+        self.with_slot(|slot| {
+            slot.transport = transport;
+            slot.scheme = scheme;
+        });
+    }
+
+    fn with_slot<T>(&self, f: impl FnOnce(&mut Slot) -> T) -> T {
+        f(SharedConnection::slot(
+            &mut self.shared.lock(),
+            self.host_hash,
+        ))
+    }
+}
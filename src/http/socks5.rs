@@ -1,15 +1,23 @@
 use std::{
+    fmt::{self, Debug, Formatter},
     io::{Read, Write},
     net::TcpStream,
 };
 
-use anyhow::{Result, ensure};
+use anyhow::{Context, Result, bail, ensure};
 
 const SOCKS_VERSION: u8 = 0x05;
 const NO_AUTH_NUM_METHODS: u8 = 0x01;
 const NO_AUTH: u8 = 0x00;
 const HANDSHAKE: [u8; 3] = [SOCKS_VERSION, NO_AUTH_NUM_METHODS, NO_AUTH];
 
+//RFC 1929
+const AUTH_NUM_METHODS: u8 = 0x02;
+const USERPASS_AUTH: u8 = 0x02;
+const USERPASS_AUTH_VERSION: u8 = 0x01;
+const AUTH_HANDSHAKE: [u8; 4] = [SOCKS_VERSION, AUTH_NUM_METHODS, NO_AUTH, USERPASS_AUTH];
+const AUTH_SUCCESS: u8 = 0x00;
+
 const CONNECT_COMMAND: u8 = 0x01;
 const ADDRESS_TYPE_DOMAIN: u8 = 0x03;
 const RESERVED: u8 = 0x00;
@@ -19,16 +27,62 @@ const COMMAND_SUCCESS: u8 = 0x00;
 const HANDSHAKE_RESPONSE_LEN: usize = 2;
 const REQUEST_RESPONSE_LEN: usize = 10;
 
-pub fn connect(mut sock: TcpStream, target_host: &str, target_port: u16) -> Result<TcpStream> {
-    sock.write_all(&HANDSHAKE)?;
+#[derive(Clone)]
+pub struct Credentials {
+    username: String,
+    password: String,
+}
+
+impl Credentials {
+    pub fn new(arg: &str) -> Result<Option<Self>> {
+        let (username, password) = arg
+            .split_once(':')
+            .context("Invalid credentials, expected <USERNAME:PASSWORD>")?;
+
+        ensure!(
+            username.len() <= u8::MAX.into() && password.len() <= u8::MAX.into(),
+            "SOCKS5 username and password can't be longer than 255 bytes"
+        );
+
+        Ok(Some(Self {
+            username: username.to_owned(),
+            password: password.to_owned(),
+        }))
+    }
+}
+
+impl Debug for Credentials {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("<hidden>")
+    }
+}
+
+pub fn connect(
+    mut sock: TcpStream,
+    target_host: &str,
+    target_port: u16,
+    credentials: Option<&Credentials>,
+) -> Result<TcpStream> {
+    sock.write_all(if credentials.is_some() {
+        &AUTH_HANDSHAKE
+    } else {
+        &HANDSHAKE
+    })?;
 
     let mut response = [0u8; HANDSHAKE_RESPONSE_LEN];
     sock.read_exact(&mut response)?;
     ensure!(
-        response[0] == SOCKS_VERSION && response[1] == NO_AUTH,
+        response[0] == SOCKS_VERSION,
         "Invalid handshake from SOCKS5 server"
     );
 
+    match (response[1], credentials) {
+        (NO_AUTH, _) => (),
+        (USERPASS_AUTH, Some(credentials)) => authenticate(&mut sock, credentials)?,
+        (USERPASS_AUTH, None) => bail!("SOCKS5 server requires authentication"),
+        _ => bail!("No supported SOCKS5 authentication method"),
+    }
+
     let mut request = vec![
         SOCKS_VERSION,
         CONNECT_COMMAND,
@@ -51,3 +105,20 @@ pub fn connect(mut sock: TcpStream, target_host: &str, target_port: u16) -> Resu
 
     Ok(sock)
 }
+
+fn authenticate(sock: &mut TcpStream, credentials: &Credentials) -> Result<()> {
+    let mut request = vec![
+        USERPASS_AUTH_VERSION,
+        u8::try_from(credentials.username.len())?,
+    ];
+    request.extend_from_slice(credentials.username.as_bytes());
+    request.push(u8::try_from(credentials.password.len())?);
+    request.extend_from_slice(credentials.password.as_bytes());
+    sock.write_all(&request)?;
+
+    let mut response = [0u8; 2];
+    sock.read_exact(&mut response)?;
+    ensure!(response[1] == AUTH_SUCCESS, "SOCKS5 authentication failed");
+
+    Ok(())
+}
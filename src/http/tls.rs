@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use rustls::{
+    DigitallySignedStruct, Error, RootCertStore, SignatureScheme,
+    client::{
+        WebPkiServerVerifier,
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    },
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, ServerName, UnixTime},
+};
+
+use super::is_twitch_host;
+
+//--tls-no-verify, certificates of Twitch hosts are still checked so tokens can't leak
+#[derive(Debug)]
+pub struct PartialVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+}
+
+impl PartialVerifier {
+    pub fn new(roots: RootCertStore, provider: Arc<CryptoProvider>) -> Result<Self> {
+        Ok(Self {
+            inner: WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .context("Failed to create certificate verifier")?,
+        })
+    }
+
+    fn is_twitch(server_name: &ServerName) -> bool {
+        let ServerName::DnsName(name) = server_name else {
+            return false;
+        };
+
+        is_twitch_host(name.as_ref())
+    }
+}
+
+impl ServerCertVerifier for PartialVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        if !Self::is_twitch(server_name) {
+            return Ok(ServerCertVerified::assertion());
+        }
+
+        self.inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    //Handshake signatures are still checked against whatever certificate was sent
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
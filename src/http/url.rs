@@ -74,6 +74,41 @@ impl Url {
             .context("Failed to parse path in URL")
     }
 
+    //Resolves a URL found in a playlist against the playlist's URL, absolute URLs are kept as is
+    pub fn join(&self, reference: &str) -> Self {
+        if reference.contains("://") {
+            return reference.into();
+        }
+
+        if let Some(reference) = reference.strip_prefix("//") {
+            return format!("{}://{reference}", self.scheme).into();
+        }
+
+        let base = self
+            .inner
+            .split_once('?')
+            .map_or(self.inner.as_str(), |s| s.0);
+        let origin_end = base.find("://").map_or(0, |p| p + 3);
+        let host_end = base[origin_end..]
+            .find('/')
+            .map_or(base.len(), |p| origin_end + p);
+
+        if reference.starts_with('/') {
+            return format!("{}{reference}", &base[..host_end]).into();
+        }
+
+        //Directory of the base URL, which may be just the host
+        let dir = base[host_end..]
+            .rfind('/')
+            .map_or(base, |p| &base[..=host_end + p]);
+
+        if dir.ends_with('/') {
+            format!("{dir}{reference}").into()
+        } else {
+            format!("{dir}/{reference}").into()
+        }
+    }
+
     pub fn port(&self) -> Result<u16> {
         if let Some(port) = self
             .inner
@@ -87,7 +122,7 @@ impl Url {
         match self.scheme {
             Scheme::Http => Ok(80),
             Scheme::Https => Ok(443),
-            Scheme::Unknown => bail!("Unknown scheme in URL"),
+            Scheme::File | Scheme::Unknown => bail!("Unknown scheme in URL"),
         }
     }
 }
@@ -96,6 +131,7 @@ impl Url {
 pub enum Scheme {
     Http,
     Https,
+    File,
 
     #[default]
     Unknown,
@@ -106,6 +142,7 @@ impl Display for Scheme {
         match self {
             Self::Http => f.write_str("http"),
             Self::Https => f.write_str("https"),
+            Self::File => f.write_str("file"),
             Self::Unknown => f.write_str("<unknown>"),
         }
     }
@@ -116,6 +153,7 @@ impl Scheme {
         match url.split(':').next() {
             Some("http") => Self::Http,
             Some("https") => Self::Https,
+            Some("file") => Self::File,
             _ => Self::Unknown,
         }
     }
@@ -0,0 +1,61 @@
+//Without a TLS provider only the playlist parser and state are built (the core-only feature),
+//they don't touch the network or files so they also build for wasm
+#[cfg(not(any(feature = "net", feature = "core-only")))]
+compile_error!("At least one of the ring or aws-lc-rs features must be enabled, or core-only");
+
+#[cfg(feature = "net")]
+mod args;
+#[cfg(feature = "net")]
+mod cli;
+#[cfg(feature = "net")]
+mod constants;
+#[cfg(feature = "net")]
+mod control;
+#[cfg(all(feature = "net", unix))]
+mod daemon;
+#[cfg(all(feature = "net", feature = "ffi"))]
+#[allow(unsafe_code)] //FFI
+mod ffi;
+#[cfg(feature = "net")]
+mod hls;
+#[cfg(feature = "net")]
+mod http;
+#[cfg(feature = "net")]
+mod lock;
+#[cfg(feature = "net")]
+mod logger;
+#[cfg(feature = "net")]
+mod obs;
+#[cfg(feature = "net")]
+mod output;
+#[cfg(feature = "net")]
+mod replay;
+#[cfg(feature = "net")]
+mod shutdown;
+#[cfg(feature = "net")]
+mod sidecar;
+#[cfg(feature = "net")]
+mod stats;
+
+#[cfg(feature = "net")]
+pub use cli::main;
+
+//Shared with the CLI's private modules, which aren't held to public API lints
+#[cfg(not(feature = "net"))]
+#[allow(clippy::must_use_candidate, clippy::missing_errors_doc)]
+pub mod hls {
+    pub mod parse;
+    pub mod state;
+}
+
+#[cfg(not(feature = "net"))]
+#[allow(
+    clippy::must_use_candidate,
+    clippy::missing_errors_doc,
+    clippy::return_self_not_must_use
+)]
+pub mod http {
+    mod url;
+
+    pub use url::Url;
+}
@@ -0,0 +1,103 @@
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::Write,
+    path::{Path, PathBuf},
+    process,
+    thread::{self, Builder as ThreadBuilder},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use log::info;
+
+use crate::shutdown;
+
+#[derive(Default, Copy, Clone, Debug)]
+pub enum Mode {
+    #[default]
+    Disabled,
+    Exit,
+    Takeover,
+}
+
+impl Mode {
+    pub fn new(arg: &str) -> Result<Self> {
+        match arg {
+            "disabled" => Ok(Self::Disabled),
+            "exit" => Ok(Self::Exit),
+            "takeover" => Ok(Self::Takeover),
+            _ => bail!("Invalid channel lock mode"),
+        }
+    }
+}
+
+//Held for as long as the process runs, the OS releases it on exit even after a crash
+pub struct ChannelLock {
+    _file: File,
+}
+
+impl ChannelLock {
+    pub fn acquire(dir: Option<&Path>, channel: &str, mode: Mode) -> Result<Option<Self>> {
+        if matches!(mode, Mode::Disabled) {
+            return Ok(None);
+        }
+
+        let dir = dir.context("Failed to find config directory for channel lock")?;
+        fs::create_dir_all(dir).context("Failed to create lock directory")?;
+
+        let name = channel.replace(|c: char| !c.is_ascii_alphanumeric() && c != '_', "_");
+        let path = dir.join(format!("{name}.lock"));
+        let takeover_path = dir.join(format!("{name}.takeover"));
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .context("Failed to open channel lock file")?;
+
+        match file.try_lock() {
+            Ok(()) => (),
+            Err(TryLockError::WouldBlock) => match mode {
+                Mode::Exit => bail!("Another instance is already running for {channel}"),
+                Mode::Takeover => {
+                    info!("Another instance is running for {channel}, taking over...");
+                    fs::write(&takeover_path, format!("{}\n", process::id()))
+                        .context("Failed to write channel takeover file")?;
+
+                    file.lock().context("Failed to lock channel lock file")?;
+                }
+                Mode::Disabled => unreachable!(),
+            },
+            Err(TryLockError::Error(e)) => {
+                return Err(e).context("Failed to lock channel lock file");
+            }
+        }
+
+        let _ = fs::remove_file(&takeover_path);
+        file.set_len(0)?;
+        writeln!(file, "{}", process::id())?;
+
+        watch_takeover(takeover_path)?;
+        Ok(Some(Self { _file: file }))
+    }
+}
+
+//A newer instance asks this one to stop by creating the takeover file
+fn watch_takeover(path: PathBuf) -> Result<()> {
+    const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+    ThreadBuilder::new()
+        .name("channel lock".to_owned())
+        .spawn(move || {
+            while !path.exists() {
+                thread::sleep(POLL_INTERVAL);
+            }
+
+            info!("Another instance is taking over, exiting...");
+            shutdown::request();
+        })
+        .context("Failed to spawn channel lock thread")?;
+
+    Ok(())
+}
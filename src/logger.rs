@@ -1,15 +1,57 @@
 use std::{
     env,
-    io::{self, IsTerminal},
+    fmt::Arguments,
+    io::{self, IsTerminal, Write},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
     time::SystemTime,
 };
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use log::{Level, LevelFilter, Log, Metadata, Record};
 
+use crate::stats::format_date_time;
+
+//Single line redrawn in place below the log output
+static STATUS: Mutex<String> = Mutex::new(String::new());
+
+//Set with --stdout, the stream is written there so everything else goes to stderr
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Default, Copy, Clone, Debug)]
+pub enum Style {
+    #[default]
+    Auto,
+    Terminal,
+    Plain,
+}
+
+impl Style {
+    pub fn new(arg: &str) -> Result<Self> {
+        match arg {
+            "auto" => Ok(Self::Auto),
+            "terminal" => Ok(Self::Terminal),
+            "plain" => Ok(Self::Plain),
+            _ => bail!("Invalid log style"),
+        }
+    }
+
+    //Service managers and docker capture output through pipes
+    pub fn is_plain(self) -> bool {
+        match self {
+            Self::Auto => !io::stdout().is_terminal() || !io::stderr().is_terminal(),
+            Self::Terminal => false,
+            Self::Plain => true,
+        }
+    }
+}
+
 pub struct Logger {
     enable_debug: bool,
     enable_colors: bool,
+    enable_timestamps: bool,
 }
 
 impl Log for Logger {
@@ -18,12 +60,17 @@ impl Log for Logger {
     }
 
     fn log(&self, record: &Record<'_>) {
+        let status = STATUS.lock().ok().filter(|s| !s.is_empty());
+        if status.is_some() {
+            print(format_args!("\r\x1b[2K"));
+        }
+
         let level = record.level();
         match level {
-            Level::Error | Level::Info | Level::Debug if self.enable_debug => {
+            Level::Error | Level::Warn | Level::Info | Level::Debug if self.enable_debug => {
                 let thread = std::thread::current();
-                println!(
-                    "{time} {tag} ({thread}) {module}: {log}",
+                print(format_args!(
+                    "{time} {tag} ({thread}) {module}: {log}\n",
                     time = SystemTime::now()
                         .duration_since(SystemTime::UNIX_EPOCH)
                         .unwrap_or_default()
@@ -32,22 +79,46 @@ impl Log for Logger {
                     thread = thread.name().unwrap_or("<unknown>"),
                     module = record.module_path().unwrap_or("<unknown>"),
                     log = record.args(),
+                ));
+            }
+            Level::Error | Level::Warn | Level::Info if self.enable_timestamps => {
+                let line = format!(
+                    "{time} {tag} {log}",
+                    time = format_date_time(SystemTime::now()),
+                    tag = level_tag_no_color(level),
+                    log = record.args(),
                 );
+
+                if level == Level::Info {
+                    print(format_args!("{line}\n"));
+                } else {
+                    eprintln!("{line}");
+                }
             }
-            Level::Error => eprintln!("{} {}", level_tag(level, self.enable_colors), record.args()),
-            Level::Info => println!("{}", record.args()),
+            Level::Error | Level::Warn => {
+                eprintln!("{} {}", level_tag(level, self.enable_colors), record.args());
+            }
+            Level::Info => print(format_args!("{}\n", record.args())),
             _ => (),
         }
+
+        if let Some(status) = status {
+            print(format_args!("{status}"));
+        }
     }
 
     fn flush(&self) {}
 }
 
 impl Logger {
-    pub fn init(enable_debug: bool) -> Result<()> {
+    pub fn init(enable_debug: bool, style: Style, reserve_stdout: bool) -> Result<()> {
+        STDOUT_RESERVED.store(reserve_stdout, Ordering::Relaxed);
+
+        let plain = style.is_plain();
         log::set_boxed_logger(Box::new(Self {
             enable_debug,
-            enable_colors: env::var_os("NO_COLOR").is_none() && io::stdout().is_terminal(),
+            enable_colors: env::var_os("NO_COLOR").is_none() && !plain,
+            enable_timestamps: plain,
         }))?;
 
         log::set_max_level(if enable_debug {
@@ -64,12 +135,47 @@ pub fn is_debug() -> bool {
     log::max_level() == LevelFilter::Debug
 }
 
+pub fn set_status(status: &str) {
+    let Ok(mut current) = STATUS.lock() else {
+        return;
+    };
+
+    status.clone_into(&mut current);
+    print(format_args!("\r\x1b[2K{current}"));
+}
+
+pub fn clear_status() {
+    if let Ok(mut current) = STATUS.lock()
+        && !current.is_empty()
+    {
+        current.clear();
+        print(format_args!("\r\x1b[2K"));
+    }
+}
+
+//OSC 0, sets the terminal window and icon title
+pub fn set_title(title: &str) {
+    print(format_args!("\x1b]0;{title}\x07"));
+}
+
+fn print(args: Arguments<'_>) {
+    if STDOUT_RESERVED.load(Ordering::Relaxed) {
+        let _ = io::stderr().write_fmt(args);
+        return;
+    }
+
+    let mut stdout = io::stdout().lock();
+    let _ = stdout.write_fmt(args);
+    let _ = stdout.flush();
+}
+
 fn level_tag_no_color(level: Level) -> &'static str {
     match level {
         Level::Error => "[ERROR]",
+        Level::Warn => "[WARN]",
         Level::Info => "[INFO]",
         Level::Debug => "[DEBUG]",
-        _ => unreachable!(),
+        Level::Trace => unreachable!(),
     }
 }
 
@@ -77,9 +183,10 @@ fn level_tag(level: Level, enable_colors: bool) -> &'static str {
     if enable_colors {
         match level {
             Level::Error => "\x1b[31m[ERROR]\x1b[0m", //red
+            Level::Warn => "\x1b[33m[WARN]\x1b[0m",   //yellow
             Level::Info => "\x1b[34m[INFO]\x1b[0m",   //blue
             Level::Debug => "\x1b[36m[DEBUG]\x1b[0m", //cyan
-            _ => unreachable!(),
+            Level::Trace => unreachable!(),
         }
     } else {
         level_tag_no_color(level)
@@ -0,0 +1,251 @@
+mod json;
+mod websocket;
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::mpsc::{self, Sender},
+    thread::Builder as ThreadBuilder,
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use log::{debug, error, info};
+
+use crate::{
+    args::{Parse, Parser},
+    http::encode_base64,
+};
+use json::Value;
+use websocket::WebSocket;
+
+#[cfg(not(feature = "ring"))]
+use aws_lc_rs::digest;
+#[cfg(feature = "ring")]
+use ring::digest;
+
+#[derive(Default, Clone)]
+pub struct Args {
+    websocket: Option<String>,
+    password: Option<String>,
+    ad_scene: Option<String>,
+    scene: Option<String>,
+}
+
+impl Debug for Args {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Args")
+            .field("websocket", &self.websocket)
+            .field("password", &self.password.as_ref().map(|_| "<hidden>"))
+            .field("ad_scene", &self.ad_scene)
+            .field("scene", &self.scene)
+            .finish()
+    }
+}
+
+impl Parse for Args {
+    fn parse(&mut self, parser: &mut Parser) -> Result<()> {
+        parser.parse_opt(&mut self.websocket, "--obs-websocket")?;
+        parser.parse_opt(&mut self.password, "--obs-password")?;
+        parser.parse_opt(&mut self.ad_scene, "--obs-ad-scene")?;
+        parser.parse_opt(&mut self.scene, "--obs-scene")?;
+
+        ensure!(
+            self.websocket.is_none() || self.ad_scene.is_some(),
+            "--obs-websocket requires --obs-ad-scene"
+        );
+
+        Ok(())
+    }
+}
+
+enum Event {
+    AdBreakStarted,
+    AdBreakEnded,
+}
+
+//Switches scenes on a separate thread so a slow or missing OBS never stalls the stream
+pub struct Obs {
+    sender: Sender<Event>,
+}
+
+impl Obs {
+    pub fn new(args: &Args) -> Result<Option<Self>> {
+        let (Some(addr), Some(ad_scene)) = (&args.websocket, &args.ad_scene) else {
+            return Ok(None);
+        };
+
+        let addr = addr.clone();
+        let password = args.password.clone();
+        let ad_scene = ad_scene.clone();
+        let scene = args.scene.clone();
+
+        let (sender, receiver) = mpsc::channel();
+        ThreadBuilder::new()
+            .name("obs".to_owned())
+            .spawn(move || {
+                let mut client = None;
+                let mut return_scene = None;
+                for event in receiver {
+                    let result = match event {
+                        Event::AdBreakStarted => Client::get(&mut client, &addr, &password)
+                            .and_then(|c| {
+                                return_scene = match &scene {
+                                    Some(scene) => Some(scene.clone()),
+                                    None => Some(c.current_scene()?),
+                                };
+
+                                c.set_scene(&ad_scene)
+                            }),
+                        Event::AdBreakEnded => return_scene.take().map_or(Ok(()), |scene| {
+                            Client::get(&mut client, &addr, &password)
+                                .and_then(|c| c.set_scene(&scene))
+                        }),
+                    };
+
+                    if let Err(e) = result {
+                        error!("OBS: {e}");
+                        client = None;
+                    }
+                }
+            })
+            .context("Failed to spawn OBS thread")?;
+
+        Ok(Some(Self { sender }))
+    }
+
+    pub fn ad_break_started(&self) {
+        let _ = self.sender.send(Event::AdBreakStarted);
+    }
+
+    pub fn ad_break_ended(&self) {
+        let _ = self.sender.send(Event::AdBreakEnded);
+    }
+}
+
+//obs-websocket 5.x protocol
+struct Client {
+    socket: WebSocket,
+    request_id: u64,
+}
+
+impl Client {
+    const RPC_VERSION: u32 = 1;
+
+    //Connects on first use and again after errors, OBS may not be running yet
+    fn get<'a>(
+        client: &'a mut Option<Self>,
+        addr: &str,
+        password: &Option<String>,
+    ) -> Result<&'a mut Self> {
+        if client.is_none() {
+            *client = Some(Self::connect(addr, password.as_deref())?);
+        }
+
+        client.as_mut().context("Missing OBS client")
+    }
+
+    fn connect(addr: &str, password: Option<&str>) -> Result<Self> {
+        let mut socket = WebSocket::connect(addr, "obswebsocket.json")?;
+
+        let hello = socket.recv()?;
+        debug!("OBS hello: {hello}");
+        let hello = Value::parse(&hello).context("Invalid OBS hello message")?;
+        ensure!(op(&hello) == Some(0), "Unexpected OBS hello message");
+
+        let authentication = match (
+            string(&hello, &["d", "authentication", "challenge"]),
+            string(&hello, &["d", "authentication", "salt"]),
+        ) {
+            (Some(challenge), Some(salt)) => {
+                let password = password.context("OBS websocket requires --obs-password")?;
+                let secret = encode_base64(sha256(format!("{password}{salt}").as_bytes()).as_ref());
+
+                format!(
+                    r#","authentication":"{}""#,
+                    encode_base64(sha256(format!("{secret}{challenge}").as_bytes()).as_ref())
+                )
+            }
+            _ => String::new(),
+        };
+
+        socket.send(&format!(
+            r#"{{"op":1,"d":{{"rpcVersion":{}{authentication},"eventSubscriptions":0}}}}"#,
+            Self::RPC_VERSION,
+        ))?;
+
+        let identified = Value::parse(&socket.recv()?).context("Invalid OBS identify response")?;
+        ensure!(
+            op(&identified) == Some(2),
+            "OBS websocket authentication failed"
+        );
+
+        info!("Connected to OBS websocket: {addr}");
+        Ok(Self {
+            socket,
+            request_id: u64::default(),
+        })
+    }
+
+    fn current_scene(&mut self) -> Result<String> {
+        let response = self.request("GetCurrentProgramScene", "{}")?;
+        string(&response, &["d", "responseData", "currentProgramSceneName"])
+            .map(ToOwned::to_owned)
+            .context("Failed to find current OBS scene")
+    }
+
+    fn set_scene(&mut self, scene: &str) -> Result<()> {
+        self.request(
+            "SetCurrentProgramScene",
+            &format!(r#"{{"sceneName":"{}"}}"#, escape_json(scene)),
+        )?;
+
+        info!("Switched OBS scene to {scene}");
+        Ok(())
+    }
+
+    fn request(&mut self, request_type: &str, data: &str) -> Result<Value> {
+        self.request_id += 1;
+        let id = self.request_id.to_string();
+
+        self.socket.send(&format!(
+            r#"{{"op":6,"d":{{"requestType":"{request_type}","requestId":"{id}","requestData":{data}}}}}"#,
+        ))?;
+
+        loop {
+            let response = self.socket.recv()?;
+            debug!("OBS response: {response}");
+
+            let response = Value::parse(&response).context("Invalid OBS response")?;
+            if op(&response) != Some(7) || string(&response, &["d", "requestId"]) != Some(&id) {
+                continue;
+            }
+
+            let status = response.get(&["d", "requestStatus"]);
+            if status.and_then(|s| s.get(&["result"])?.as_bool()) != Some(true) {
+                bail!(
+                    "{request_type} failed: {}",
+                    status
+                        .and_then(|s| s.get(&["comment"])?.as_str())
+                        .unwrap_or("<unknown>")
+                );
+            }
+
+            return Ok(response);
+        }
+    }
+}
+
+fn string<'a>(message: &'a Value, path: &[&str]) -> Option<&'a str> {
+    message.get(path)?.as_str()
+}
+
+fn op(message: &Value) -> Option<u64> {
+    message.get(&["op"])?.as_u64()
+}
+
+fn sha256(data: &[u8]) -> digest::Digest {
+    digest::digest(&digest::SHA256, data)
+}
+
+fn escape_json(string: &str) -> String {
+    string.replace('\\', r"\\").replace('"', r#"\""#)
+}
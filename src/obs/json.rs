@@ -0,0 +1,193 @@
+use std::{iter::Peekable, str::Chars};
+
+use anyhow::{Context, Result, bail, ensure};
+
+//Minimal RFC 8259 parser, only what's needed to read obs-websocket messages
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array, //elements are checked but not kept, nothing needs them
+    Object(Vec<(String, Self)>),
+}
+
+impl Value {
+    pub fn parse(message: &str) -> Result<Self> {
+        let mut chars = message.chars().peekable();
+        let value = parse_value(&mut chars)?;
+
+        skip_whitespace(&mut chars);
+        ensure!(chars.next().is_none(), "Trailing data after JSON value");
+
+        Ok(value)
+    }
+
+    //Nested object members, each key is looked up in the object found by the previous one
+    pub fn get(&self, path: &[&str]) -> Option<&Self> {
+        path.iter().try_fold(self, |value, key| match value {
+            Self::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        })
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub const fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(bool) => Some(*bool),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] //checked
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Self::Number(number) if number.fract() == 0.0 && *number >= 0.0 => Some(*number as u64),
+            _ => None,
+        }
+    }
+}
+
+type Input<'a> = Peekable<Chars<'a>>;
+
+fn parse_value(chars: &mut Input) -> Result<Value> {
+    skip_whitespace(chars);
+    match chars.peek().context("Unexpected end of JSON")? {
+        '{' => parse_object(chars),
+        '[' => parse_array(chars),
+        '"' => Ok(Value::String(parse_string(chars)?)),
+        't' => parse_literal(chars, "true", Value::Bool(true)),
+        'f' => parse_literal(chars, "false", Value::Bool(false)),
+        'n' => parse_literal(chars, "null", Value::Null),
+        _ => parse_number(chars),
+    }
+}
+
+fn parse_object(chars: &mut Input) -> Result<Value> {
+    chars.next(); //{
+
+    let mut members = Vec::new();
+    skip_whitespace(chars);
+    if chars.next_if_eq(&'}').is_some() {
+        return Ok(Value::Object(members));
+    }
+
+    loop {
+        skip_whitespace(chars);
+        let key = parse_string(chars)?;
+
+        skip_whitespace(chars);
+        ensure!(chars.next() == Some(':'), "Expected ':' in JSON object");
+        members.push((key, parse_value(chars)?));
+
+        skip_whitespace(chars);
+        match chars.next() {
+            Some(',') => (),
+            Some('}') => return Ok(Value::Object(members)),
+            _ => bail!("Expected ',' or '}}' in JSON object"),
+        }
+    }
+}
+
+fn parse_array(chars: &mut Input) -> Result<Value> {
+    chars.next(); //[
+
+    skip_whitespace(chars);
+    if chars.next_if_eq(&']').is_some() {
+        return Ok(Value::Array);
+    }
+
+    loop {
+        parse_value(chars)?;
+
+        skip_whitespace(chars);
+        match chars.next() {
+            Some(',') => (),
+            Some(']') => return Ok(Value::Array),
+            _ => bail!("Expected ',' or ']' in JSON array"),
+        }
+    }
+}
+
+fn parse_string(chars: &mut Input) -> Result<String> {
+    ensure!(chars.next() == Some('"'), "Expected JSON string");
+
+    let mut string = String::new();
+    loop {
+        match chars.next().context("Unterminated JSON string")? {
+            '"' => return Ok(string),
+            '\\' => match chars.next().context("Unterminated JSON string")? {
+                'b' => string.push('\u{8}'),
+                'f' => string.push('\u{c}'),
+                'n' => string.push('\n'),
+                'r' => string.push('\r'),
+                't' => string.push('\t'),
+                'u' => string.push(parse_unicode_escape(chars)?),
+                c @ ('"' | '\\' | '/') => string.push(c),
+                c => bail!("Invalid JSON escape: \\{c}"),
+            },
+            c => string.push(c),
+        }
+    }
+}
+
+fn parse_unicode_escape(chars: &mut Input) -> Result<char> {
+    let high = parse_hex4(chars)?;
+    if !(0xD800..0xDC00).contains(&high) {
+        return char::from_u32(high).context("Invalid JSON unicode escape");
+    }
+
+    //Characters outside the BMP are escaped as a UTF-16 surrogate pair
+    ensure!(
+        chars.next() == Some('\\') && chars.next() == Some('u'),
+        "Unpaired JSON surrogate escape"
+    );
+    let low = parse_hex4(chars)?;
+    ensure!(
+        (0xDC00..0xE000).contains(&low),
+        "Invalid JSON surrogate escape"
+    );
+
+    char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+        .context("Invalid JSON unicode escape")
+}
+
+fn parse_hex4(chars: &mut Input) -> Result<u32> {
+    let hex = chars.take(4).collect::<String>();
+    ensure!(hex.len() == 4, "Invalid JSON unicode escape");
+
+    u32::from_str_radix(&hex, 16).context("Invalid JSON unicode escape")
+}
+
+fn parse_literal(chars: &mut Input, literal: &str, value: Value) -> Result<Value> {
+    ensure!(
+        literal.chars().all(|c| chars.next() == Some(c)),
+        "Invalid JSON literal"
+    );
+
+    Ok(value)
+}
+
+fn parse_number(chars: &mut Input) -> Result<Value> {
+    let mut number = String::new();
+    while let Some(c) = chars.next_if(|c| matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E')) {
+        number.push(c);
+    }
+
+    Ok(Value::Number(
+        number.parse().context("Invalid JSON number")?,
+    ))
+}
+
+fn skip_whitespace(chars: &mut Input) {
+    while chars
+        .next_if(|c| matches!(c, ' ' | '\t' | '\n' | '\r'))
+        .is_some()
+    {}
+}
@@ -0,0 +1,134 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use getrandom::getrandom;
+
+use crate::http::encode_base64;
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+//Payload lengths that are followed by an extended length
+const LEN_16: u8 = 0x7E;
+const LEN_64: u8 = 0x7F;
+
+const FIN: u8 = 0x80;
+const MASKED: u8 = 0x80;
+
+//Minimal RFC 6455 client, text messages only
+pub struct WebSocket {
+    stream: BufReader<TcpStream>,
+}
+
+impl WebSocket {
+    pub fn connect(addr: &str, protocol: &str) -> Result<Self> {
+        const TIMEOUT: Duration = Duration::from_secs(10);
+
+        let sock = TcpStream::connect(addr).context("Failed to connect to websocket")?;
+        sock.set_read_timeout(Some(TIMEOUT))?;
+        sock.set_write_timeout(Some(TIMEOUT))?;
+        sock.set_nodelay(true)?;
+
+        let mut key = [0u8; 16];
+        getrandom(&mut key)?;
+
+        let mut stream = BufReader::new(sock);
+        write!(
+            stream.get_mut(),
+            "GET / HTTP/1.1\r\n\
+             Host: {addr}\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: {key}\r\n\
+             Sec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Protocol: {protocol}\r\n\
+             \r\n",
+            key = encode_base64(&key),
+        )?;
+
+        let mut line = String::new();
+        stream.read_line(&mut line)?;
+        ensure!(
+            line.split_whitespace().nth(1) == Some("101"),
+            "Websocket upgrade failed: {}",
+            line.trim_end(),
+        );
+
+        //Rest of the response headers
+        while {
+            line.clear();
+            stream.read_line(&mut line)? > 2
+        } {}
+
+        Ok(Self { stream })
+    }
+
+    pub fn send(&mut self, message: &str) -> Result<()> {
+        self.send_frame(OPCODE_TEXT, message.as_bytes())
+    }
+
+    //Control frames are handled here, only text messages are returned
+    pub fn recv(&mut self) -> Result<String> {
+        loop {
+            let mut head = [0u8; 2];
+            self.stream.read_exact(&mut head)?;
+
+            let opcode = head[0] & 0x0F;
+            let len = match head[1] & 0x7F {
+                LEN_16 => {
+                    let mut len = [0u8; 2];
+                    self.stream.read_exact(&mut len)?;
+                    u64::from(u16::from_be_bytes(len))
+                }
+                LEN_64 => {
+                    let mut len = [0u8; 8];
+                    self.stream.read_exact(&mut len)?;
+                    u64::from_be_bytes(len)
+                }
+                len => u64::from(len),
+            };
+
+            let mut payload = Vec::new();
+            (&mut self.stream).take(len).read_to_end(&mut payload)?;
+            ensure!(payload.len() as u64 == len, "Websocket closed mid frame");
+
+            match opcode {
+                OPCODE_TEXT => return Ok(String::from_utf8(payload)?),
+                OPCODE_PING => self.send_frame(OPCODE_PONG, &payload)?,
+                OPCODE_CLOSE => bail!("Websocket closed by server"),
+                _ => (),
+            }
+        }
+    }
+
+    fn send_frame(&mut self, opcode: u8, payload: &[u8]) -> Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(FIN | opcode);
+        match payload.len() {
+            len @ 0..126 => frame.push(MASKED | u8::try_from(len)?),
+            len @ 126..=0xFFFF => {
+                frame.push(MASKED | LEN_16);
+                frame.extend_from_slice(&u16::try_from(len)?.to_be_bytes());
+            }
+            len => {
+                frame.push(MASKED | LEN_64);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+
+        //Client frames must be masked
+        let mut mask = [0u8; 4];
+        getrandom(&mut mask)?;
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+
+        self.stream.get_mut().write_all(&frame)?;
+        Ok(())
+    }
+}
@@ -1,23 +1,63 @@
+#[cfg(feature = "ffi")]
+#[allow(unsafe_code)] //FFI
+mod callback;
+#[cfg(unix)]
+mod fifo;
 mod file;
 mod player;
+#[cfg(feature = "rtmp")]
+mod rtmp;
+mod serve;
+mod stdout;
 mod tcp;
+mod udp;
 
-pub use player::{Player, PlayerClosedError};
+#[cfg(feature = "ffi")]
+pub use callback::{Callback, CallbackStoppedError, Function as CallbackFunction};
+pub use file::FileSizeLimitError;
+pub use player::{Player, PlayerClosedError, mpv_buffered, mpv_drop_buffers};
+pub use serve::SERVE;
+pub use stdout::StdoutClosedError;
 
-use std::io::{self, Write};
+use player::LazyPlayer;
 
-use anyhow::{Result, ensure};
+#[cfg(target_os = "linux")]
+use std::os::fd::{BorrowedFd, OwnedFd};
+use std::{
+    io::{self, Write},
+    mem,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, bail, ensure};
 use log::{debug, info};
 
+#[cfg(unix)]
+use fifo::{Args as FifoArgs, Fifo};
 use file::{Args as FileArgs, File};
 use player::Args as PlayerArgs;
+#[cfg(feature = "rtmp")]
+use rtmp::{Args as RtmpArgs, Rtmp};
+use serve::Args as ServeArgs;
+use stdout::{Args as StdoutArgs, Stdout};
 use tcp::{Args as TcpArgs, Tcp};
+use udp::{Args as UdpArgs, Udp};
 
 use crate::args::{Parse, Parser};
 
 pub trait Output: Write + Send {
     fn set_header(&mut self, header: &[u8]) -> io::Result<()>;
 
+    //Recordings only
+    fn path(&self) -> Option<&Path> {
+        None
+    }
+
+    //Placeholder for filtered ad segments, only players use it
+    fn write_slate(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
     fn should_wait(&self) -> bool {
         false
     }
@@ -25,28 +65,102 @@ pub trait Output: Write + Send {
     fn wait_for_output(&mut self) -> io::Result<()> {
         Ok(())
     }
+
+    //Pipe the output writes to, players only
+    #[cfg(target_os = "linux")]
+    fn pipe(&self) -> Option<BorrowedFd<'_>> {
+        None
+    }
+}
+
+#[derive(Default, Copy, Clone, Debug)]
+pub enum FlushPolicy {
+    #[default]
+    Segment,
+    Size,
+}
+
+impl FlushPolicy {
+    pub fn new(arg: &str) -> Result<Self> {
+        match arg {
+            "segment" => Ok(Self::Segment),
+            "size" => Ok(Self::Size),
+            _ => bail!("Invalid flush policy"),
+        }
+    }
 }
 
 #[derive(Default, Debug)]
 pub struct Args {
     pub player: PlayerArgs,
     tcp: TcpArgs,
+    udp: UdpArgs,
+    serve: ServeArgs,
+    stdout: StdoutArgs,
     file: FileArgs,
+    #[cfg(unix)]
+    fifo: FifoArgs,
+    #[cfg(feature = "rtmp")]
+    rtmp: RtmpArgs,
+    write_buffer: usize,
+    flush_policy: FlushPolicy,
+}
+
+impl Args {
+    pub const fn record_metadata(&self) -> bool {
+        self.file.metadata()
+    }
+
+    pub const fn stdout(&self) -> bool {
+        self.stdout.enabled()
+    }
 }
 
 impl Parse for Args {
     fn parse(&mut self, parser: &mut Parser) -> Result<()> {
+        parser.parse(&mut self.write_buffer, "--write-buffer")?;
+        parser.parse_fn(&mut self.flush_policy, "--flush-policy", FlushPolicy::new)?;
         self.player.parse(parser)?;
         self.tcp.parse(parser)?;
+        self.udp.parse(parser)?;
+        self.serve.parse(parser)?;
+        self.stdout.parse(parser)?;
+        if self.player.take_stdout() {
+            self.stdout.enable();
+        }
+
         self.file.parse(parser)?;
+        #[cfg(unix)]
+        self.fifo.parse(parser)?;
+        #[cfg(feature = "rtmp")]
+        self.rtmp.parse(parser)?;
+        #[cfg(not(feature = "rtmp"))]
+        {
+            let mut rtmp = None::<String>;
+            parser.parse_opt(&mut rtmp, "--rtmp")?;
+            ensure!(
+                rtmp.is_none(),
+                "RTMP output is not enabled in this build (requires the 'rtmp' feature)"
+            );
+        }
 
         Ok(())
     }
 }
 
-#[derive(Default)]
 pub struct Writer {
     outputs: Vec<Box<dyn Output>>,
+
+    //Segment data is collected here and written in chunks of up to buf_size
+    buf: Vec<u8>,
+    buf_size: usize,
+    flush_policy: FlushPolicy,
+
+    //Copy of the current segment for --replay-buffer
+    capture: Option<Vec<u8>>,
+
+    //Segment kept back until it's complete, see hold
+    held: Option<Vec<u8>>,
 }
 
 impl Output for Writer {
@@ -55,6 +169,12 @@ impl Output for Writer {
         self.handle_outputs(|output| output.set_header(header))
     }
 
+    fn write_slate(&mut self) -> io::Result<()> {
+        debug!("Outputting ad slate");
+        self.write_buffered()?;
+        self.handle_outputs(|output| output.write_slate())
+    }
+
     fn should_wait(&self) -> bool {
         if self.outputs.len() == 1
             && let Some(output) = self.outputs.first()
@@ -81,6 +201,14 @@ impl Write for Writer {
     }
 
     fn flush(&mut self) -> io::Result<()> {
+        if let Some(held) = self.held.take() {
+            self.write_output(&held)?;
+        }
+
+        if matches!(self.flush_policy, FlushPolicy::Segment) {
+            self.write_buffered()?;
+        }
+
         self.handle_outputs(Write::flush)?;
 
         debug!("Finished writing segment");
@@ -88,23 +216,177 @@ impl Write for Writer {
     }
 
     fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
-        self.handle_outputs(|output| output.write_all(buf))
+        if let Some(capture) = &mut self.capture {
+            capture.extend_from_slice(buf);
+        }
+
+        if let Some(held) = &mut self.held {
+            held.extend_from_slice(buf);
+            return Ok(());
+        }
+
+        self.write_output(buf)
+    }
+}
+
+//Whatever --flush-policy size left behind still belongs to the stream
+impl Drop for Writer {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            debug!("Failed to write remaining buffer: {e}");
+        }
     }
 }
 
 impl Writer {
     pub fn new(args: &Args, channel: &str) -> Result<Self> {
-        let mut writer = Self::default();
+        let mut writer = Self::with_buffer(args);
 
-        writer.add_output(Player::new(&args.player, channel)?);
+        if args.player.lazy() {
+            writer.add_output(LazyPlayer::new(&args.player, channel));
+        } else {
+            writer.add_output(Player::new(&args.player, channel)?);
+        }
         writer.add_output(Tcp::new(&args.tcp)?);
+        writer.add_output(Udp::new(&args.udp)?);
+        writer.add_output(Stdout::new(&args.stdout));
         writer.add_output(File::new(&args.file)?);
+        #[cfg(unix)]
+        writer.add_output(Fifo::new(&args.fifo)?);
+        #[cfg(feature = "rtmp")]
+        writer.add_output(Rtmp::new(&args.rtmp)?);
+        #[cfg(feature = "ffi")]
+        writer.add_output(Callback::new());
 
-        ensure!(!writer.outputs.is_empty(), "No output configured");
+        //Segments are served from the worker's copy, there's nothing to write
+        serve::spawn(&args.serve)?;
+        ensure!(
+            !writer.outputs.is_empty() || args.serve.enabled(),
+            "No output configured"
+        );
 
         Ok(writer)
     }
 
+    pub fn audio(args: &Args) -> Result<Option<Self>> {
+        let mut writer = Self::with_buffer(args);
+        writer.add_output(File::audio(&args.file)?);
+
+        Ok((!writer.outputs.is_empty()).then_some(writer))
+    }
+
+    //Moves the recording to its own writer for --record-quality
+    pub fn split_recording(&mut self) -> Result<Self> {
+        let (outputs, rest) = mem::take(&mut self.outputs)
+            .into_iter()
+            .partition::<Vec<_>, _>(|output| output.path().is_some());
+        self.outputs = rest;
+
+        ensure!(
+            !outputs.is_empty(),
+            "--record-quality requires a recording (-r)"
+        );
+        ensure!(
+            !self.outputs.is_empty(),
+            "--record-quality requires another output to watch"
+        );
+
+        Ok(Self {
+            outputs,
+            buf: Vec::with_capacity(self.buf_size),
+            buf_size: self.buf_size,
+            flush_policy: self.flush_policy,
+            capture: None,
+            held: None,
+        })
+    }
+
+    //Writes out the remaining buffer regardless of --flush-policy, for when the stream stops
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.outputs.is_empty() {
+            return Ok(());
+        }
+
+        self.write_buffered()?;
+        self.handle_outputs(Write::flush)
+    }
+
+    pub fn recording_path(&self) -> Option<PathBuf> {
+        self.outputs
+            .iter()
+            .find_map(|output| output.path())
+            .map(Path::to_path_buf)
+    }
+
+    pub fn enable_capture(&mut self) {
+        self.capture.get_or_insert_default();
+    }
+
+    //Keeps the segment out of the outputs until it's flushed as complete, so a download
+    //aborted by --segment-timeout doesn't leave a truncated segment behind. Anything
+    //still held from a failed download is dropped.
+    pub fn hold(&mut self, enabled: bool) {
+        self.held = enabled.then(Vec::new);
+    }
+
+    //Player's stdin when it's the only place the segment goes, so plain HTTP bodies can be
+    //spliced to it. Not while anything else needs to see the bytes.
+    #[cfg(target_os = "linux")]
+    pub fn pipe(&self) -> Option<OwnedFd> {
+        if self.outputs.len() != 1
+            || self.buf_size > 0
+            || self.capture.is_some()
+            || self.held.is_some()
+        {
+            return None;
+        }
+
+        self.outputs.first()?.pipe()?.try_clone_to_owned().ok()
+    }
+
+    //Everything written since the last call
+    pub fn take_capture(&mut self) -> Option<Vec<u8>> {
+        self.capture.as_mut().map(mem::take)
+    }
+
+    fn with_buffer(args: &Args) -> Self {
+        Self {
+            outputs: Vec::default(),
+            buf: Vec::with_capacity(args.write_buffer),
+            buf_size: args.write_buffer,
+            flush_policy: args.flush_policy,
+            capture: None,
+            held: None,
+        }
+    }
+
+    fn write_output(&mut self, buf: &[u8]) -> io::Result<()> {
+        if self.buf_size == 0 {
+            return self.handle_outputs(|output| output.write_all(buf));
+        }
+
+        self.buf.extend_from_slice(buf);
+        if self.buf.len() >= self.buf_size {
+            self.write_buffered()?;
+        }
+
+        Ok(())
+    }
+
+    fn write_buffered(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        let buf = mem::take(&mut self.buf);
+        let result = self.handle_outputs(|output| output.write_all(&buf));
+
+        self.buf = buf;
+        self.buf.clear();
+
+        result
+    }
+
     fn add_output(&mut self, output: Option<impl Output + 'static>) {
         if let Some(output) = output {
             self.outputs.push(Box::new(output));
@@ -131,7 +413,10 @@ impl Writer {
 
             true
         });
-        debug_assert!(!self.outputs.is_empty() || self.outputs.is_empty() && result.is_err());
+        debug_assert!(
+            !self.outputs.is_empty()
+                || self.outputs.is_empty() && (result.is_err() || SERVE.is_enabled())
+        );
 
         result
     }
@@ -0,0 +1,80 @@
+use std::{
+    ffi::{c_int, c_void},
+    fmt::{self, Display, Formatter},
+    io::{self, Write},
+    sync::{Mutex, PoisonError},
+};
+
+use log::info;
+
+use super::Output;
+
+//Called on the HLS worker thread with each chunk of the stream, non-zero stops the stream
+pub type Function =
+    unsafe extern "C" fn(data: *const u8, len: usize, user_data: *mut c_void) -> c_int;
+
+//Read when a session starts, see thc_set_output_callback
+static CALLBACK: Mutex<Option<Callback>> = Mutex::new(None);
+
+#[derive(Debug)]
+pub struct CallbackStoppedError;
+
+impl std::error::Error for CallbackStoppedError {}
+
+impl Display for CallbackStoppedError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("Output callback stopped the stream")
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Callback {
+    function: Function,
+    user_data: *mut c_void,
+}
+
+//SAFETY: thc_set_output_callback requires user_data to be usable from any thread
+unsafe impl Send for Callback {}
+
+impl Output for Callback {
+    fn set_header(&mut self, header: &[u8]) -> io::Result<()> {
+        self.write_all(header)
+    }
+}
+
+impl Write for Callback {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        unreachable!();
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        //SAFETY: the function was passed in as a valid callback, buf outlives the call
+        if unsafe { (self.function)(buf.as_ptr(), buf.len(), self.user_data) } != 0 {
+            return Err(io::Error::other(CallbackStoppedError));
+        }
+
+        Ok(())
+    }
+}
+
+impl Callback {
+    pub fn new() -> Option<Self> {
+        let callback = *CALLBACK.lock().unwrap_or_else(PoisonError::into_inner);
+        if callback.is_some() {
+            info!("Writing to output callback");
+        }
+
+        callback
+    }
+
+    pub fn set(function: Option<Function>, user_data: *mut c_void) {
+        *CALLBACK.lock().unwrap_or_else(PoisonError::into_inner) = function.map(|function| Self {
+            function,
+            user_data,
+        });
+    }
+}
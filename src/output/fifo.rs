@@ -0,0 +1,154 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Write},
+    os::unix::fs::FileTypeExt,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread::Builder as ThreadBuilder,
+};
+
+use anyhow::{Context, Result, ensure};
+use log::info;
+use nix::{sys::stat::Mode, unistd};
+
+use super::Output;
+use crate::args::{Parse, Parser};
+
+#[derive(Default, Debug)]
+pub struct Args {
+    path: Option<String>,
+}
+
+impl Parse for Args {
+    fn parse(&mut self, parser: &mut Parser) -> Result<()> {
+        parser.parse_opt(&mut self.path, "--fifo")?;
+        parser.parse_opt(&mut self.path, "--output-fifo")?;
+
+        Ok(())
+    }
+}
+
+pub struct Fifo {
+    path: String,
+    state: State,
+    header: Option<Box<[u8]>>,
+}
+
+impl Output for Fifo {
+    fn set_header(&mut self, header: &[u8]) -> io::Result<()> {
+        self.header = Some(header.into());
+        Ok(())
+    }
+
+    fn should_wait(&self) -> bool {
+        matches!(self.state, State::Waiting(_))
+    }
+
+    fn wait_for_output(&mut self) -> io::Result<()> {
+        if let State::Waiting(receiver) = &self.state {
+            let file = receiver
+                .recv()
+                .map_err(|_| io::Error::other("FIFO open thread exited"))??;
+
+            self.attach(file)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for Fifo {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        unreachable!();
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let State::Waiting(receiver) = &self.state {
+            match receiver.try_recv() {
+                Ok(file) => self.attach(file?)?,
+                Err(TryRecvError::Empty) => (),
+                Err(TryRecvError::Disconnected) => {
+                    return Err(io::Error::other("FIFO open thread exited"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        if let State::Attached(file) = &mut self.state {
+            match file.write_all(buf) {
+                Ok(()) => (),
+                Err(e) if e.kind() == ErrorKind::BrokenPipe => {
+                    info!("FIFO reader disconnected, waiting for a new one...");
+                    self.state = State::Waiting(Self::open(&self.path)?);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Fifo {
+    pub fn new(args: &Args) -> Result<Option<Self>> {
+        let Some(path) = &args.path else {
+            return Ok(None);
+        };
+
+        match fs::metadata(path) {
+            Ok(metadata) => ensure!(
+                metadata.file_type().is_fifo(),
+                "{path} exists and is not a FIFO"
+            ),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                //Same as mkfifo(1), the umask still applies
+                unistd::mkfifo(path.as_str(), Mode::from_bits_truncate(0o666))
+                    .with_context(|| format!("Failed to create FIFO: {path}"))?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        info!("Writing to FIFO: {path}");
+        Ok(Some(Self {
+            path: path.clone(),
+            state: State::Waiting(Self::open(path)?),
+            header: Option::default(),
+        }))
+    }
+
+    //Opening a FIFO for writing blocks until there's a reader, so do it on another thread
+    fn open(path: &str) -> io::Result<Receiver<io::Result<fs::File>>> {
+        let (sender, receiver) = mpsc::channel();
+        let path = path.to_owned();
+
+        ThreadBuilder::new()
+            .name("fifo open".to_owned())
+            .spawn(move || {
+                let _ = sender.send(OpenOptions::new().write(true).open(path));
+            })
+            .map_err(|e| io::Error::other(format!("Failed to spawn FIFO open thread: {e}")))?;
+
+        Ok(receiver)
+    }
+
+    fn attach(&mut self, mut file: fs::File) -> io::Result<()> {
+        info!("FIFO reader connected");
+        if let Some(header) = &self.header
+            && let Err(e) = file.write_all(header)
+        {
+            info!("Failed to send segment header to FIFO reader ({e}), waiting for a new one...");
+            self.state = State::Waiting(Self::open(&self.path)?);
+            return Ok(());
+        }
+
+        self.state = State::Attached(file);
+        Ok(())
+    }
+}
+
+enum State {
+    Waiting(Receiver<io::Result<fs::File>>),
+    Attached(fs::File),
+}
@@ -1,36 +1,132 @@
 use std::{
-    fs,
-    io::{self, Write},
+    fmt::{self, Display, Formatter},
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result, bail, ensure};
 use log::info;
 
 use super::Output;
 use crate::args::{Parse, Parser};
 
+#[derive(Default, Copy, Clone, Debug)]
+pub enum Mode {
+    Append,
+    Overwrite,
+    #[default]
+    Fail,
+    Rename,
+}
+
+impl Mode {
+    pub fn new(arg: &str) -> Result<Self> {
+        match arg {
+            "append" => Ok(Self::Append),
+            "overwrite" => Ok(Self::Overwrite),
+            "fail" => Ok(Self::Fail),
+            "rename" => Ok(Self::Rename),
+            _ => bail!("Invalid record mode"),
+        }
+    }
+}
+
+#[derive(Default, Copy, Clone, Debug)]
+pub enum LimitAction {
+    #[default]
+    Exit,
+    Split,
+}
+
+impl LimitAction {
+    pub fn new(arg: &str) -> Result<Self> {
+        match arg {
+            "exit" => Ok(Self::Exit),
+            "split" => Ok(Self::Split),
+            _ => bail!("Invalid max file size action"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FileSizeLimitError;
+
+impl std::error::Error for FileSizeLimitError {}
+
+impl Display for FileSizeLimitError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("Maximum file size reached")
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct Args {
     path: Option<String>,
-    overwrite: bool,
+    audio_path: Option<String>,
+    mode: Mode,
+    max_filesize: Option<u64>,
+    max_filesize_action: LimitAction,
+    metadata: bool,
 }
 
 impl Parse for Args {
     fn parse(&mut self, parser: &mut Parser) -> Result<()> {
-        parser.parse_opt_cfg(&mut self.path, "-r", "record")?;
-        parser.parse_switch(&mut self.overwrite, "--overwrite")?;
+        parser.parse_opt_or(&mut self.path, "-r", "--record")?;
+        parser.parse_opt(&mut self.audio_path, "--audio-record")?;
+        parser.parse_fn(&mut self.mode, "--record-mode", Mode::new)?;
+
+        let mut overwrite = false;
+        parser.parse_switch(&mut overwrite, "--overwrite")?;
+        if overwrite {
+            self.mode = Mode::Overwrite;
+        }
+
+        parser.parse_fn(&mut self.max_filesize, "--max-filesize", parse_size)?;
+        parser.parse_fn(
+            &mut self.max_filesize_action,
+            "--max-filesize-action",
+            LimitAction::new,
+        )?;
+        parser.parse_switch(&mut self.metadata, "--record-metadata")?;
+
+        ensure!(
+            !self.metadata || self.path.is_some(),
+            "--record-metadata requires a recording (-r)"
+        );
 
         Ok(())
     }
 }
 
+impl Args {
+    pub const fn metadata(&self) -> bool {
+        self.metadata
+    }
+}
+
 pub struct File {
-    file: fs::File,
+    handle: fs::File,
+    path: PathBuf,
+    written: u64,
+
+    max_size: Option<u64>,
+    limit_action: LimitAction,
+    header: Option<Box<[u8]>>,
 }
 
 impl Output for File {
     fn set_header(&mut self, header: &[u8]) -> io::Result<()> {
-        self.file.write_all(header)
+        if self.max_size.is_some() {
+            self.header = Some(header.into());
+        }
+
+        self.written += header.len() as u64;
+        self.handle.write_all(header)
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(&self.path)
     }
 }
 
@@ -40,11 +136,22 @@ impl Write for File {
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.file.flush()
+        self.handle.flush()?;
+
+        //Only checked between segments so they're never cut in half
+        if self.max_size.is_some_and(|max| self.written >= max) {
+            match self.limit_action {
+                LimitAction::Exit => return Err(io::Error::other(FileSizeLimitError)),
+                LimitAction::Split => self.split()?,
+            }
+        }
+
+        Ok(())
     }
 
     fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
-        self.file.write_all(buf)
+        self.written += buf.len() as u64;
+        self.handle.write_all(buf)
     }
 }
 
@@ -54,15 +161,106 @@ impl File {
             return Ok(None);
         };
 
-        info!("Recording to: {path}");
-        if args.overwrite {
-            return Ok(Some(Self {
-                file: fs::File::create(path)?,
-            }));
+        let file = Self::open(path, args)?;
+        info!("Recording to: {}", file.path.display());
+
+        Ok(Some(file))
+    }
+
+    pub fn audio(args: &Args) -> Result<Option<Self>> {
+        let Some(path) = &args.audio_path else {
+            return Ok(None);
+        };
+
+        let file = Self::open(path, args)?;
+        info!("Recording audio to: {}", file.path.display());
+
+        Ok(Some(file))
+    }
+
+    fn open(path: &str, args: &Args) -> Result<Self> {
+        let (file, path) = Self::create(path, args.mode)?;
+        Ok(Self {
+            written: file.metadata()?.len(),
+            handle: file,
+            path,
+            max_size: args.max_filesize,
+            limit_action: args.max_filesize_action,
+            header: Option::default(),
+        })
+    }
+
+    fn split(&mut self) -> io::Result<()> {
+        let (file, path) = Self::create_renamed(&self.path).map_err(io::Error::other)?;
+        info!(
+            "Maximum file size reached, recording to: {}",
+            path.display()
+        );
+
+        self.handle = file;
+        self.written = 0;
+        if let Some(header) = &self.header {
+            self.written += header.len() as u64;
+            self.handle.write_all(header)?;
         }
 
-        Ok(Some(Self {
-            file: fs::File::create_new(path)?,
-        }))
+        Ok(())
+    }
+
+    fn create(path: &str, mode: Mode) -> Result<(fs::File, PathBuf)> {
+        let path = PathBuf::from(path);
+        let file = match mode {
+            Mode::Append => OpenOptions::new().append(true).create(true).open(&path),
+            Mode::Overwrite => fs::File::create(&path),
+            Mode::Fail => fs::File::create_new(&path),
+            Mode::Rename => return Self::create_renamed(&path),
+        }
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+
+        Ok((file, path))
     }
+
+    //Adds a counter before the extension until the path is free, ie. recording-1.ts
+    fn create_renamed(path: &Path) -> Result<(fs::File, PathBuf)> {
+        let stem = path.file_stem().unwrap_or(path.as_os_str()).to_owned();
+        let extension = path.extension().map(ToOwned::to_owned);
+
+        let mut candidate = path.to_path_buf();
+        for count in 1..=u16::MAX {
+            match fs::File::create_new(&candidate) {
+                Ok(file) => return Ok((file, candidate)),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => (),
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to open {}", candidate.display()));
+                }
+            }
+
+            let mut name = stem.clone();
+            name.push(format!("-{count}"));
+            if let Some(extension) = &extension {
+                name.push(".");
+                name.push(extension);
+            }
+
+            candidate = path.with_file_name(name);
+        }
+
+        bail!("No free file name for {}", path.display());
+    }
+}
+
+//BYTES, or with a K, M or G suffix (powers of 1024)
+fn parse_size(arg: &str) -> Result<Option<u64>> {
+    let (num, shift) = match arg.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&arg[..i], 10),
+        Some((i, 'M' | 'm')) => (&arg[..i], 20),
+        Some((i, 'G' | 'g')) => (&arg[..i], 30),
+        _ => (arg, 0),
+    };
+
+    let size = num.parse::<u64>()?;
+    Ok(Some(
+        size.checked_mul(1 << shift).context("Size is too large")?,
+    ))
 }
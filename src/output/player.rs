@@ -1,21 +1,34 @@
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsFd, BorrowedFd};
 use std::{
     borrow::Cow,
     fmt::{self, Display, Formatter},
-    io::{self, ErrorKind::BrokenPipe, Write},
-    process::{Child, ChildStdin, Command, Stdio},
+    fs,
+    io::{self, BufRead, BufReader, ErrorKind::BrokenPipe, Write},
+    mem,
+    path::Path,
+    process::{Child, ChildStderr, ChildStdin, Command, ExitCode, ExitStatus, Stdio},
+    thread::{self, Builder as ThreadBuilder},
+    time::{Duration, Instant},
 };
 
 use anyhow::{Context, Result, bail};
-use log::{error, info};
+use log::{debug, error, info};
 
 use super::Output;
 use crate::args::{Parse, Parser};
 
 #[derive(Debug)]
-pub struct PlayerClosedError;
+pub struct PlayerClosedError(Option<ExitStatus>);
 
 impl std::error::Error for PlayerClosedError {}
 
+impl PlayerClosedError {
+    pub fn exit_code(&self) -> ExitCode {
+        exit_code(self.0)
+    }
+}
+
 impl Display for PlayerClosedError {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         f.write_str("Unhandled player closed")
@@ -24,38 +37,85 @@ impl Display for PlayerClosedError {
 
 #[derive(Clone, Debug)]
 pub struct Args {
-    path: Option<String>,
+    paths: Option<Vec<String>>, //tried in order until one spawns
     pargs: Cow<'static, str>,
     quiet: bool,
     no_kill: bool,
+    lazy: bool,
+    env: Option<Vec<(String, String)>>,
+    slate: Option<String>,
+    pipe_cmd: Option<String>,
+    ready_delay: Duration,
+    title: Option<String>, //fetched after parsing
 }
 
 impl Default for Args {
     fn default() -> Self {
         Self {
             pargs: "-".into(),
-            path: Option::default(),
+            paths: Option::default(),
             quiet: bool::default(),
             no_kill: bool::default(),
+            lazy: bool::default(),
+            env: Option::default(),
+            slate: Option::default(),
+            pipe_cmd: Option::default(),
+            ready_delay: Duration::default(),
+            title: Option::default(),
         }
     }
 }
 
 impl Parse for Args {
     fn parse(&mut self, parser: &mut Parser) -> Result<()> {
-        parser.parse_opt_cfg(&mut self.path, "-p", "player")?;
+        parser.parse_comma_list_or(&mut self.paths, "-p", "--player")?;
         parser.parse_cow_string_cfg(&mut self.pargs, "-a", "player-args")?;
         parser.parse_switch_or(&mut self.quiet, "-q", "--quiet")?;
         parser.parse_switch(&mut self.no_kill, "--no-kill")?;
+        parser.parse_switch(&mut self.lazy, "--lazy-player")?;
+        parser.parse_fn(&mut self.env, "--player-env", parse_env)?;
+        parser.parse_opt(&mut self.slate, "--ad-slate")?;
+        parser.parse_opt(&mut self.pipe_cmd, "--pipe-cmd")?;
+        parser.parse_duration(&mut self.ready_delay, "--player-ready-delay")?;
 
         Ok(())
     }
 }
 
+impl Args {
+    pub const fn lazy(&self) -> bool {
+        self.lazy
+    }
+
+    //Only players that are known to take a title from the command line
+    pub fn wants_title(&self) -> bool {
+        self.paths
+            .as_ref()
+            .is_some_and(|paths| paths.iter().any(|p| title_option(p).is_some()))
+    }
+
+    pub fn set_title(&mut self, title: String) {
+        self.title = Some(title);
+    }
+
+    //-p - writes to stdout instead of spawning a player
+    pub fn take_stdout(&mut self) -> bool {
+        if matches!(self.paths.as_deref(), Some([path]) if path == "-") {
+            self.paths = None;
+            return true;
+        }
+
+        false
+    }
+}
+
 pub struct Player {
     stdin: ChildStdin,
     process: Child,
+    filter: Option<Child>, //--pipe-cmd, exits on its own once its stdin is closed
     no_kill: bool,
+    slate: Option<Slate>,                //written in place of filtered ads
+    pending: Option<(Instant, Vec<u8>)>, //written once the player is ready
 }
 
 impl Drop for Player {
@@ -70,9 +130,28 @@ impl Drop for Player {
 
 impl Output for Player {
     fn set_header(&mut self, header: &[u8]) -> io::Result<()> {
-        self.stdin
-            .write_all(header)
-            .map_err(|e| self.handle_broken_pipe(e))
+        self.write_ready(header)
+    }
+
+    //Not until it's ready, what's pending has to go first
+    #[cfg(target_os = "linux")]
+    fn pipe(&self) -> Option<BorrowedFd<'_>> {
+        self.pending.is_none().then(|| self.stdin.as_fd())
+    }
+
+    fn write_slate(&mut self) -> io::Result<()> {
+        let Some(mut slate) = self.slate.take() else {
+            return Ok(());
+        };
+
+        let result = if slate.is_due() {
+            self.write_ready(&slate.data)
+        } else {
+            Ok(())
+        };
+        self.slate = Some(slate);
+
+        result
     }
 }
 
@@ -81,49 +160,93 @@ impl Write for Player {
         unreachable!();
     }
 
+    //Checks if the player is ready, in case no more data is written for a while
     fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_some() {
+            return self.write_ready(&[]);
+        }
+
         Ok(())
     }
 
     fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
-        self.stdin
-            .write_all(buf)
-            .map_err(|e| self.handle_broken_pipe(e))
+        self.write_ready(buf)
     }
 }
 
 impl Player {
+    //How long a player that closed its stdin gets to exit before the generic exit code is used
+    const EXIT_WAIT: Duration = Duration::from_secs(1);
+
     pub fn new(args: &Args, channel: &str) -> Result<Option<Self>> {
-        let Some(path) = &args.path else {
+        let Some(paths) = &args.paths else {
             return Ok(None);
         };
 
-        info!("Opening player: {path} {}", args.pargs);
-        let mut command = Command::new(path);
-        let player_args = prepare_player_args(&args.pargs, channel);
-        command
-            .args(player_args.split_whitespace())
-            .stdin(Stdio::piped());
+        let slate = args
+            .slate
+            .as_ref()
+            .map(|slate| fs::read(slate).context("Failed to read ad slate"))
+            .transpose()?
+            .map(Slate::new)
+            .transpose()?;
 
-        if args.quiet {
-            command.stdout(Stdio::null()).stderr(Stdio::null());
-        }
+        let mut paths = paths.iter().peekable();
+        let (mut process, mut filter) = loop {
+            let path = paths.next().context("No player set")?;
+            let mut filter = args
+                .pipe_cmd
+                .as_deref()
+                .map(|cmd| spawn_filter(cmd, args.quiet))
+                .transpose()?;
 
-        let mut process = command.spawn().context("Failed to open player")?;
-        let stdin = process
-            .stdin
+            info!("Opening player: {path} {}", args.pargs);
+            let mut command = Self::command(path, args, channel);
+
+            //The player reads the filter's output directly
+            match filter.as_mut().and_then(|f| f.stdout.take()) {
+                Some(stdout) => command.stdin(stdout),
+                None => command.stdin(Stdio::piped()),
+            };
+
+            match command.spawn() {
+                Ok(process) => break (process, filter),
+                Err(e) if paths.peek().is_some() => {
+                    error!("Failed to open player {path}: {e}, trying next player");
+                    if let Some(mut filter) = filter {
+                        let _ = filter.kill();
+                        let _ = filter.wait();
+                    }
+                }
+                Err(e) => return Err(e).context("Failed to open player"),
+            }
+        };
+
+        let stdin = filter
+            .as_mut()
+            .map_or(&mut process.stdin, |f| &mut f.stdin)
             .take()
             .context("Failed to open player stdin")?;
 
+        if let Some(stderr) = process.stderr.take() {
+            forward_stderr(stderr, "player")?;
+        }
+
         Ok(Some(Self {
             stdin,
             process,
+            filter,
             no_kill: args.no_kill,
+            slate,
+            pending: (!args.ready_delay.is_zero())
+                .then(|| (Instant::now() + args.ready_delay, Vec::new())),
         }))
     }
 
-    pub fn passthrough(args: &mut Args, url: &str, channel: &str) -> Result<()> {
+    pub fn passthrough(args: &mut Args, url: &str, channel: &str) -> Result<ExitCode> {
         info!("Passing through playlist URL to player");
+        args.pipe_cmd = None; //the player fetches the stream itself
+
         if args.pargs.split_whitespace().any(|a| a == "-") {
             args.pargs = args
                 .pargs
@@ -146,24 +269,412 @@ impl Player {
             bail!("No player set");
         };
 
-        player
+        let status = player
             .process
             .wait()
             .context("Failed to wait for player process")?;
 
-        Ok(())
+        Ok(exit_code(Some(status)))
+    }
+
+    fn command(path: &str, args: &Args, channel: &str) -> Command {
+        let mut command = Command::new(path);
+        own_process_group(&mut command);
+        let player_args = prepare_player_args(&args.pargs, channel);
+        command.args(player_args.split_whitespace());
+
+        //Passed as a single argument, titles have spaces in them
+        if let Some(title) = &args.title
+            && let Some(option) = title_option(path)
+            && !args.pargs.contains(option)
+        {
+            command.arg(format!("{option}={title}"));
+        }
+
+        if let Some(env) = &args.env {
+            command.envs(env.iter().map(|(k, v)| (k, v)));
+        }
+
+        if args.quiet {
+            command.stdout(Stdio::null()).stderr(Stdio::null());
+        } else {
+            command.stderr(Stdio::piped());
+        }
+
+        command
+    }
+
+    //Slow players can miss the start of the stream if it's written before they open stdin,
+    //so it's buffered until the player has stayed alive for --player-ready-delay.
+    fn write_ready(&mut self, buf: &[u8]) -> io::Result<()> {
+        let Some((ready_at, pending)) = &mut self.pending else {
+            return self
+                .stdin
+                .write_all(buf)
+                .map_err(|e| self.handle_broken_pipe(e));
+        };
+
+        pending.extend_from_slice(buf);
+        if Instant::now() < *ready_at {
+            return Ok(());
+        }
+
+        if let Ok(Some(status)) = self.process.try_wait() {
+            return Err(io::Error::other(PlayerClosedError(Some(status))));
+        }
+
+        let pending = mem::take(pending);
+        self.pending = None;
+
+        debug!("Player is ready, writing {} buffered bytes", pending.len());
+        self.stdin
+            .write_all(&pending)
+            .map_err(|e| self.handle_broken_pipe(e))
+    }
+
+    fn try_wait_for(&mut self, timeout: Duration) -> Option<ExitStatus> {
+        const POLL: Duration = Duration::from_millis(50);
+
+        let start = Instant::now();
+        loop {
+            match self.process.try_wait() {
+                Ok(Some(status)) => return Some(status),
+                Ok(None) if start.elapsed() < timeout => thread::sleep(POLL),
+                _ => return None,
+            }
+        }
     }
 
     fn handle_broken_pipe(&mut self, error: io::Error) -> io::Error {
         if error.kind() == BrokenPipe {
-            let _ = self.process.try_wait(); //reap pid
-            return io::Error::other(PlayerClosedError);
+            //Only means stdin was closed, the player can keep running (VLC after stop, mpv
+            //--idle) or it was --pipe-cmd that exited, so it's never waited on for long
+            let status = self.try_wait_for(Self::EXIT_WAIT);
+            if let Some(filter) = &mut self.filter {
+                let _ = filter.try_wait(); //reap pid
+            }
+            return io::Error::other(PlayerClosedError(status));
         }
 
         error
     }
 }
 
+//Holds off opening the player until a full segment is downloaded.
+//Ads are never written, so starting inside an ad break doesn't show a blank player.
+pub struct LazyPlayer {
+    args: Args,
+    channel: String,
+    player: Option<Player>,
+
+    header: Option<Box<[u8]>>,
+    buf: Vec<u8>,
+}
+
+impl Output for LazyPlayer {
+    fn set_header(&mut self, header: &[u8]) -> io::Result<()> {
+        if let Some(player) = &mut self.player {
+            return player.set_header(header);
+        }
+
+        self.header = Some(header.into());
+        Ok(())
+    }
+
+    //Not opened for ads
+    fn write_slate(&mut self) -> io::Result<()> {
+        self.player.as_mut().map_or(Ok(()), Output::write_slate)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn pipe(&self) -> Option<BorrowedFd<'_>> {
+        self.player.as_ref()?.pipe()
+    }
+}
+
+impl Write for LazyPlayer {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        unreachable!();
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.player.is_some() || self.buf.is_empty() {
+            return Ok(());
+        }
+
+        let mut player = Player::new(&self.args, &self.channel)
+            .map_err(io::Error::other)?
+            .ok_or_else(|| io::Error::other("No player set"))?;
+
+        if let Some(header) = self.header.take() {
+            player.set_header(&header)?;
+        }
+
+        player.write_all(&mem::take(&mut self.buf))?;
+        self.player = Some(player);
+
+        Ok(())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        if let Some(player) = &mut self.player {
+            return player.write_all(buf);
+        }
+
+        self.buf.extend_from_slice(buf);
+        Ok(())
+    }
+}
+
+impl LazyPlayer {
+    pub fn new(args: &Args, channel: &str) -> Option<Self> {
+        args.paths.as_ref()?;
+
+        info!("Player will be opened after the first segment");
+        Some(Self {
+            args: args.clone(),
+            channel: channel.to_owned(),
+            player: Option::default(),
+            header: Option::default(),
+            buf: Vec::default(),
+        })
+    }
+}
+
+//Drops the player's buffered data via mpv's JSON IPC (--input-ipc-server)
+pub fn mpv_drop_buffers(ipc_path: &str) -> io::Result<()> {
+    const COMMAND: &[u8] = b"{\"command\":[\"drop-buffers\"]}\n";
+
+    #[cfg(unix)]
+    return std::os::unix::net::UnixStream::connect(ipc_path)?.write_all(COMMAND);
+
+    //Named pipe on Windows
+    #[cfg(not(unix))]
+    return std::fs::OpenOptions::new()
+        .write(true)
+        .open(ipc_path)?
+        .write_all(COMMAND);
+}
+
+//Seconds of stream mpv has buffered ahead of what's playing, via its JSON IPC
+pub fn mpv_buffered(ipc_path: &str) -> io::Result<Duration> {
+    const COMMAND: &[u8] =
+        b"{\"command\":[\"get_property\",\"demuxer-cache-duration\"],\"request_id\":1}\n";
+
+    #[cfg(unix)]
+    let (reader, mut writer) = {
+        let sock = std::os::unix::net::UnixStream::connect(ipc_path)?;
+        sock.set_read_timeout(Some(Duration::from_secs(1)))?;
+        (sock.try_clone()?, sock)
+    };
+
+    //Named pipe on Windows
+    #[cfg(not(unix))]
+    let (reader, mut writer) = {
+        let pipe = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(ipc_path)?;
+        (pipe.try_clone()?, pipe)
+    };
+
+    writer.write_all(COMMAND)?;
+    for line in BufReader::new(reader).lines() {
+        //{"data":1.5,"request_id":1,"error":"success"}, events are sent on the same connection
+        let line = line?;
+        if !line.contains("\"request_id\":1") {
+            continue;
+        }
+
+        return line
+            .split_once("\"data\":")
+            .and_then(|(_, rest)| rest.split([',', '}']).next())
+            .and_then(|secs| secs.trim().parse().ok())
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .ok_or_else(|| io::Error::other("demuxer-cache-duration is unavailable"));
+    }
+
+    Err(io::ErrorKind::UnexpectedEof.into())
+}
+
+//Ctrl-C in the terminal only reaches the client, which stops the stream cleanly
+//and then kills the player, or leaves it running with --no-kill
+fn own_process_group(command: &mut Command) {
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(command, 0);
+
+    #[cfg(windows)]
+    {
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        std::os::windows::process::CommandExt::creation_flags(command, CREATE_NEW_PROCESS_GROUP);
+    }
+}
+
+//Player's own exit code so wrapper scripts can tell how it was closed
+fn exit_code(status: Option<ExitStatus>) -> ExitCode {
+    match status.map(|s| s.code()) {
+        None => ExitCode::SUCCESS, //failed to wait, assume it was closed normally
+        Some(None) => ExitCode::FAILURE, //killed by a signal
+        Some(Some(code)) => u8::try_from(code).map_or(ExitCode::FAILURE, ExitCode::from),
+    }
+}
+
+//Players otherwise show "stdin" or "fd://0" as the window title
+fn title_option(path: &str) -> Option<&'static str> {
+    let name = Path::new(path).file_stem()?.to_str()?.to_lowercase();
+    match name.as_str() {
+        "mpv" => Some("--force-media-title"),
+        "vlc" | "cvlc" => Some("--meta-title"),
+        _ => None,
+    }
+}
+
+//Ad slate, written no faster than it plays since it's sent on every playlist reload
+struct Slate {
+    data: Box<[u8]>,
+    duration: Duration,
+    sent_until: Option<Instant>, //when the slate written so far runs out
+}
+
+impl Slate {
+    fn new(data: Vec<u8>) -> Result<Self> {
+        let duration = ts_duration(&data)
+            .context("Failed to find ad slate duration, it must be an MPEG-TS file")?;
+        debug!("Ad slate duration: {:.3}s", duration.as_secs_f64());
+
+        Ok(Self {
+            data: data.into_boxed_slice(),
+            duration,
+            sent_until: None,
+        })
+    }
+
+    //Keeps less than one slate queued ahead of realtime
+    fn is_due(&mut self) -> bool {
+        let now = Instant::now();
+        let sent_until = self.sent_until.map_or(now, |t| t.max(now));
+        if sent_until.duration_since(now) >= self.duration {
+            return false;
+        }
+
+        self.sent_until = Some(sent_until + self.duration);
+        true
+    }
+}
+
+//From the first and last PTS of the first elementary stream, plus one frame
+fn ts_duration(data: &[u8]) -> Option<Duration> {
+    const PACKET_SIZE: usize = 188;
+    const PTS_HZ: f64 = 90_000.0;
+
+    let mut pid = None;
+    let mut pts = Vec::new();
+    for packet in data.chunks_exact(PACKET_SIZE) {
+        let payload_start = packet[1] & 0x40 != 0;
+        if packet[0] != 0x47 || !payload_start {
+            continue;
+        }
+
+        let packet_pid = (u16::from(packet[1] & 0x1F) << 8) | u16::from(packet[2]);
+        let payload = match (packet[3] >> 4) & 0x3 {
+            0b01 => &packet[4..],
+            0b11 => packet.get(5 + usize::from(packet[4])..)?,
+            _ => continue,
+        };
+
+        //PES start code, with the PTS flag set
+        if payload.len() < 14 || payload[..3] != [0, 0, 1] || payload[7] & 0x80 == 0 {
+            continue;
+        }
+        if *pid.get_or_insert(packet_pid) != packet_pid {
+            continue;
+        }
+
+        let p = &payload[9..14];
+        pts.push(
+            (u64::from(p[0] >> 1 & 0x7) << 30)
+                | (u64::from(p[1]) << 22)
+                | (u64::from(p[2] >> 1) << 15)
+                | (u64::from(p[3]) << 7)
+                | u64::from(p[4] >> 1),
+        );
+    }
+
+    let first = *pts.iter().min()?;
+    let last = *pts.iter().max()?;
+    if pts.len() < 2 || last == first {
+        return None;
+    }
+
+    #[allow(clippy::cast_precision_loss)] //fine for durations
+    let span = (last - first) as f64 * pts.len() as f64 / (pts.len() - 1) as f64;
+    Some(Duration::from_secs_f64(span / PTS_HZ))
+}
+
+fn spawn_filter(cmd: &str, quiet: bool) -> Result<Child> {
+    info!("Piping through: {cmd}");
+
+    let mut args = cmd.split_whitespace();
+    let mut command = Command::new(args.next().context("Empty pipe command")?);
+    own_process_group(&mut command);
+    command
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(if quiet { Stdio::null() } else { Stdio::piped() });
+
+    let mut filter = command.spawn().context("Failed to spawn pipe command")?;
+    if let Some(stderr) = filter.stderr.take() {
+        forward_stderr(stderr, "pipe-cmd")?;
+    }
+
+    Ok(filter)
+}
+
+//Routes player output through the logger so it doesn't interleave with the status line
+fn forward_stderr(stderr: ChildStderr, name: &'static str) -> Result<()> {
+    ThreadBuilder::new()
+        .name(format!("{name} stderr"))
+        .spawn(move || {
+            for line in BufReader::new(stderr).split(b'\n') {
+                let Ok(line) = line else {
+                    return;
+                };
+
+                //Progress lines are redrawn with carriage returns, only keep the latest
+                let line = String::from_utf8_lossy(&line);
+                let line = line.rsplit('\r').find(|l| !l.trim().is_empty());
+                let Some(line) = line.map(str::trim_end) else {
+                    continue;
+                };
+
+                if line.to_lowercase().contains("error") {
+                    error!("{name}: {line}");
+                } else {
+                    info!("{name}: {line}");
+                }
+            }
+        })
+        .context("Failed to spawn player stderr thread")?;
+
+    Ok(())
+}
+
+//KEY1=VALUE1,KEY2=VALUE2
+fn parse_env(arg: &str) -> Result<Option<Vec<(String, String)>>> {
+    arg.split(',')
+        .map(|var| {
+            let (key, value) = var
+                .split_once('=')
+                .filter(|(k, _)| !k.is_empty())
+                .context("Invalid player environment variable, expected KEY=VALUE")?;
+
+            Ok((key.to_owned(), value.to_owned()))
+        })
+        .collect::<Result<_>>()
+        .map(Some)
+}
+
 fn prepare_player_args(arg_str: &str, channel: &str) -> String {
     arg_str.replace("[channel]", channel)
 }
@@ -0,0 +1,149 @@
+mod amf;
+mod connection;
+mod flv;
+mod ts;
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    io::{self, ErrorKind, Write},
+};
+
+use anyhow::{Context, Result, bail};
+use log::info;
+
+use super::Output;
+use crate::args::{Parse, Parser};
+use connection::Connection;
+use flv::{Muxer, Tag};
+use ts::{Demuxer, Frame};
+
+#[derive(Default, Debug)]
+pub struct Args {
+    target: Option<Target>,
+}
+
+impl Parse for Args {
+    fn parse(&mut self, parser: &mut Parser) -> Result<()> {
+        parser.parse_fn(&mut self.target, "--rtmp", Target::new)?;
+
+        Ok(())
+    }
+}
+
+pub struct Target {
+    host: String,
+    port: u16,
+    app: String,
+    key: String,
+}
+
+//Keeps the stream key out of debug logs
+impl Debug for Target {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "rtmp://{}:{}/{}/<key>", self.host, self.port, self.app)
+    }
+}
+
+impl Target {
+    const DEFAULT_PORT: u16 = 1935;
+
+    //rtmp://HOST[:PORT]/APP/KEY, the key is everything after the last slash
+    fn new(arg: &str) -> Result<Option<Self>> {
+        let Some(("rtmp", rest)) = arg.split_once("://") else {
+            bail!("Invalid RTMP URL, expected rtmp://HOST[:PORT]/APP/KEY");
+        };
+
+        let (addr, path) = rest
+            .split_once('/')
+            .context("RTMP URL is missing the app")?;
+        let (app, key) = path
+            .rsplit_once('/')
+            .filter(|(app, key)| !app.is_empty() && !key.is_empty())
+            .context("RTMP URL is missing the stream key")?;
+
+        //[IPv6]:PORT
+        let (host, port) = match addr.strip_prefix('[') {
+            Some(addr) => {
+                let (host, port) = addr.split_once(']').context("Invalid RTMP host")?;
+                (host, port.strip_prefix(':'))
+            }
+            None => addr
+                .split_once(':')
+                .map_or((addr, None), |(host, port)| (host, Some(port))),
+        };
+
+        Ok(Some(Self {
+            host: host.to_owned(),
+            port: port
+                .map(str::parse)
+                .transpose()
+                .context("Invalid RTMP port")?
+                .unwrap_or(Self::DEFAULT_PORT),
+            app: app.to_owned(),
+            key: key.to_owned(),
+        }))
+    }
+}
+
+//Remuxes the TS stream to FLV and publishes it, segments are sent as fast as they're
+//downloaded like a reconnecting encoder would
+pub struct Rtmp {
+    connection: Connection,
+    demuxer: Demuxer,
+    muxer: Muxer,
+    frames: Vec<Frame>,
+    tags: Vec<Tag>,
+}
+
+impl Output for Rtmp {
+    //Only fMP4 streams have one
+    fn set_header(&mut self, _header: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "RTMP output only supports MPEG-TS streams",
+        ))
+    }
+}
+
+impl Write for Rtmp {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        unreachable!();
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.demuxer.flush(&mut self.frames);
+        self.send_frames()
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.demuxer.push(buf, &mut self.frames);
+        self.send_frames()
+    }
+}
+
+impl Rtmp {
+    pub fn new(args: &Args) -> Result<Option<Self>> {
+        let Some(target) = &args.target else {
+            return Ok(None);
+        };
+
+        info!("Publishing to RTMP server: {target:?}");
+        Ok(Some(Self {
+            connection: Connection::new(target)?,
+            demuxer: Demuxer::default(),
+            muxer: Muxer::default(),
+            frames: Vec::new(),
+            tags: Vec::new(),
+        }))
+    }
+
+    fn send_frames(&mut self) -> io::Result<()> {
+        for frame in self.frames.drain(..) {
+            self.muxer.tags(&frame, &mut self.tags);
+        }
+
+        self.tags
+            .drain(..)
+            .try_for_each(|tag| self.connection.send(&tag))
+    }
+}
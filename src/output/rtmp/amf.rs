@@ -0,0 +1,140 @@
+use anyhow::{Context, Result, bail};
+
+//AMF0, only the types used by RTMP commands
+#[derive(Debug)]
+pub enum Value {
+    Number(f64),
+    Bool(bool),
+    String(String),
+    Object(Vec<(String, Self)>),
+    Null,
+}
+
+impl Value {
+    const NUMBER: u8 = 0x00;
+    const BOOL: u8 = 0x01;
+    const STRING: u8 = 0x02;
+    const OBJECT: u8 = 0x03;
+    const NULL: u8 = 0x05;
+    const UNDEFINED: u8 = 0x06;
+    const ECMA_ARRAY: u8 = 0x08;
+    const OBJECT_END: [u8; 3] = [0x00, 0x00, 0x09];
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Self::Number(number) => {
+                buf.push(Self::NUMBER);
+                buf.extend_from_slice(&number.to_be_bytes());
+            }
+            Self::Bool(bool) => buf.extend_from_slice(&[Self::BOOL, u8::from(*bool)]),
+            Self::String(string) => {
+                buf.push(Self::STRING);
+                encode_str(string, buf);
+            }
+            Self::Object(members) => {
+                buf.push(Self::OBJECT);
+                for (key, value) in members {
+                    encode_str(key, buf);
+                    value.encode(buf);
+                }
+                buf.extend_from_slice(&Self::OBJECT_END);
+            }
+            Self::Null => buf.push(Self::NULL),
+        }
+    }
+
+    //Every value in a command message
+    pub fn decode_all(mut data: &[u8]) -> Result<Vec<Self>> {
+        let mut values = Vec::new();
+        while !data.is_empty() {
+            values.push(Self::decode(&mut data)?);
+        }
+
+        Ok(values)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Self> {
+        match self {
+            Self::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub const fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    fn decode(data: &mut &[u8]) -> Result<Self> {
+        match take(data, 1)?[0] {
+            Self::NUMBER => Ok(Self::Number(f64::from_be_bytes(
+                take(data, 8)?.try_into().context("Invalid AMF number")?,
+            ))),
+            Self::BOOL => Ok(Self::Bool(take(data, 1)?[0] != 0)),
+            Self::STRING => Ok(Self::String(decode_str(data)?)),
+            Self::OBJECT => Self::decode_object(data),
+            Self::ECMA_ARRAY => {
+                take(data, 4)?; //count, the members end like an object's
+                Self::decode_object(data)
+            }
+            Self::NULL | Self::UNDEFINED => Ok(Self::Null),
+            marker => bail!("Unsupported AMF type: {marker:#04x}"),
+        }
+    }
+
+    fn decode_object(data: &mut &[u8]) -> Result<Self> {
+        let mut members = Vec::new();
+        loop {
+            if data.starts_with(&Self::OBJECT_END) {
+                *data = &data[Self::OBJECT_END.len()..];
+                return Ok(Self::Object(members));
+            }
+
+            let key = decode_str(data)?;
+            members.push((key, Self::decode(data)?));
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(string: &str) -> Self {
+        Self::String(string.to_owned())
+    }
+}
+
+impl From<f64> for Value {
+    fn from(number: f64) -> Self {
+        Self::Number(number)
+    }
+}
+
+fn encode_str(string: &str, buf: &mut Vec<u8>) {
+    #[allow(clippy::cast_possible_truncation)] //only short command strings are sent
+    buf.extend_from_slice(&(string.len() as u16).to_be_bytes());
+    buf.extend_from_slice(string.as_bytes());
+}
+
+fn decode_str(data: &mut &[u8]) -> Result<String> {
+    let len = take(data, 2)?;
+    let len = usize::from(u16::from_be_bytes([len[0], len[1]]));
+
+    Ok(String::from_utf8_lossy(take(data, len)?).into_owned())
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    let Some((taken, rest)) = data.split_at_checked(len) else {
+        bail!("Truncated AMF value");
+    };
+
+    *data = rest;
+    Ok(taken)
+}
@@ -0,0 +1,470 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufReader, Read, Write},
+    mem,
+    net::{TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    thread::Builder as ThreadBuilder,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use getrandom::getrandom;
+use log::{debug, error};
+
+use super::{Target, amf::Value, flv::Tag, ts::Kind};
+
+//Message types
+const SET_CHUNK_SIZE: u8 = 1;
+const USER_CONTROL: u8 = 4;
+const AUDIO: u8 = 8;
+const VIDEO: u8 = 9;
+const DATA: u8 = 18;
+const COMMAND: u8 = 20;
+
+//Publishing client, the handshake is the simple one without digests
+pub struct Connection {
+    sender: Arc<Mutex<Sender>>,
+    stream_id: u32,
+}
+
+impl Connection {
+    const TIMEOUT: Duration = Duration::from_secs(10);
+    const HANDSHAKE_SIZE: usize = 1536;
+    const CHUNK_SIZE: u32 = 4096;
+
+    pub fn new(target: &Target) -> Result<Self> {
+        let sock = Self::connect(target)?;
+        let sender = Arc::new(Mutex::new(Sender {
+            sock: sock.try_clone()?,
+            chunk_size: Sender::DEFAULT_CHUNK_SIZE,
+            buf: Vec::new(),
+        }));
+        let mut receiver = Receiver {
+            sock: BufReader::new(sock),
+            chunk_size: Receiver::DEFAULT_CHUNK_SIZE,
+            streams: HashMap::default(),
+        };
+
+        lock(&sender).set_chunk_size(Self::CHUNK_SIZE)?;
+
+        let tc_url = if target.host.contains(':') {
+            format!("rtmp://[{}]:{}/{}", target.host, target.port, target.app)
+        } else {
+            format!("rtmp://{}:{}/{}", target.host, target.port, target.app)
+        };
+        command(
+            &sender,
+            0,
+            &[
+                "connect".into(),
+                1.0.into(),
+                Value::Object(vec![
+                    ("app".to_owned(), target.app.as_str().into()),
+                    ("type".to_owned(), "nonprivate".into()),
+                    (
+                        "flashVer".to_owned(),
+                        "FMLE/3.0 (compatible; FMSc/1.0)".into(),
+                    ),
+                    ("tcUrl".to_owned(), tc_url.as_str().into()),
+                ]),
+            ],
+        )?;
+        receiver.result(&sender, 1.0)?;
+
+        //Only some servers answer these, errors are expected
+        command(
+            &sender,
+            0,
+            &[
+                "releaseStream".into(),
+                2.0.into(),
+                Value::Null,
+                target.key.as_str().into(),
+            ],
+        )?;
+        command(
+            &sender,
+            0,
+            &[
+                "FCPublish".into(),
+                3.0.into(),
+                Value::Null,
+                target.key.as_str().into(),
+            ],
+        )?;
+        command(
+            &sender,
+            0,
+            &["createStream".into(), 4.0.into(), Value::Null],
+        )?;
+
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] //small integer
+        let stream_id = receiver
+            .result(&sender, 4.0)?
+            .get(3)
+            .and_then(Value::as_f64)
+            .context("Invalid RTMP createStream result")? as u32;
+
+        command(
+            &sender,
+            stream_id,
+            &[
+                "publish".into(),
+                5.0.into(),
+                Value::Null,
+                target.key.as_str().into(),
+                "live".into(),
+            ],
+        )?;
+        receiver.publish_started(&sender)?;
+
+        let mut metadata = Vec::new();
+        for value in [
+            "@setDataFrame".into(),
+            "onMetaData".into(),
+            Value::Object(vec![
+                ("videocodecid".to_owned(), 7.0.into()),  //AVC
+                ("audiocodecid".to_owned(), 10.0.into()), //AAC
+                ("encoder".to_owned(), env!("CARGO_PKG_NAME").into()),
+            ]),
+        ] {
+            value.encode(&mut metadata);
+        }
+        lock(&sender).send(Sender::CSID_COMMAND, DATA, stream_id, 0, &metadata)?;
+
+        receiver.spawn(Arc::clone(&sender))?;
+        Ok(Self { sender, stream_id })
+    }
+
+    pub fn send(&self, tag: &Tag) -> io::Result<()> {
+        let (csid, message_type) = match tag.kind {
+            Kind::Audio => (Sender::CSID_AUDIO, AUDIO),
+            Kind::Video => (Sender::CSID_VIDEO, VIDEO),
+        };
+
+        lock(&self.sender).send(csid, message_type, self.stream_id, tag.timestamp, &tag.data)
+    }
+
+    fn connect(target: &Target) -> Result<TcpStream> {
+        let mut last_error = None;
+        for addr in (target.host.as_str(), target.port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, Self::TIMEOUT) {
+                Ok(mut sock) => {
+                    sock.set_nodelay(true)?;
+                    sock.set_read_timeout(Some(Self::TIMEOUT))?;
+                    sock.set_write_timeout(Some(Self::TIMEOUT))?;
+
+                    Self::handshake(&mut sock).context("RTMP handshake failed")?;
+                    return Ok(sock);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        match last_error {
+            Some(e) => Err(e).context("Failed to connect to RTMP server"),
+            None => bail!("Failed to resolve RTMP host"),
+        }
+    }
+
+    //C0 and C1, S0 and S1, then C2 echoes S1 back
+    fn handshake(sock: &mut TcpStream) -> Result<()> {
+        let mut c0c1 = vec![0u8; 1 + Self::HANDSHAKE_SIZE];
+        c0c1[0] = 3; //version
+        getrandom(&mut c0c1[9..])?; //after the time and zero fields
+        sock.write_all(&c0c1)?;
+
+        let mut s0s1 = vec![0u8; 1 + Self::HANDSHAKE_SIZE];
+        sock.read_exact(&mut s0s1)?;
+        if s0s1[0] != 3 {
+            bail!("Unsupported RTMP version: {}", s0s1[0]);
+        }
+        sock.write_all(&s0s1[1..])?;
+
+        let mut s2 = vec![0u8; Self::HANDSHAKE_SIZE];
+        sock.read_exact(&mut s2)?;
+
+        Ok(())
+    }
+}
+
+struct Sender {
+    sock: TcpStream,
+    chunk_size: usize,
+    buf: Vec<u8>,
+}
+
+impl Sender {
+    const DEFAULT_CHUNK_SIZE: usize = 128;
+
+    const CSID_CONTROL: u8 = 2;
+    const CSID_COMMAND: u8 = 3;
+    const CSID_AUDIO: u8 = 4;
+    const CSID_VIDEO: u8 = 6;
+
+    const MAX_TIMESTAMP: u32 = 0xFF_FFFF; //larger ones are sent as extended timestamps
+
+    //Type 0 header on the first chunk, type 3 on the rest
+    fn send(
+        &mut self,
+        csid: u8,
+        message_type: u8,
+        stream_id: u32,
+        timestamp: u32,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let extended = timestamp >= Self::MAX_TIMESTAMP;
+
+        #[allow(clippy::cast_possible_truncation)] //messages are far below 16MB
+        let len = payload.len() as u32;
+
+        self.buf.clear();
+        self.buf.push(csid);
+        self.buf
+            .extend_from_slice(&timestamp.min(Self::MAX_TIMESTAMP).to_be_bytes()[1..]);
+        self.buf.extend_from_slice(&len.to_be_bytes()[1..]);
+        self.buf.push(message_type);
+        self.buf.extend_from_slice(&stream_id.to_le_bytes());
+
+        for (i, chunk) in payload.chunks(self.chunk_size).enumerate() {
+            if i > 0 {
+                self.buf.push(0xC0 | csid);
+            }
+            if extended {
+                self.buf.extend_from_slice(&timestamp.to_be_bytes());
+            }
+
+            self.buf.extend_from_slice(chunk);
+        }
+
+        self.sock.write_all(&self.buf)
+    }
+
+    fn set_chunk_size(&mut self, size: u32) -> io::Result<()> {
+        self.send(
+            Self::CSID_CONTROL,
+            SET_CHUNK_SIZE,
+            0,
+            0,
+            &size.to_be_bytes(),
+        )?;
+        self.chunk_size = size as usize;
+
+        Ok(())
+    }
+}
+
+struct Receiver {
+    sock: BufReader<TcpStream>,
+    chunk_size: usize,
+    streams: HashMap<u32, Incoming>, //by chunk stream ID
+}
+
+//Header of the last chunk on a chunk stream, later chunks only send what changed
+#[derive(Default)]
+struct Incoming {
+    len: usize,
+    message_type: u8,
+    extended: bool,
+    payload: Vec<u8>,
+}
+
+impl Receiver {
+    const DEFAULT_CHUNK_SIZE: usize = 128;
+
+    const PING_REQUEST: u16 = 6;
+    const PING_RESPONSE: u16 = 7;
+
+    //Answers pings and shows errors until the connection is closed
+    fn spawn(mut self, sender: Arc<Mutex<Sender>>) -> Result<()> {
+        self.sock.get_ref().set_read_timeout(None)?;
+
+        ThreadBuilder::new()
+            .name("rtmp".to_owned())
+            .spawn(move || {
+                loop {
+                    match self.command(&sender) {
+                        Ok(Some(values)) if is_error(&values) => {
+                            error!("RTMP: {}", describe(&values));
+                        }
+                        Ok(_) => (),
+                        Err(e) => {
+                            debug!("RTMP connection closed: {e}");
+                            return;
+                        }
+                    }
+                }
+            })
+            .context("Failed to spawn RTMP thread")?;
+
+        Ok(())
+    }
+
+    fn result(&mut self, sender: &Mutex<Sender>, transaction: f64) -> Result<Vec<Value>> {
+        loop {
+            let Some(values) = self.command(sender)? else {
+                continue;
+            };
+
+            let name = values.first().and_then(Value::as_str);
+            #[allow(clippy::float_cmp)] //transaction IDs are small integers
+            if matches!(name, Some("_result" | "_error"))
+                && values.get(1).and_then(Value::as_f64) == Some(transaction)
+            {
+                if name == Some("_error") {
+                    bail!("RTMP server refused: {}", describe(&values));
+                }
+
+                return Ok(values);
+            }
+        }
+    }
+
+    fn publish_started(&mut self, sender: &Mutex<Sender>) -> Result<()> {
+        loop {
+            let Some(values) = self.command(sender)? else {
+                continue;
+            };
+
+            if is_error(&values) {
+                bail!("RTMP server refused: {}", describe(&values));
+            }
+
+            if status_code(&values) == Some("NetStream.Publish.Start") {
+                return Ok(());
+            }
+        }
+    }
+
+    //Protocol control messages are handled here, commands are returned
+    fn command(&mut self, sender: &Mutex<Sender>) -> Result<Option<Vec<Value>>> {
+        let (message_type, payload) = self.message()?;
+        match message_type {
+            SET_CHUNK_SIZE if payload.len() >= 4 => {
+                let size = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
+                self.chunk_size = (size & 0x7FFF_FFFF) as usize;
+            }
+            USER_CONTROL
+                if payload.len() >= 6
+                    && u16::from_be_bytes([payload[0], payload[1]]) == Self::PING_REQUEST =>
+            {
+                let mut response = Self::PING_RESPONSE.to_be_bytes().to_vec();
+                response.extend_from_slice(&payload[2..6]);
+
+                lock(sender).send(Sender::CSID_CONTROL, USER_CONTROL, 0, 0, &response)?;
+            }
+            COMMAND => return Value::decode_all(&payload).map(Some),
+            _ => (),
+        }
+
+        Ok(None)
+    }
+
+    fn message(&mut self) -> Result<(u8, Vec<u8>)> {
+        loop {
+            let [basic] = self.read::<1>()?;
+            let csid = match basic & 0x3F {
+                0 => 64 + u32::from(self.read::<1>()?[0]),
+                1 => {
+                    let [low, high] = self.read::<2>()?;
+                    64 + u32::from(low) + u32::from(high) * 256
+                }
+                csid => u32::from(csid),
+            };
+
+            //Types 0 and 1 start with the same fields, 0 adds the message stream ID
+            let fmt = basic >> 6;
+            let mut header = [0u8; 11];
+            let header_len = [11, 7, 3, 0][usize::from(fmt)];
+            self.sock
+                .read_exact(&mut header[..header_len])
+                .context("Failed to read RTMP chunk")?;
+
+            let incoming = self.streams.entry(csid).or_default();
+            if fmt <= 1 {
+                incoming.len = usize::from(header[3]) << 16
+                    | usize::from(header[4]) << 8
+                    | usize::from(header[5]);
+                incoming.message_type = header[6];
+            }
+            if fmt <= 2 {
+                incoming.extended = header[..3] == [0xFF; 3];
+            }
+
+            if incoming.extended {
+                self.read::<4>()?;
+            }
+
+            let chunk_size = self.chunk_size;
+            let incoming = self.streams.entry(csid).or_default();
+            let len = (incoming.len - incoming.payload.len()).min(chunk_size);
+            let start = incoming.payload.len();
+            incoming.payload.resize(start + len, 0);
+            self.sock
+                .read_exact(&mut incoming.payload[start..])
+                .context("Failed to read RTMP chunk")?;
+
+            if incoming.payload.len() == incoming.len {
+                return Ok((incoming.message_type, mem::take(&mut incoming.payload)));
+            }
+        }
+    }
+
+    fn read<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf = [0u8; N];
+        self.sock
+            .read_exact(&mut buf)
+            .context("Failed to read RTMP chunk")?;
+
+        Ok(buf)
+    }
+}
+
+fn command(sender: &Mutex<Sender>, stream_id: u32, values: &[Value]) -> Result<()> {
+    let mut payload = Vec::new();
+    for value in values {
+        value.encode(&mut payload);
+    }
+
+    lock(sender).send(Sender::CSID_COMMAND, COMMAND, stream_id, 0, &payload)?;
+
+    Ok(())
+}
+
+fn lock(sender: &Mutex<Sender>) -> MutexGuard<'_, Sender> {
+    sender.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+//onStatus info object's code
+fn status_code(values: &[Value]) -> Option<&str> {
+    values.get(3)?.get("code")?.as_str()
+}
+
+fn is_error(values: &[Value]) -> bool {
+    match values.first().and_then(Value::as_str) {
+        Some("_error") => true,
+        Some("onStatus") => {
+            values
+                .get(3)
+                .and_then(|info| info.get("level"))
+                .and_then(Value::as_str)
+                == Some("error")
+        }
+        _ => false,
+    }
+}
+
+fn describe(values: &[Value]) -> String {
+    let info = values.get(3);
+    let code = info.and_then(|i| i.get("code")).and_then(Value::as_str);
+    let description = info
+        .and_then(|i| i.get("description"))
+        .and_then(Value::as_str);
+
+    match (code, description) {
+        (Some(code), Some(description)) => format!("{code} ({description})"),
+        (Some(text), None) | (None, Some(text)) => text.to_owned(),
+        (None, None) => "unknown error".to_owned(),
+    }
+}
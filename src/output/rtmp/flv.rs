@@ -0,0 +1,236 @@
+use log::debug;
+
+use super::ts::{Frame, Kind};
+
+//Audio or video message body, the same as an FLV tag's data
+pub struct Tag {
+    pub kind: Kind,
+    pub timestamp: u32, //milliseconds
+    pub data: Vec<u8>,
+}
+
+//Turns Annex B H.264 and ADTS AAC into FLV tags, with sequence headers sent before the first
+//frame and whenever the stream's parameters change
+#[derive(Default)]
+pub struct Muxer {
+    timeline: Timeline,
+    avc_config: Option<Vec<u8>>, //last AVCDecoderConfigurationRecord sent
+    aac_config: Option<[u8; 2]>, //last AudioSpecificConfig sent
+}
+
+impl Muxer {
+    const NAL_SEI: u8 = 6;
+    const NAL_IDR: u8 = 5;
+    const NAL_SPS: u8 = 7;
+    const NAL_PPS: u8 = 8;
+
+    const VIDEO_KEYFRAME: u8 = 0x17; //keyframe, AVC
+    const VIDEO_INTER: u8 = 0x27; //inter frame, AVC
+    const AUDIO_AAC: u8 = 0xAF; //AAC, 44kHz, 16 bit, stereo (ignored for AAC)
+
+    const AAC_FRAME_SAMPLES: u64 = 1024;
+    const AAC_SAMPLE_RATES: [u64; 13] = [
+        96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+    ];
+
+    pub fn tags(&mut self, frame: &Frame, tags: &mut Vec<Tag>) {
+        match frame.kind {
+            Kind::Video => self.video(frame, tags),
+            Kind::Audio => self.audio(frame, tags),
+        }
+    }
+
+    fn video(&mut self, frame: &Frame, tags: &mut Vec<Tag>) {
+        let mut sps = None;
+        let mut pps = None;
+        let mut keyframe = false;
+        let mut data = Vec::with_capacity(frame.data.len() + 16);
+        for nal in nal_units(&frame.data) {
+            match nal[0] & 0x1F {
+                Self::NAL_SPS => sps = Some(nal),
+                Self::NAL_PPS => pps = Some(nal),
+                nal_type @ (Self::NAL_IDR | Self::NAL_SEI | 1..=4) => {
+                    keyframe |= nal_type == Self::NAL_IDR;
+
+                    #[allow(clippy::cast_possible_truncation)] //NAL units are much smaller
+                    data.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+                    data.extend_from_slice(nal);
+                }
+                _ => (), //access unit delimiters and filler
+            }
+        }
+
+        if let (Some(sps), Some(pps)) = (sps, pps)
+            && sps.len() >= 4
+        {
+            let config = avc_config(sps, pps);
+            if self.avc_config.as_ref() != Some(&config) {
+                debug!("RTMP: sending AVC sequence header");
+
+                let mut header = vec![Self::VIDEO_KEYFRAME, 0, 0, 0, 0];
+                header.extend_from_slice(&config);
+                tags.push(Tag {
+                    kind: Kind::Video,
+                    timestamp: self.timeline.map(frame.dts),
+                    data: header,
+                });
+
+                self.avc_config = Some(config);
+            }
+        }
+
+        //Decoders can't start before the first sequence header
+        if self.avc_config.is_none() || data.is_empty() {
+            return;
+        }
+
+        #[allow(clippy::cast_possible_truncation)] //composition offsets are a few frames
+        let composition_time = (frame.pts.saturating_sub(frame.dts) / 90) as u32;
+
+        let mut body = Vec::with_capacity(data.len() + 5);
+        body.push(if keyframe {
+            Self::VIDEO_KEYFRAME
+        } else {
+            Self::VIDEO_INTER
+        });
+        body.push(1); //NALU
+        body.extend_from_slice(&composition_time.to_be_bytes()[1..]);
+        body.extend_from_slice(&data);
+
+        tags.push(Tag {
+            kind: Kind::Video,
+            timestamp: self.timeline.map(frame.dts),
+            data: body,
+        });
+    }
+
+    //PES packets usually hold several ADTS frames
+    fn audio(&mut self, frame: &Frame, tags: &mut Vec<Tag>) {
+        let mut data = frame.data.as_slice();
+        let mut index = 0;
+        while data.len() >= 7 && data[0] == 0xFF && data[1] & 0xF0 == 0xF0 {
+            let header_len = if data[1] & 0x01 == 0 { 9 } else { 7 }; //CRC
+            let frame_len = (usize::from(data[3] & 0x03) << 11)
+                | (usize::from(data[4]) << 3)
+                | usize::from(data[5] >> 5);
+            if frame_len <= header_len || frame_len > data.len() {
+                break;
+            }
+
+            let object_type = (data[2] >> 6) + 1;
+            let rate_index = (data[2] >> 2) & 0x0F;
+            let channels = ((data[2] & 0x01) << 2) | (data[3] >> 6);
+            let Some(&rate) = Self::AAC_SAMPLE_RATES.get(usize::from(rate_index)) else {
+                break;
+            };
+
+            let config = [
+                (object_type << 3) | (rate_index >> 1),
+                ((rate_index & 0x01) << 7) | (channels << 3),
+            ];
+            let pts = frame.pts + index * Self::AAC_FRAME_SAMPLES * 90_000 / rate;
+            if self.aac_config != Some(config) {
+                debug!("RTMP: sending AAC sequence header");
+                tags.push(Tag {
+                    kind: Kind::Audio,
+                    timestamp: self.timeline.map(pts),
+                    data: vec![Self::AUDIO_AAC, 0, config[0], config[1]],
+                });
+
+                self.aac_config = Some(config);
+            }
+
+            let mut body = Vec::with_capacity(frame_len - header_len + 2);
+            body.extend_from_slice(&[Self::AUDIO_AAC, 1]);
+            body.extend_from_slice(&data[header_len..frame_len]);
+            tags.push(Tag {
+                kind: Kind::Audio,
+                timestamp: self.timeline.map(pts),
+                data: body,
+            });
+
+            data = &data[frame_len..];
+            index += 1;
+        }
+    }
+}
+
+//Maps 90kHz timestamps to milliseconds from the start of the stream. Jumps from filtered ads,
+//switching variants or the 33 bit wraparound are stitched so the timeline keeps going.
+#[derive(Default)]
+struct Timeline {
+    base: Option<i64>,
+    last: i64,
+}
+
+impl Timeline {
+    const MAX_BACKWARDS: i64 = 90_000; //audio and video aren't interleaved exactly
+    const MAX_FORWARDS: i64 = 10 * 90_000;
+
+    #[allow(clippy::cast_possible_wrap)] //33 bit timestamps
+    fn map(&mut self, timestamp: u64) -> u32 {
+        let timestamp = timestamp as i64;
+        let base = *self.base.get_or_insert(timestamp);
+
+        let mut mapped = timestamp - base;
+        if mapped < self.last - Self::MAX_BACKWARDS || mapped > self.last + Self::MAX_FORWARDS {
+            debug!("RTMP: timestamp discontinuity, continuing timeline");
+            self.base = Some(timestamp - self.last);
+            mapped = self.last;
+        }
+        self.last = self.last.max(mapped);
+
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] //wraps like RTMP's
+        let ms = (mapped.max(0) / 90) as u32;
+        ms
+    }
+}
+
+//Annex B byte stream, split on 3 and 4 byte start codes
+fn nal_units(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i..i + 3] == [0, 0, 1] {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+
+    let ends = starts
+        .iter()
+        .skip(1)
+        .map(|&start| start - 3)
+        .chain([data.len()])
+        .collect::<Vec<_>>();
+
+    starts
+        .into_iter()
+        .zip(ends)
+        .map(move |(start, end)| {
+            //Trailing zero of a 4 byte start code
+            let nal = &data[start..end];
+            let len = nal.len() - nal.iter().rev().take_while(|&&b| b == 0).count();
+            &nal[..len]
+        })
+        .filter(|nal| !nal.is_empty())
+}
+
+//AVCDecoderConfigurationRecord with one SPS and PPS, NAL unit lengths are 4 bytes
+fn avc_config(sps: &[u8], pps: &[u8]) -> Vec<u8> {
+    let mut config = Vec::with_capacity(11 + sps.len() + pps.len());
+    config.extend_from_slice(&[1, sps[1], sps[2], sps[3], 0xFF, 0xE1]);
+
+    #[allow(clippy::cast_possible_truncation)] //parameter sets are tiny
+    {
+        config.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+        config.extend_from_slice(sps);
+        config.push(1);
+        config.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+        config.extend_from_slice(pps);
+    }
+
+    config
+}
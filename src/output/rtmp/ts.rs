@@ -0,0 +1,220 @@
+use std::mem;
+
+use log::debug;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Kind {
+    Video, //H.264
+    Audio, //AAC in ADTS frames
+}
+
+//One PES packet, timestamps are in 90kHz units
+pub struct Frame {
+    pub kind: Kind,
+    pub pts: u64,
+    pub dts: u64,
+    pub data: Vec<u8>,
+}
+
+//Finds the H.264 and AAC streams through the PAT and PMT and collects their PES packets,
+//other streams (like Twitch's ID3 metadata) are ignored
+#[derive(Default)]
+pub struct Demuxer {
+    pending: Vec<u8>, //partial TS packet carried over to the next push
+    pmt_pid: Option<u16>,
+    video: Option<Pes>,
+    audio: Option<Pes>,
+}
+
+impl Demuxer {
+    const PACKET_SIZE: usize = 188;
+    const SYNC_BYTE: u8 = 0x47;
+
+    const STREAM_TYPE_AAC: u8 = 0x0F;
+    const STREAM_TYPE_H264: u8 = 0x1B;
+
+    pub fn push(&mut self, buf: &[u8], frames: &mut Vec<Frame>) {
+        let mut pending = mem::take(&mut self.pending);
+        pending.extend_from_slice(buf);
+
+        let mut pos = 0;
+        while pending.len() - pos >= Self::PACKET_SIZE {
+            if pending[pos] != Self::SYNC_BYTE {
+                pos += 1;
+                continue;
+            }
+
+            self.packet(&pending[pos..pos + Self::PACKET_SIZE], frames);
+            pos += Self::PACKET_SIZE;
+        }
+
+        pending.drain(..pos);
+        self.pending = pending;
+    }
+
+    //Segments end on a frame boundary, so the last PES packets don't have to wait for the next
+    //segment to start
+    pub fn flush(&mut self, frames: &mut Vec<Frame>) {
+        for pes in [&mut self.video, &mut self.audio].into_iter().flatten() {
+            frames.extend(pes.take());
+        }
+    }
+
+    fn packet(&mut self, packet: &[u8], frames: &mut Vec<Frame>) {
+        let unit_start = packet[1] & 0x40 != 0;
+        let pid = (u16::from(packet[1] & 0x1F) << 8) | u16::from(packet[2]);
+        let payload = match (packet[3] >> 4) & 0x3 {
+            0b01 => &packet[4..],
+            0b11 => match packet.get(5 + usize::from(packet[4])..) {
+                Some(payload) => payload,
+                None => return,
+            },
+            _ => return,
+        };
+
+        if pid == 0 {
+            if unit_start {
+                self.pmt_pid = Self::parse_pat(payload);
+            }
+        } else if Some(pid) == self.pmt_pid {
+            if unit_start {
+                self.parse_pmt(payload);
+            }
+        } else if let Some(pes) = [&mut self.video, &mut self.audio]
+            .into_iter()
+            .flatten()
+            .find(|pes| pes.pid == pid)
+        {
+            if unit_start {
+                frames.extend(pes.take());
+                pes.start(payload);
+            } else {
+                pes.data.extend_from_slice(payload);
+            }
+        }
+    }
+
+    //PID of the first program's PMT
+    fn parse_pat(payload: &[u8]) -> Option<u16> {
+        let section = Self::section(payload)?;
+        section
+            .get(8..section.len().checked_sub(4)?)? //CRC
+            .chunks_exact(4)
+            .find(|program| program[..2] != [0, 0]) //network PID
+            .map(|program| (u16::from(program[2] & 0x1F) << 8) | u16::from(program[3]))
+    }
+
+    fn parse_pmt(&mut self, payload: &[u8]) {
+        let Some(section) = Self::section(payload) else {
+            return;
+        };
+        let Some(info_len) = section
+            .get(10..12)
+            .map(|len| (usize::from(len[0] & 0x0F) << 8) | usize::from(len[1]))
+        else {
+            return;
+        };
+        let Some(mut streams) = section.get(12 + info_len..section.len().saturating_sub(4)) else {
+            return;
+        };
+
+        while streams.len() >= 5 {
+            let stream_type = streams[0];
+            let pid = (u16::from(streams[1] & 0x1F) << 8) | u16::from(streams[2]);
+            let es_info_len = (usize::from(streams[3] & 0x0F) << 8) | usize::from(streams[4]);
+
+            let (slot, kind) = match stream_type {
+                Self::STREAM_TYPE_H264 => (&mut self.video, Kind::Video),
+                Self::STREAM_TYPE_AAC => (&mut self.audio, Kind::Audio),
+                _ => {
+                    streams = streams.get(5 + es_info_len..).unwrap_or_default();
+                    continue;
+                }
+            };
+
+            if slot.as_ref().is_none_or(|pes| pes.pid != pid) {
+                debug!("RTMP: found {kind:?} stream on PID {pid}");
+                *slot = Some(Pes::new(pid, kind));
+            }
+
+            streams = streams.get(5 + es_info_len..).unwrap_or_default();
+        }
+    }
+
+    //PSI section after the pointer field, cut to its length
+    fn section(payload: &[u8]) -> Option<&[u8]> {
+        let section = payload.get(1 + usize::from(*payload.first()?)..)?;
+        let len = (usize::from(*section.get(1)? & 0x0F) << 8) | usize::from(*section.get(2)?);
+
+        section.get(..3 + len)
+    }
+}
+
+struct Pes {
+    pid: u16,
+    kind: Kind,
+    header: Option<(u64, u64)>, //PTS and DTS of the packet being collected
+    data: Vec<u8>,
+}
+
+impl Pes {
+    const fn new(pid: u16, kind: Kind) -> Self {
+        Self {
+            pid,
+            kind,
+            header: None,
+            data: Vec::new(),
+        }
+    }
+
+    fn start(&mut self, payload: &[u8]) {
+        self.data.clear();
+        self.header = None;
+
+        //Start code, then PTS or PTS and DTS
+        if payload.len() < 9 || payload[..3] != [0, 0, 1] {
+            return;
+        }
+
+        let flags = payload[7] >> 6;
+        let header_end = 9 + usize::from(payload[8]);
+        let (Some(pts), Some(data)) = (
+            (flags & 0b10 != 0)
+                .then(|| payload.get(9..14).map(timestamp))
+                .flatten(),
+            payload.get(header_end..),
+        ) else {
+            return;
+        };
+        let dts = (flags == 0b11)
+            .then(|| payload.get(14..19).map(timestamp))
+            .flatten()
+            .unwrap_or(pts);
+
+        self.header = Some((pts, dts));
+        self.data.extend_from_slice(data);
+    }
+
+    fn take(&mut self) -> Option<Frame> {
+        let (pts, dts) = self.header.take()?;
+        if self.data.is_empty() {
+            return None;
+        }
+
+        Some(Frame {
+            kind: self.kind,
+            pts,
+            dts,
+            data: mem::take(&mut self.data),
+        })
+    }
+}
+
+//33 bits spread over 5 bytes with marker bits
+fn timestamp(bytes: &[u8]) -> u64 {
+    (u64::from(bytes[0] >> 1 & 0x07) << 30)
+        | (u64::from(bytes[1]) << 22)
+        | (u64::from(bytes[2] >> 1) << 15)
+        | (u64::from(bytes[3]) << 7)
+        | u64::from(bytes[4] >> 1)
+}
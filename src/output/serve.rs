@@ -0,0 +1,300 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    thread::Builder as ThreadBuilder,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use log::{debug, error, info};
+
+use crate::args::{Parse, Parser};
+
+//Filled by the HLS worker, read by the HTTP client threads
+pub static SERVE: Segments = Segments::new();
+
+#[derive(Default, Debug)]
+pub struct Args {
+    addr: Option<SocketAddr>,
+}
+
+impl Parse for Args {
+    fn parse(&mut self, parser: &mut Parser) -> Result<()> {
+        parser.parse_fn(&mut self.addr, "--serve", |arg| {
+            Ok(Some(
+                arg.to_socket_addrs()?
+                    .next()
+                    .context("Invalid socket address")?,
+            ))
+        })?;
+
+        Ok(())
+    }
+}
+
+impl Args {
+    pub const fn enabled(&self) -> bool {
+        self.addr.is_some()
+    }
+}
+
+struct Segment {
+    sequence: u64,
+    duration: Duration,
+    data: Arc<[u8]>,
+}
+
+struct Inner {
+    enabled: bool,
+    next_sequence: u64,
+    header: Option<Arc<[u8]>>,
+    segments: VecDeque<Segment>,
+}
+
+//Most recent segments, numbered in the order they were downloaded
+pub struct Segments {
+    inner: Mutex<Inner>,
+}
+
+impl Segments {
+    //Segments in the playlist, a few more are kept for clients that are behind
+    const PLAYLIST_SIZE: usize = 6;
+    const MAX_SEGMENTS: usize = Self::PLAYLIST_SIZE * 2;
+
+    //Prefetch segments don't have a duration yet
+    const DEFAULT_DURATION: Duration = Duration::from_secs(2);
+
+    const fn new() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                enabled: false,
+                next_sequence: 0,
+                header: None,
+                segments: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.lock().enabled
+    }
+
+    pub fn set_header(&self, header: &[u8]) {
+        let mut inner = self.lock();
+        if inner.enabled {
+            inner.header = Some(header.into());
+        }
+    }
+
+    pub fn push(&self, duration: Option<Duration>, data: Arc<[u8]>) {
+        let mut inner = self.lock();
+        if !inner.enabled || data.is_empty() {
+            return;
+        }
+
+        let sequence = inner.next_sequence;
+        inner.next_sequence += 1;
+        inner.segments.push_back(Segment {
+            sequence,
+            duration: duration.unwrap_or(Self::DEFAULT_DURATION),
+            data,
+        });
+
+        if inner.segments.len() > Self::MAX_SEGMENTS {
+            inner.segments.pop_front();
+        }
+        drop(inner);
+    }
+
+    fn playlist(&self) -> Option<String> {
+        let inner = self.lock();
+        let start = inner.segments.len().saturating_sub(Self::PLAYLIST_SIZE);
+        let first = inner.segments.get(start)?;
+
+        let target = inner
+            .segments
+            .range(start..)
+            .map(|s| s.duration.as_secs_f32().ceil())
+            .fold(0.0, f32::max);
+
+        let mut playlist = format!(
+            "#EXTM3U\n#EXT-X-VERSION:{}\n#EXT-X-TARGETDURATION:{target}\n#EXT-X-MEDIA-SEQUENCE:{}\n",
+            if inner.header.is_some() { 6 } else { 3 },
+            first.sequence,
+        );
+
+        if inner.header.is_some() {
+            playlist.push_str("#EXT-X-MAP:URI=\"init.mp4\"\n");
+        }
+
+        let extension = inner.extension();
+        for segment in inner.segments.range(start..) {
+            let _ = write!(
+                playlist,
+                "#EXTINF:{:.3},\n{}.{extension}\n",
+                segment.duration.as_secs_f32(),
+                segment.sequence,
+            );
+        }
+        drop(inner);
+
+        Some(playlist)
+    }
+
+    //Content type and data of 123.ts or 123.m4s
+    fn segment(&self, name: &str) -> Option<(&'static str, Arc<[u8]>)> {
+        let inner = self.lock();
+        let sequence = name
+            .strip_suffix(inner.extension())?
+            .strip_suffix('.')?
+            .parse::<u64>()
+            .ok()?;
+
+        inner
+            .segments
+            .iter()
+            .find(|s| s.sequence == sequence)
+            .map(|s| (inner.content_type(), Arc::clone(&s.data)))
+    }
+
+    fn header(&self) -> Option<Arc<[u8]>> {
+        self.lock().header.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Inner {
+    //Streams with a header are fMP4
+    const fn extension(&self) -> &'static str {
+        if self.header.is_some() { "m4s" } else { "ts" }
+    }
+
+    const fn content_type(&self) -> &'static str {
+        if self.header.is_some() {
+            "video/iso.segment"
+        } else {
+            "video/mp2t"
+        }
+    }
+}
+
+pub fn spawn(args: &Args) -> Result<()> {
+    let Some(addr) = &args.addr else {
+        return Ok(());
+    };
+
+    let listener = TcpListener::bind(addr).context("Failed to bind HTTP server address/port")?;
+    SERVE.lock().enabled = true;
+    info!("Serving playlist on: http://{addr}/playlist.m3u8");
+
+    ThreadBuilder::new()
+        .name("serve".to_owned())
+        .spawn(move || {
+            for incoming in listener.incoming() {
+                let sock = match incoming {
+                    Ok(sock) => sock,
+                    Err(e) => {
+                        error!("Failed to accept HTTP client: {e}");
+                        continue;
+                    }
+                };
+
+                //Players fetch the playlist and segments at the same time
+                let spawned =
+                    ThreadBuilder::new()
+                        .name("serve client".to_owned())
+                        .spawn(move || {
+                            if let Err(e) = handle_client(sock) {
+                                debug!("HTTP client error: {e}");
+                            }
+                        });
+
+                if let Err(e) = spawned {
+                    error!("Failed to spawn HTTP client thread: {e}");
+                }
+            }
+        })
+        .context("Failed to spawn HTTP server thread")?;
+
+    Ok(())
+}
+
+fn handle_client(sock: TcpStream) -> io::Result<()> {
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    sock.set_read_timeout(Some(TIMEOUT))?;
+    sock.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut writer = sock.try_clone()?;
+    let mut reader = BufReader::new(sock);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        //Request headers aren't needed, connections are kept alive until the client closes them
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let mut parts = line.split_whitespace();
+        let (method, path) = (
+            parts.next().unwrap_or_default(),
+            parts.next().unwrap_or("/"),
+        );
+        let path = path.split_once('?').map_or(path, |(p, _)| p);
+        debug!("HTTP request: {method} {path}");
+
+        let (status, content_type, body) = route(method, path);
+        write!(
+            writer,
+            "HTTP/1.1 {status}\r\n\
+             Content-Type: {content_type}\r\n\
+             Content-Length: {}\r\n\
+             Cache-Control: no-cache\r\n\
+             Access-Control-Allow-Origin: *\r\n\
+             \r\n",
+            body.len(),
+        )?;
+
+        if method != "HEAD" {
+            writer.write_all(&body)?;
+        }
+        writer.flush()?;
+    }
+}
+
+fn route(method: &str, path: &str) -> (&'static str, &'static str, Arc<[u8]>) {
+    if !matches!(method, "GET" | "HEAD") {
+        return ("405 Method Not Allowed", "text/plain", Arc::default());
+    }
+
+    let response = match path.trim_start_matches('/') {
+        "" | "playlist.m3u8" => {
+            let Some(playlist) = SERVE.playlist() else {
+                return ("503 Service Unavailable", "text/plain", Arc::default());
+            };
+
+            Some((
+                "application/vnd.apple.mpegurl",
+                playlist.into_bytes().into(),
+            ))
+        }
+        "init.mp4" => SERVE.header().map(|header| ("video/mp4", header)),
+        name => SERVE.segment(name),
+    };
+
+    response.map_or_else(
+        || ("404 Not Found", "text/plain", Arc::default()),
+        |(content_type, body)| ("200 OK", content_type, body),
+    )
+}
@@ -0,0 +1,87 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    io::{self, ErrorKind, Write},
+};
+
+use anyhow::Result;
+use log::info;
+
+use super::Output;
+use crate::args::{Parse, Parser};
+
+#[derive(Debug)]
+pub struct StdoutClosedError;
+
+impl std::error::Error for StdoutClosedError {}
+
+impl Display for StdoutClosedError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("Stdout closed")
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct Args {
+    enabled: bool,
+}
+
+impl Parse for Args {
+    fn parse(&mut self, parser: &mut Parser) -> Result<()> {
+        parser.parse_switch(&mut self.enabled, "--stdout")?;
+
+        Ok(())
+    }
+}
+
+impl Args {
+    pub const fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    //-p -
+    pub const fn enable(&mut self) {
+        self.enabled = true;
+    }
+}
+
+//Logging goes to stderr while this is used, see Logger::init
+pub struct Stdout;
+
+impl Output for Stdout {
+    fn set_header(&mut self, header: &[u8]) -> io::Result<()> {
+        self.write_all(header)
+    }
+}
+
+impl Write for Stdout {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        unreachable!();
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().lock().flush().map_err(map_broken_pipe)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        io::stdout().lock().write_all(buf).map_err(map_broken_pipe)
+    }
+}
+
+impl Stdout {
+    pub fn new(args: &Args) -> Option<Self> {
+        if !args.enabled {
+            return None;
+        }
+
+        info!("Writing to stdout");
+        Some(Self)
+    }
+}
+
+fn map_broken_pipe(error: io::Error) -> io::Error {
+    if error.kind() == ErrorKind::BrokenPipe {
+        return io::Error::other(StdoutClosedError);
+    }
+
+    error
+}
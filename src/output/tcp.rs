@@ -10,7 +10,7 @@ use std::{
     time::Duration,
 };
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use log::{error, info};
 
 use super::Output;
@@ -33,13 +33,8 @@ impl Default for Args {
 
 impl Parse for Args {
     fn parse(&mut self, parser: &mut Parser) -> Result<()> {
-        parser.parse_fn_cfg(&mut self.addr, "-t", "tcp-server", |arg| {
-            Ok(Some(
-                arg.to_socket_addrs()?
-                    .next()
-                    .context("Invalid socket address")?,
-            ))
-        })?;
+        parser.parse_fn_cfg(&mut self.addr, "-t", "tcp-server", parse_addr)?;
+        parser.parse_fn(&mut self.addr, "--listen", parse_listen)?;
         parser.parse_duration(&mut self.client_timeout, "--tcp-client-timeout")?;
 
         Ok(())
@@ -155,6 +150,23 @@ impl Tcp {
     }
 }
 
+fn parse_addr(arg: &str) -> Result<Option<SocketAddr>> {
+    Ok(Some(
+        arg.to_socket_addrs()?
+            .next()
+            .context("Invalid socket address")?,
+    ))
+}
+
+//tcp://HOST:PORT, the scheme is optional
+fn parse_listen(arg: &str) -> Result<Option<SocketAddr>> {
+    match arg.split_once("://") {
+        Some(("tcp", addr)) => parse_addr(addr),
+        Some((scheme, _)) => bail!("Unsupported --listen scheme: {scheme}"),
+        None => parse_addr(arg),
+    }
+}
+
 #[derive(Default)]
 enum State {
     #[default]
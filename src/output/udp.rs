@@ -0,0 +1,202 @@
+use std::{
+    io::{self, ErrorKind, Write},
+    mem,
+    net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::Instant,
+};
+
+use anyhow::{Context, Result, bail};
+use getrandom::getrandom;
+use log::info;
+
+use super::Output;
+use crate::args::{Parse, Parser};
+
+#[derive(Default, Debug)]
+pub struct Args {
+    target: Option<Target>,
+    ttl: Option<u32>,
+}
+
+impl Parse for Args {
+    fn parse(&mut self, parser: &mut Parser) -> Result<()> {
+        parser.parse_fn(&mut self.target, "--udp", Target::new)?;
+        parser.parse_opt(&mut self.ttl, "--udp-ttl")?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Target {
+    addr: SocketAddr,
+    rtp: bool,
+}
+
+impl Target {
+    //udp://HOST:PORT or rtp://HOST:PORT
+    fn new(arg: &str) -> Result<Option<Self>> {
+        let (rtp, addr) = match arg.split_once("://") {
+            Some(("udp", addr)) => (false, addr),
+            Some(("rtp", addr)) => (true, addr),
+            _ => bail!("Invalid UDP output, expected udp://HOST:PORT or rtp://HOST:PORT"),
+        };
+
+        Ok(Some(Self {
+            addr: addr
+                .to_socket_addrs()?
+                .next()
+                .context("Invalid socket address")?,
+            rtp,
+        }))
+    }
+}
+
+//Sends the TS stream as datagrams of 7 packets, the usual size for IPTV
+pub struct Udp {
+    sock: UdpSocket,
+    addr: SocketAddr,
+    rtp: Option<Rtp>,
+    pending: Vec<u8>, //partial datagram carried over to the next write
+}
+
+impl Output for Udp {
+    fn set_header(&mut self, header: &[u8]) -> io::Result<()> {
+        self.write_all(header)
+    }
+}
+
+impl Write for Udp {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        unreachable!();
+    }
+
+    //Whole TS packets left at the end of a segment are sent in a shorter datagram
+    fn flush(&mut self) -> io::Result<()> {
+        let len = self.pending.len() - self.pending.len() % Self::TS_PACKET_SIZE;
+        if len == 0 {
+            return Ok(());
+        }
+
+        let mut pending = mem::take(&mut self.pending);
+        let result = self.send(&pending[..len]);
+
+        pending.drain(..len);
+        self.pending = pending;
+
+        result
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let mut pending = mem::take(&mut self.pending);
+        pending.extend_from_slice(buf);
+
+        let mut chunks = pending.chunks_exact(Self::PAYLOAD_SIZE);
+        let result = chunks.by_ref().try_for_each(|chunk| self.send(chunk));
+        let sent = pending.len() - chunks.remainder().len();
+
+        pending.drain(..sent);
+        self.pending = pending;
+
+        result
+    }
+}
+
+impl Udp {
+    const TS_PACKET_SIZE: usize = 188;
+    const PAYLOAD_SIZE: usize = Self::TS_PACKET_SIZE * 7;
+
+    pub fn new(args: &Args) -> Result<Option<Self>> {
+        let Some(target) = &args.target else {
+            return Ok(None);
+        };
+
+        let bind_addr: SocketAddr = if target.addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0; 16], 0).into()
+        };
+        let sock = UdpSocket::bind(bind_addr).context("Failed to bind UDP socket")?;
+
+        match target.addr.ip() {
+            IpAddr::V4(ip) if ip.is_multicast() => {
+                sock.set_multicast_ttl_v4(args.ttl.unwrap_or(1))?;
+            }
+            _ => {
+                if let Some(ttl) = args.ttl {
+                    sock.set_ttl(ttl)?;
+                }
+            }
+        }
+
+        let rtp = target.rtp.then(Rtp::new).transpose()?;
+        info!(
+            "Sending {} to: {}",
+            if rtp.is_some() { "RTP" } else { "UDP" },
+            target.addr
+        );
+
+        Ok(Some(Self {
+            sock,
+            addr: target.addr,
+            rtp,
+            pending: Vec::with_capacity(Self::PAYLOAD_SIZE * 2),
+        }))
+    }
+
+    fn send(&mut self, payload: &[u8]) -> io::Result<()> {
+        let result = match &mut self.rtp {
+            Some(rtp) => self.sock.send_to(rtp.packet(payload), self.addr),
+            None => self.sock.send_to(payload, self.addr),
+        };
+
+        match result {
+            //Unicast receiver not running (yet), keep sending
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => Ok(()),
+            Err(e) => Err(e),
+            Ok(_) => Ok(()),
+        }
+    }
+}
+
+//RFC 3550 header with the static MP2T payload type from RFC 3551
+struct Rtp {
+    sequence: u16,
+    ssrc: u32,
+    start: Instant,
+    buf: Vec<u8>,
+}
+
+impl Rtp {
+    const HEADER_SIZE: usize = 12;
+    const PAYLOAD_TYPE_MP2T: u8 = 33;
+    const CLOCK_RATE: u128 = 90_000;
+
+    fn new() -> Result<Self> {
+        let mut buf = [0u8; 6];
+        getrandom(&mut buf)?;
+
+        Ok(Self {
+            sequence: u16::from_be_bytes([buf[0], buf[1]]),
+            ssrc: u32::from_be_bytes([buf[2], buf[3], buf[4], buf[5]]),
+            start: Instant::now(),
+            buf: Vec::with_capacity(Self::HEADER_SIZE + Udp::PAYLOAD_SIZE),
+        })
+    }
+
+    fn packet(&mut self, payload: &[u8]) -> &[u8] {
+        #[allow(clippy::cast_possible_truncation)] //wraps around like it's supposed to
+        let timestamp = (self.start.elapsed().as_micros() * Self::CLOCK_RATE / 1_000_000) as u32;
+
+        self.buf.clear();
+        self.buf.push(0x80); //version 2, no padding, extension or CSRCs
+        self.buf.push(Self::PAYLOAD_TYPE_MP2T);
+        self.buf.extend_from_slice(&self.sequence.to_be_bytes());
+        self.buf.extend_from_slice(&timestamp.to_be_bytes());
+        self.buf.extend_from_slice(&self.ssrc.to_be_bytes());
+        self.buf.extend_from_slice(payload);
+
+        self.sequence = self.sequence.wrapping_add(1);
+        &self.buf
+    }
+}
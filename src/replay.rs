@@ -0,0 +1,111 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+//Filled by the HLS worker, saved from the control thread
+pub static REPLAY: Replay = Replay::new();
+
+struct Segment {
+    duration: Duration,
+    data: Arc<[u8]>,
+}
+
+struct Inner {
+    length: Duration, //zero when disabled
+    buffered: Duration,
+    header: Option<Arc<[u8]>>,
+    segments: VecDeque<Segment>,
+}
+
+//Last --replay-buffer seconds of segments, kept in memory until saved
+pub struct Replay {
+    inner: Mutex<Inner>,
+}
+
+impl Replay {
+    //Prefetch segments don't have a duration yet
+    const DEFAULT_DURATION: Duration = Duration::from_secs(2);
+
+    const fn new() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                length: Duration::ZERO,
+                buffered: Duration::ZERO,
+                header: None,
+                segments: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn enable(&self, length: Duration) {
+        self.lock().length = length;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.lock().length.is_zero()
+    }
+
+    pub fn set_header(&self, header: &[u8]) {
+        let mut inner = self.lock();
+        if !inner.length.is_zero() {
+            inner.header = Some(header.into());
+        }
+    }
+
+    pub fn push(&self, duration: Option<Duration>, data: Arc<[u8]>) {
+        let mut inner = self.lock();
+        if inner.length.is_zero() || data.is_empty() {
+            return;
+        }
+
+        let duration = duration.unwrap_or(Self::DEFAULT_DURATION);
+        inner.buffered += duration;
+        inner.segments.push_back(Segment { duration, data });
+
+        while inner.buffered > inner.length
+            && let Some(segment) = inner.segments.pop_front()
+        {
+            inner.buffered -= segment.duration;
+        }
+        drop(inner);
+    }
+
+    //Segments are shared, the worker isn't blocked while the file is written
+    pub fn save(&self, path: &Path) -> io::Result<Duration> {
+        let (header, segments, buffered) = {
+            let inner = self.lock();
+            let segments = inner
+                .segments
+                .iter()
+                .map(|s| Arc::clone(&s.data))
+                .collect::<Vec<_>>();
+
+            (inner.header.clone(), segments, inner.buffered)
+        };
+
+        if segments.is_empty() {
+            return Err(io::Error::other("Replay buffer is empty"));
+        }
+
+        let mut file = File::create_new(path)?;
+        if let Some(header) = header {
+            file.write_all(&header)?;
+        }
+
+        for segment in segments {
+            file.write_all(&segment)?;
+        }
+
+        file.flush()?;
+        Ok(buffered)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
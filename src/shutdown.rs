@@ -0,0 +1,115 @@
+use std::sync::{
+    Arc, Condvar, LazyLock, Mutex, PoisonError,
+    atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::{Context, Result};
+use signal_hook::{consts, flag};
+
+static REQUESTED: LazyLock<Arc<AtomicBool>> = LazyLock::new(Arc::default);
+static STOPPED: (Mutex<bool>, Condvar) = (Mutex::new(false), Condvar::new());
+
+//Set for sessions started through the C ABI, signals are left to the host application
+#[cfg(feature = "ffi")]
+static EMBEDDED: AtomicBool = AtomicBool::new(false);
+
+//On Windows the C runtime's console ctrl handler raises SIGINT for CTRL_C and SIGBREAK for
+//CTRL_BREAK, closing the console window is handled separately in console
+#[cfg(unix)]
+const SIGNALS: &[i32] = &[consts::SIGINT, consts::SIGTERM, consts::SIGHUP];
+#[cfg(not(unix))]
+const SIGNALS: &[i32] = &[consts::SIGINT, consts::SIGTERM, consts::SIGBREAK];
+
+//Marks the graceful stop as finished when dropped, after the outputs are closed
+pub struct Registration;
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        *STOPPED.0.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        STOPPED.1.notify_all();
+    }
+}
+
+//The first signal stops the stream gracefully, a second one exits immediately
+pub fn register() -> Result<Registration> {
+    #[cfg(feature = "ffi")]
+    if EMBEDDED.load(Ordering::Relaxed) {
+        return Ok(Registration);
+    }
+
+    for &signal in SIGNALS {
+        flag::register_conditional_shutdown(signal, 1, Arc::clone(&REQUESTED))
+            .context("Failed to register signal handler")?;
+        flag::register(signal, Arc::clone(&REQUESTED))
+            .context("Failed to register signal handler")?;
+    }
+
+    #[cfg(windows)]
+    console::register().context("Failed to register console handler")?;
+
+    Ok(Registration)
+}
+
+//Embedded sessions can be started again after the previous one stopped
+#[cfg(feature = "ffi")]
+pub fn embed() {
+    EMBEDDED.store(true, Ordering::Relaxed);
+    REQUESTED.store(false, Ordering::Relaxed);
+    *STOPPED.0.lock().unwrap_or_else(PoisonError::into_inner) = false;
+}
+
+pub fn request() {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+#[cfg(windows)]
+fn wait_until_stopped() {
+    let stopped = STOPPED.0.lock().unwrap_or_else(PoisonError::into_inner);
+    drop(
+        STOPPED
+            .1
+            .wait_while(stopped, |stopped| !*stopped)
+            .unwrap_or_else(PoisonError::into_inner),
+    );
+}
+
+//The UCRT doesn't map CTRL_CLOSE_EVENT to a signal, and the process is terminated as soon
+//as a handler returns, so the handler has to wait for the graceful stop itself.
+//Windows still kills the process if it takes longer than a few seconds.
+#[cfg(windows)]
+#[allow(unsafe_code)] //FFI
+mod console {
+    use std::io;
+
+    use windows_sys::{
+        Win32::System::Console::{
+            CTRL_CLOSE_EVENT, CTRL_LOGOFF_EVENT, CTRL_SHUTDOWN_EVENT, SetConsoleCtrlHandler,
+        },
+        core::BOOL,
+    };
+
+    pub fn register() -> io::Result<()> {
+        //SAFETY: handler is a plain function that lives as long as the process
+        if unsafe { SetConsoleCtrlHandler(Some(handler), 1) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    //Called on its own thread by the system
+    unsafe extern "system" fn handler(ctrl_type: u32) -> BOOL {
+        match ctrl_type {
+            CTRL_CLOSE_EVENT | CTRL_LOGOFF_EVENT | CTRL_SHUTDOWN_EVENT => {
+                super::request();
+                super::wait_until_stopped();
+                1
+            }
+            _ => 0, //passed on to the C runtime's handler
+        }
+    }
+}
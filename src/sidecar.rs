@@ -0,0 +1,208 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    thread::{self, Builder as ThreadBuilder},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use log::{debug, error, info};
+
+use crate::{
+    hls::{Args as HlsArgs, Metadata},
+    http::Agent,
+    stats::{RECORD_STATS, STATS, Stats, format_date_time},
+};
+
+//Title or game change, offset from the start of the recording
+struct Chapter {
+    offset: Duration,
+    title: Option<String>,
+    game: Option<String>,
+}
+
+//JSON and FFMETADATA written next to the recording with --record-metadata, for archiving
+pub struct Sidecar {
+    path: PathBuf,
+    channel: String,
+    quality: Option<String>,
+    stats: &'static Stats,
+    start: SystemTime,
+    chapters: Arc<Mutex<Vec<Chapter>>>,
+}
+
+impl Sidecar {
+    const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+    pub fn new(recording: &Path, hls_args: &HlsArgs, agent: &Agent) -> Result<Self> {
+        let sidecar = Self {
+            path: recording.to_path_buf(),
+            channel: hls_args.channel().to_owned(),
+            quality: hls_args
+                .record_quality()
+                .or_else(|| hls_args.quality())
+                .map(ToOwned::to_owned),
+            stats: if hls_args.record_quality().is_some() {
+                &RECORD_STATS
+            } else {
+                &STATS
+            },
+            start: SystemTime::now(),
+            chapters: Arc::default(),
+        };
+
+        let Some(metadata) = Metadata::new(hls_args) else {
+            return Ok(sidecar);
+        };
+
+        let start = sidecar.start;
+        let chapters = Arc::clone(&sidecar.chapters);
+        let agent = agent.clone();
+        ThreadBuilder::new()
+            .name("chapters".to_owned())
+            .spawn(move || {
+                loop {
+                    match metadata.fetch_info(&agent) {
+                        Ok((title, game)) => {
+                            let mut chapters =
+                                chapters.lock().unwrap_or_else(PoisonError::into_inner);
+                            if chapters
+                                .last()
+                                .is_none_or(|c| c.title != title || c.game != game)
+                            {
+                                debug!("New chapter: {title:?} ({game:?})");
+                                chapters.push(Chapter {
+                                    offset: start.elapsed().unwrap_or_default(),
+                                    title,
+                                    game,
+                                });
+                            }
+                        }
+                        Err(e) => error!("Failed to fetch stream info for recording metadata: {e}"),
+                    }
+
+                    thread::sleep(Self::POLL_INTERVAL);
+                }
+            })
+            .context("Failed to spawn chapters thread")?;
+
+        Ok(sidecar)
+    }
+
+    pub fn write(&self) -> Result<()> {
+        let end = self.start.elapsed().unwrap_or_default();
+        let chapters = self.chapters.lock().unwrap_or_else(PoisonError::into_inner);
+
+        //Recording starts with the first chapter's title and game
+        let first = chapters.first();
+        let mut json = format!(
+            r#"{{"channel":{},"title":{},"game":{},"quality":{},"start":"{}","end":"{}","#,
+            string(Some(&self.channel)),
+            string(first.and_then(|c| c.title.as_deref())),
+            string(first.and_then(|c| c.game.as_deref())),
+            string(self.quality.as_deref()),
+            format_date_time(self.start),
+            format_date_time(SystemTime::now()),
+        );
+
+        self.stats.write_json(&mut json, self.start);
+
+        json.push_str(r#","chapters":["#);
+        for (i, chapter) in chapters.iter().enumerate() {
+            let _ = write!(
+                json,
+                r#"{}{{"offset":{},"title":{},"game":{}}}"#,
+                if i == 0 { "" } else { "," },
+                chapter.offset.as_secs(),
+                string(chapter.title.as_deref()),
+                string(chapter.game.as_deref()),
+            );
+        }
+        json.push_str("]}\n");
+
+        let ffmetadata = ffmetadata(&chapters, end);
+        drop(chapters);
+
+        let path = self.path.with_extension("json");
+        fs::write(&path, json).with_context(|| format!("Failed to write {}", path.display()))?;
+        info!("Recording metadata written to: {}", path.display());
+
+        //ffmpeg -i recording.ts -i recording.ffmetadata -map_metadata 1 -codec copy recording.mkv
+        if let Some(ffmetadata) = ffmetadata {
+            let path = self.path.with_extension("ffmetadata");
+            fs::write(&path, ffmetadata)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn ffmetadata(chapters: &[Chapter], end: Duration) -> Option<String> {
+    if chapters.is_empty() {
+        return None;
+    }
+
+    let mut ffmetadata = String::from(";FFMETADATA1\n");
+    for (i, chapter) in chapters.iter().enumerate() {
+        let chapter_end = chapters.get(i + 1).map_or(end, |next| next.offset);
+        let title = match (&chapter.title, &chapter.game) {
+            (Some(title), Some(game)) => format!("{title} ({game})"),
+            (Some(title), None) => title.clone(),
+            (None, Some(game)) => game.clone(),
+            (None, None) => format!("Chapter {}", i + 1),
+        };
+
+        let _ = write!(
+            ffmetadata,
+            "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            if i == 0 {
+                0
+            } else {
+                chapter.offset.as_millis()
+            },
+            chapter_end.as_millis(),
+            escape_ffmetadata(&title),
+        );
+    }
+
+    Some(ffmetadata)
+}
+
+//'=', ';', '#', '\\' and newlines are escaped with a backslash
+fn escape_ffmetadata(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+//Quoted and escaped, or null
+fn string(value: Option<&str>) -> String {
+    let Some(value) = value else {
+        return "null".to_owned();
+    };
+
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str(r#"\""#),
+            '\\' => quoted.push_str(r"\\"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", u32::from(c));
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
@@ -0,0 +1,444 @@
+use std::{
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    sync::{
+        Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{Context, Result, bail};
+use log::{error, info};
+
+use crate::logger;
+
+//Shared between the main thread and the HLS worker
+pub static STATS: Stats = Stats::new();
+
+//Used by --audio-record so it doesn't skew the main session's numbers
+pub static AUDIO_STATS: Stats = Stats::new();
+
+//Used by --record-quality, the recording downloads its own segments
+pub static RECORD_STATS: Stats = Stats::new();
+
+pub struct AdBreak {
+    pub start: SystemTime,
+    started: Instant,
+    duration: Option<Duration>,
+}
+
+impl AdBreak {
+    pub fn duration(&self) -> Duration {
+        self.duration.unwrap_or_else(|| self.started.elapsed())
+    }
+}
+
+pub struct SegmentStats {
+    pub sequence: usize,
+    pub duration: Option<Duration>,
+    pub bytes: u64,
+    pub elapsed: Duration,
+    pub write_time: Duration,
+}
+
+#[derive(Default)]
+pub struct ConnectTiming {
+    pub dns: Duration,
+    pub connect: Duration,
+    pub tls: Option<Duration>,
+}
+
+#[derive(Default, Copy, Clone, Debug)]
+pub enum Format {
+    #[default]
+    Csv,
+    Jsonl,
+}
+
+impl Format {
+    pub fn new(arg: &str) -> Result<Self> {
+        match arg {
+            "csv" => Ok(Self::Csv),
+            "jsonl" => Ok(Self::Jsonl),
+            _ => bail!("Invalid stats format"),
+        }
+    }
+}
+
+pub struct Stats {
+    segments: AtomicU64,
+    bytes: AtomicU64,
+    queued: AtomicU64,
+    slow: AtomicU64,
+    skipped: AtomicU64,
+    dropped: AtomicU64,
+    connections: AtomicU64,
+    dns_ms: AtomicU64,
+    connect_ms: AtomicU64,
+    tls_ms: AtomicU64,
+    ad_breaks: Mutex<Vec<AdBreak>>,
+    file: Mutex<Option<(File, Format)>>,
+}
+
+impl Stats {
+    const fn new() -> Self {
+        Self {
+            segments: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            queued: AtomicU64::new(0),
+            slow: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            connections: AtomicU64::new(0),
+            dns_ms: AtomicU64::new(0),
+            connect_ms: AtomicU64::new(0),
+            tls_ms: AtomicU64::new(0),
+            ad_breaks: Mutex::new(Vec::new()),
+            file: Mutex::new(None),
+        }
+    }
+
+    pub fn open_file(&self, path: &str, format: Format) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context("Failed to open stats file")?;
+
+        if matches!(format, Format::Csv) && file.metadata()?.len() == 0 {
+            file.write_all(b"timestamp,sequence,duration,bytes,fetch_ms,write_ms\n")?;
+        }
+
+        *self.file.lock().unwrap_or_else(PoisonError::into_inner) = Some((file, format));
+
+        Ok(())
+    }
+
+    pub fn segment_queued(&self) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn segment_done(&self, segment: &SegmentStats) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        self.segments.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(segment.bytes, Ordering::Relaxed);
+
+        let mut stats_file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((file, format)) = stats_file.as_mut()
+            && let Err(e) = file.write_all(Self::format_row(segment, *format).as_bytes())
+        {
+            error!("Failed to write stats file: {e}");
+            *stats_file = None;
+        }
+    }
+
+    pub fn segment_slow(&self) -> u64 {
+        self.slow.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    //Segments never queued because of a jump to the newest segment
+    pub fn segments_skipped(&self, count: usize) {
+        self.skipped.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    //Queued segments that were discarded before being downloaded
+    pub fn segments_dropped(&self, count: usize) {
+        self.queued.fetch_sub(count as u64, Ordering::Relaxed);
+        self.dropped.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn connected(&self, timing: &ConnectTiming) {
+        let millis = |d: Duration| u64::try_from(d.as_millis()).unwrap_or(u64::MAX);
+
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.dns_ms.fetch_add(millis(timing.dns), Ordering::Relaxed);
+        self.connect_ms
+            .fetch_add(millis(timing.connect), Ordering::Relaxed);
+        self.tls_ms
+            .fetch_add(timing.tls.map_or(0, millis), Ordering::Relaxed);
+    }
+
+    pub fn ad_break_started(&self) {
+        self.ad_breaks().push(AdBreak {
+            start: SystemTime::now(),
+            started: Instant::now(),
+            duration: Option::default(),
+        });
+    }
+
+    pub fn ad_break_ended(&self) -> Option<Duration> {
+        let mut ad_breaks = self.ad_breaks();
+        let ad_break = ad_breaks.last_mut()?;
+
+        let duration = ad_break.started.elapsed();
+        ad_break.duration = Some(duration);
+        drop(ad_breaks);
+
+        Some(duration)
+    }
+
+    pub fn write_status(&self, mut writer: impl Write) -> io::Result<()> {
+        let ad_breaks = self.ad_breaks();
+
+        let mut status = format!(
+            "segments={}\n\
+             bytes={}\n\
+             queued={}\n\
+             slow={}\n\
+             skipped={}\n\
+             dropped={}\n\
+             connections={}\n\
+             dns_ms={}\n\
+             connect_ms={}\n\
+             tls_ms={}\n\
+             ad_breaks={}\n\
+             ad_time={}\n",
+            self.segments.load(Ordering::Relaxed),
+            self.bytes(),
+            self.queued(),
+            self.slow.load(Ordering::Relaxed),
+            self.skipped.load(Ordering::Relaxed),
+            self.dropped.load(Ordering::Relaxed),
+            self.connections.load(Ordering::Relaxed),
+            self.dns_ms.load(Ordering::Relaxed),
+            self.connect_ms.load(Ordering::Relaxed),
+            self.tls_ms.load(Ordering::Relaxed),
+            ad_breaks.len(),
+            ad_breaks
+                .iter()
+                .map(AdBreak::duration)
+                .sum::<Duration>()
+                .as_secs(),
+        );
+
+        for ad_break in ad_breaks.iter() {
+            let _ = writeln!(
+                status,
+                "ad_break={},{}",
+                ad_break
+                    .start
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+                ad_break.duration().as_secs(),
+            );
+        }
+        drop(ad_breaks);
+
+        writer.write_all(status.as_bytes())
+    }
+
+    pub fn print_summary(&self) {
+        #[allow(clippy::cast_precision_loss)]
+        let megabytes = self.bytes() as f64 / 1_000_000.0;
+        let ad_breaks = self.ad_breaks();
+
+        info!(
+            "Session summary: {segments} segments ({megabytes:.1} MB), \
+             {skipped} skipped, {dropped} dropped, \
+             {count} ad breaks filtered ({total}s total)",
+            segments = self.segments.load(Ordering::Relaxed),
+            skipped = self.skipped.load(Ordering::Relaxed),
+            dropped = self.dropped.load(Ordering::Relaxed),
+            count = ad_breaks.len(),
+            total = ad_breaks
+                .iter()
+                .map(AdBreak::duration)
+                .sum::<Duration>()
+                .as_secs(),
+        );
+
+        for ad_break in ad_breaks.iter() {
+            info!(
+                "    Ad break at {} for {}s",
+                format_date_time(ad_break.start),
+                ad_break.duration().as_secs(),
+            );
+        }
+    }
+
+    //"segments":1,...,"ad_breaks":[...], ad break offsets are relative to start
+    pub fn write_json(&self, json: &mut String, start: SystemTime) {
+        let ad_breaks = self.ad_breaks();
+
+        let _ = write!(
+            json,
+            r#""segments":{},"bytes":{},"skipped":{},"dropped":{},"ad_breaks":["#,
+            self.segments.load(Ordering::Relaxed),
+            self.bytes(),
+            self.skipped.load(Ordering::Relaxed),
+            self.dropped.load(Ordering::Relaxed),
+        );
+
+        for (i, ad_break) in ad_breaks.iter().enumerate() {
+            let _ = write!(
+                json,
+                r#"{}{{"start":"{}","offset":{},"duration":{}}}"#,
+                if i == 0 { "" } else { "," },
+                format_date_time(ad_break.start),
+                ad_break
+                    .start
+                    .duration_since(start)
+                    .unwrap_or_default()
+                    .as_secs(),
+                ad_break.duration().as_secs(),
+            );
+        }
+        drop(ad_breaks);
+
+        json.push(']');
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn queued(&self) -> u64 {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "ffi")]
+    pub fn segments(&self) -> u64 {
+        self.segments.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "ffi")]
+    pub fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "ffi")]
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    //Ad breaks are only ever pushed or finished, a panic can't leave them half updated
+    fn ad_breaks(&self) -> MutexGuard<'_, Vec<AdBreak>> {
+        self.ad_breaks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn format_row(segment: &SegmentStats, format: Format) -> String {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let fetch_ms = segment
+            .elapsed
+            .saturating_sub(segment.write_time)
+            .as_millis();
+        let write_ms = segment.write_time.as_millis();
+
+        let mut row = String::new();
+        let _ = match format {
+            Format::Csv => writeln!(
+                row,
+                "{timestamp},{sequence},{duration},{bytes},{fetch_ms},{write_ms}",
+                sequence = segment.sequence,
+                duration = segment
+                    .duration
+                    .map(|d| format!("{:.3}", d.as_secs_f64()))
+                    .unwrap_or_default(),
+                bytes = segment.bytes,
+            ),
+            Format::Jsonl => writeln!(
+                row,
+                r#"{{"timestamp":{timestamp},"sequence":{sequence},"duration":{duration},"bytes":{bytes},"fetch_ms":{fetch_ms},"write_ms":{write_ms}}}"#,
+                sequence = segment.sequence,
+                duration = segment
+                    .duration
+                    .map_or_else(|| "null".to_owned(), |d| format!("{:.3}", d.as_secs_f64())),
+                bytes = segment.bytes,
+            ),
+        };
+
+        row
+    }
+}
+
+pub struct StatusLine {
+    quality: String,
+    bitrate: Option<f64>,
+
+    window_bytes: u64,
+    window_start: Instant,
+}
+
+impl StatusLine {
+    //Segments arrive in bursts, average over a few of them
+    const WINDOW: Duration = Duration::from_secs(6);
+
+    pub fn new(quality: Option<&str>) -> Self {
+        Self {
+            quality: quality.unwrap_or("<unknown>").to_owned(),
+            bitrate: Option::default(),
+            window_bytes: STATS.bytes(),
+            window_start: Instant::now(),
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    pub fn update(&mut self, latency: Option<Duration>) {
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= Self::WINDOW {
+            let bytes = STATS.bytes();
+
+            self.bitrate = Some(
+                (bytes - self.window_bytes) as f64 * 8.0 / elapsed.as_secs_f64() / 1_000_000.0,
+            );
+            self.window_bytes = bytes;
+            self.window_start = Instant::now();
+        }
+
+        let queued = STATS.queued();
+        logger::set_status(&format!(
+            "{quality} | {bitrate} | latency: {latency} | buffer: {buffer}",
+            quality = self.quality,
+            bitrate = self
+                .bitrate
+                .map_or_else(|| "-- Mbps".to_owned(), |b| format!("{b:.1} Mbps")),
+            latency =
+                latency.map_or_else(|| "--".to_owned(), |l| format!("{:.1}s", l.as_secs_f64())),
+            buffer = match queued {
+                0 => "empty".to_owned(),
+                1 => "1 segment".to_owned(),
+                n => format!("{n} segments"),
+            },
+        ));
+    }
+}
+
+//Formats as UTC, ie. 2024-01-01 00:00:00 UTC
+pub fn format_date_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+
+    //Civil date from days since the unix epoch (proleptic gregorian)
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+    )
+}